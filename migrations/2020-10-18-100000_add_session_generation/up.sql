@@ -0,0 +1 @@
+ALTER TABLE sessions ADD COLUMN generation INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,25 @@
+CREATE TABLE oauth_clients (
+  id VARCHAR(64) PRIMARY KEY,
+  name VARCHAR(255) NOT NULL,
+  client_secret VARCHAR(255),
+  redirect_uris TEXT[] NOT NULL DEFAULT '{}',
+  status INTEGER NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE TRIGGER set_update_timestamp
+BEFORE UPDATE ON oauth_clients
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_update_timestamp();
+
+CREATE TABLE oauth_authorization_codes (
+  code_hash VARCHAR(64) PRIMARY KEY,
+  client_id VARCHAR(64) NOT NULL REFERENCES oauth_clients (id) ON DELETE CASCADE,
+  user_id BIGINT NOT NULL,
+  redirect_uri TEXT NOT NULL,
+  code_challenge VARCHAR(128) NOT NULL,
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX oauth_authorization_codes_expires_at_idx ON oauth_authorization_codes (expires_at);
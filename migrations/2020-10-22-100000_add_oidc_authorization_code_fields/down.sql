@@ -0,0 +1,3 @@
+ALTER TABLE oauth_authorization_codes
+  DROP COLUMN nonce,
+  DROP COLUMN scope;
@@ -0,0 +1,13 @@
+CREATE TABLE user_totp (
+  user_id BIGINT PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+  secret BYTEA NOT NULL,
+  confirmed_at TIMESTAMP WITH TIME ZONE,
+  last_used_step BIGINT,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE TRIGGER set_update_timestamp
+BEFORE UPDATE ON user_totp
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_update_timestamp();
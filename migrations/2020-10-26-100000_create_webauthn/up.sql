@@ -0,0 +1,25 @@
+CREATE TABLE webauthn_credentials (
+  id BYTEA PRIMARY KEY,
+  user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  name VARCHAR(255) NOT NULL,
+  public_key BYTEA NOT NULL,
+  sign_count BIGINT NOT NULL,
+  last_used_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX webauthn_credentials_user_id_idx ON webauthn_credentials (user_id);
+
+CREATE TRIGGER set_update_timestamp
+BEFORE UPDATE ON webauthn_credentials
+FOR EACH ROW
+EXECUTE PROCEDURE trigger_set_update_timestamp();
+
+CREATE TABLE webauthn_challenges (
+  challenge VARCHAR(64) PRIMARY KEY,
+  user_id BIGINT REFERENCES users (id) ON DELETE CASCADE,
+  purpose INTEGER NOT NULL,
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX webauthn_challenges_expires_at_idx ON webauthn_challenges (expires_at);
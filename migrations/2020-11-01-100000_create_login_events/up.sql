@@ -0,0 +1,14 @@
+CREATE TABLE login_events (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT REFERENCES users (id) ON DELETE CASCADE,
+  event_type INTEGER NOT NULL,
+  success BOOLEAN NOT NULL,
+  failure_reason VARCHAR,
+  ip VARCHAR,
+  user_agent VARCHAR,
+  platform VARCHAR,
+  sub_platform VARCHAR,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX login_events_user_id_idx ON login_events (user_id, created_at);
@@ -0,0 +1,3 @@
+ALTER TABLE sessions ADD COLUMN ip VARCHAR;
+ALTER TABLE sessions ADD COLUMN user_agent VARCHAR;
+ALTER TABLE sessions ADD COLUMN last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
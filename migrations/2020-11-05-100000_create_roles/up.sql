@@ -0,0 +1,23 @@
+CREATE TABLE roles (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE TABLE role_permissions (
+  role_id INTEGER NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+  permission VARCHAR NOT NULL,
+  PRIMARY KEY (role_id, permission)
+);
+
+CREATE TABLE user_roles (
+  user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  role_id INTEGER NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  PRIMARY KEY (user_id, role_id)
+);
+
+INSERT INTO roles (name) VALUES ('admin');
+INSERT INTO role_permissions (role_id, permission)
+  SELECT id, permission FROM roles, (VALUES ('roles:admin'), ('users:admin')) AS p (permission)
+  WHERE name = 'admin';
@@ -0,0 +1,12 @@
+CREATE TABLE impersonations (
+  id UUID PRIMARY KEY,
+  admin_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  ip VARCHAR,
+  user_agent VARCHAR,
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX impersonations_user_id_idx ON impersonations (user_id, created_at);
+CREATE INDEX impersonations_admin_id_idx ON impersonations (admin_id, created_at);
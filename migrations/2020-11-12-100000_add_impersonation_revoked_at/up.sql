@@ -0,0 +1 @@
+ALTER TABLE impersonations ADD COLUMN revoked_at TIMESTAMP WITH TIME ZONE;
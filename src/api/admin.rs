@@ -0,0 +1,96 @@
+use crate::auth::{RequirePermission, UsersAdmin};
+use crate::configuration::Configuration;
+use crate::db;
+use crate::db::PgPool;
+use crate::error::ApiError;
+use crate::model::login_events::ClientInfo;
+use crate::model::sessions::TokenDto;
+use crate::model::users::{UserDetailDto, UserPageDto, UserSearchDto, UserStatusDto};
+use crate::service;
+use crate::validator::Validate;
+use actix_web::web::Json;
+use actix_web::{get, post, put, web, HttpResponse};
+
+#[get("/admin/users")]
+pub async fn search_users(
+    _: RequirePermission<UsersAdmin>,
+    pool: web::Data<PgPool>,
+    search: web::Query<UserSearchDto>,
+) -> Result<Json<UserPageDto>, ApiError> {
+    search.validate()?;
+
+    let conn = db::get_conn(&pool)?;
+    let page = web::block(move || service::admin_service::search_users(&conn, search.into_inner()))
+        .await?;
+
+    Ok(Json(page))
+}
+
+#[get("/admin/users/{id}")]
+pub async fn get_user(
+    _: RequirePermission<UsersAdmin>,
+    pool: web::Data<PgPool>,
+    user_id: web::Path<i64>,
+) -> Result<Json<UserDetailDto>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let user =
+        web::block(move || service::admin_service::get_user_detail(&conn, user_id.into_inner()))
+            .await?;
+
+    Ok(Json(user))
+}
+
+#[put("/admin/users/{id}/status")]
+pub async fn update_user_status(
+    admin: RequirePermission<UsersAdmin>,
+    pool: web::Data<PgPool>,
+    user_id: web::Path<i64>,
+    status_dto: web::Json<UserStatusDto>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    web::block(move || {
+        service::admin_service::update_user_status(
+            &conn,
+            admin.claims.user_id,
+            user_id.into_inner(),
+            status_dto.into_inner().status,
+        )
+    })
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Impersonation tokens carry no permissions, so they can't be used to impersonate again
+#[post("/admin/users/{id}/impersonate")]
+pub async fn impersonate(
+    admin: RequirePermission<UsersAdmin>,
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    client_info: ClientInfo,
+    user_id: web::Path<i64>,
+) -> Result<Json<TokenDto>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let impersonation_config = config.impersonation.clone();
+    let token = web::block(move || {
+        service::admin_service::impersonate(
+            &conn,
+            admin.claims.user_id,
+            user_id.into_inner(),
+            &client_info,
+            &jwt_config,
+            &impersonation_config,
+        )
+    })
+    .await?;
+
+    Ok(Json(token))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(search_users);
+    cfg.service(get_user);
+    cfg.service(update_user_status);
+    cfg.service(impersonate);
+}
@@ -0,0 +1,26 @@
+use crate::auth::AccessClaims;
+use crate::db;
+use crate::db::PgPool;
+use crate::error::ApiError;
+use crate::model::login_events::LoginEvent;
+use crate::service;
+use actix_web::web::Json;
+use actix_web::{get, web};
+
+#[get("/users/me/login-events")]
+pub async fn get_login_events(
+    access_claims: AccessClaims,
+    pool: web::Data<PgPool>,
+) -> Result<Json<Vec<LoginEvent>>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let login_events = web::block(move || {
+        service::login_event_service::get_users_login_events(&conn, access_claims.user_id)
+    })
+    .await?;
+
+    Ok(Json(login_events))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_login_events);
+}
@@ -0,0 +1,113 @@
+use crate::api::session::build_session_cookie;
+use crate::auth::NotImpersonated;
+use crate::configuration::Configuration;
+use crate::db;
+use crate::db::PgPool;
+use crate::error::ApiError;
+use crate::model::login_events::ClientInfo;
+use crate::model::recovery_codes::RecoveryCodesDto;
+use crate::model::totp::{MfaLoginDto, TotpCodeDto, TotpEnrollmentDto};
+use crate::service;
+use crate::validator::Validate;
+use actix_web::web::Json;
+use actix_web::{post, web, HttpResponse};
+
+#[post("/users/me/2fa/totp")]
+pub async fn enroll_totp(
+    user: NotImpersonated,
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+) -> Result<Json<TotpEnrollmentDto>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let mfa_config = config.mfa.clone();
+    let enrollment = web::block(move || {
+        service::mfa_service::enroll_totp(&conn, user.claims.user_id, &mfa_config)
+    })
+    .await?;
+
+    Ok(Json(enrollment))
+}
+
+#[post("/users/me/2fa/totp/confirm")]
+pub async fn confirm_totp(
+    user: NotImpersonated,
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    argon2_config: web::Data<argon2::Config<'static>>,
+    code_dto: web::Json<TotpCodeDto>,
+) -> Result<Json<RecoveryCodesDto>, ApiError> {
+    code_dto.validate()?;
+
+    let conn = db::get_conn(&pool)?;
+    let mfa_config = config.mfa.clone();
+    let recovery_codes = web::block(move || {
+        service::mfa_service::confirm_totp(
+            &conn,
+            user.claims.user_id,
+            &code_dto.code,
+            &mfa_config,
+            &argon2_config,
+        )
+    })
+    .await?;
+
+    Ok(Json(recovery_codes))
+}
+
+#[post("/users/me/2fa/recovery-codes")]
+pub async fn regenerate_recovery_codes(
+    user: NotImpersonated,
+    pool: web::Data<PgPool>,
+    argon2_config: web::Data<argon2::Config<'static>>,
+) -> Result<Json<RecoveryCodesDto>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let recovery_codes = web::block(move || {
+        service::mfa_service::regenerate_recovery_codes(&conn, user.claims.user_id, &argon2_config)
+    })
+    .await?;
+
+    Ok(Json(recovery_codes))
+}
+
+#[post("/sessions/mfa")]
+pub async fn complete_mfa_login(
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    mfa_login_dto: web::Json<MfaLoginDto>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, ApiError> {
+    mfa_login_dto.validate()?;
+
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let mfa_config = config.mfa.clone();
+    let lockout_config = config.lockout.clone();
+    let session_limits = config.session_limits.clone();
+    let token_pair = web::block(move || {
+        service::mfa_service::complete_mfa_login(
+            &conn,
+            &mfa_login_dto,
+            &client_info,
+            &jwt_config,
+            &mfa_config,
+            &lockout_config,
+            &session_limits,
+        )
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .cookie(build_session_cookie(
+            config.jwt.clone(),
+            token_pair.session_token.token.clone(),
+            &token_pair.session_token.expiration,
+        ))
+        .json(token_pair.access_token))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(enroll_totp);
+    cfg.service(confirm_totp);
+    cfg.service(regenerate_recovery_codes);
+    cfg.service(complete_mfa_login);
+}
@@ -1,2 +1,38 @@
+use crate::configuration::Configuration;
+use crate::error::ApiError;
+use crate::model::login_events::ClientInfo;
+use crate::rate_limit;
+use actix_web::{dev, http, web, FromRequest, HttpRequest};
+use futures::future::{ok, Ready};
+
+pub mod admin;
+pub mod login_events;
+pub mod mfa;
+pub mod oauth;
+pub mod roles;
 pub mod session;
 pub mod users;
+pub mod webauthn;
+pub mod well_known;
+
+const USER_AGENT_MAX_LEN: usize = 512;
+
+impl FromRequest for ClientInfo {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        let trusted_proxies = req
+            .app_data::<web::Data<Configuration>>()
+            .map_or(0, |config| config.rate_limit.trusted_proxies);
+        ok(ClientInfo {
+            ip: rate_limit::client_ip(req.peer_addr(), req.headers(), trusted_proxies),
+            user_agent: req
+                .headers()
+                .get(http::header::USER_AGENT)
+                .and_then(|user_agent| user_agent.to_str().ok())
+                .map(|user_agent| user_agent.chars().take(USER_AGENT_MAX_LEN).collect()),
+        })
+    }
+}
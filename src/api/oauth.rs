@@ -0,0 +1,168 @@
+use crate::api::session::get_session_token;
+use crate::auth;
+use crate::auth::ScopedAccessClaims;
+use crate::configuration::Configuration;
+use crate::db;
+use crate::db::PgPool;
+use crate::error::ApiError;
+use crate::model::login_events::ClientInfo;
+use crate::model::oauth::{
+    AuthorizeRequestDto, ConsentDecisionDto, IntrospectionDto, IntrospectionRequestDto,
+    TokenRequestDto, UserInfoDto,
+};
+use crate::service;
+use crate::service::oauth_service::AuthorizeOutcome;
+use actix_web::web::Json;
+use actix_web::{get, http, post, route, web, HttpRequest, HttpResponse};
+
+// The user is identified by the session cookie, access tokens could be replayed by any client
+#[get("/oauth/authorize")]
+pub async fn authorize(
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    authorize_request: web::Query<AuthorizeRequestDto>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let session_token = get_session_token(&req, &config.jwt)?;
+
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let oauth_config = config.oauth.clone();
+    let outcome = web::block(move || {
+        service::oauth_service::authorize(
+            &conn,
+            &session_token,
+            &authorize_request,
+            &jwt_config,
+            &oauth_config,
+        )
+    })
+    .await?;
+
+    match outcome {
+        AuthorizeOutcome::ConsentRequired(consent) => Ok(HttpResponse::Ok()
+            .header(http::header::CACHE_CONTROL, "no-store")
+            .json(consent)),
+        AuthorizeOutcome::Redirect(location) => Ok(HttpResponse::Found()
+            .header(http::header::LOCATION, location)
+            .finish()),
+    }
+}
+
+#[post("/oauth/authorize")]
+pub async fn decide_consent(
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    decision: web::Form<ConsentDecisionDto>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let session_token = get_session_token(&req, &config.jwt)?;
+
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let oauth_config = config.oauth.clone();
+    let location = web::block(move || {
+        service::oauth_service::decide_consent(
+            &conn,
+            &session_token,
+            &decision,
+            &jwt_config,
+            &oauth_config,
+        )
+    })
+    .await?;
+
+    Ok(HttpResponse::Found()
+        .header(http::header::LOCATION, location)
+        .finish())
+}
+
+#[post("/oauth/token")]
+pub async fn create_token(
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    token_request: web::Form<TokenRequestDto>,
+    req: HttpRequest,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, ApiError> {
+    // Client credentials are either sent via HTTP Basic or in the form body
+    let (client_id, client_secret) = match auth::get_basic_credentials(req.headers()) {
+        Some((client_id, client_secret)) => (client_id, Some(client_secret)),
+        None => (
+            token_request.client_id.clone().ok_or_else(|| {
+                ApiError::OAuthError("invalid_client", String::from("client_id is missing"))
+            })?,
+            token_request.client_secret.clone(),
+        ),
+    };
+
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let session_limits = config.session_limits.clone();
+    let token = web::block(move || {
+        let client = service::oauth_service::authenticate_client(
+            &conn,
+            &client_id,
+            client_secret.as_deref(),
+        )?;
+        service::oauth_service::create_token(
+            &conn,
+            &client,
+            &token_request,
+            &client_info,
+            &jwt_config,
+            &session_limits,
+        )
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .json(token))
+}
+
+#[post("/oauth/introspect")]
+pub async fn introspect(
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    introspection_request: web::Form<IntrospectionRequestDto>,
+    req: HttpRequest,
+) -> Result<Json<IntrospectionDto>, ApiError> {
+    let (client_id, client_secret) =
+        auth::get_basic_credentials(req.headers()).ok_or(ApiError::InvalidClientCredentials)?;
+    service::oauth_service::authenticate_introspection_client(
+        &config.oauth,
+        &client_id,
+        &client_secret,
+    )?;
+
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let introspection = web::block(move || {
+        service::oauth_service::introspect(&conn, &introspection_request, &jwt_config)
+    })
+    .await?;
+
+    Ok(Json(introspection))
+}
+
+// OpenID Connect Core section 5.3.1 requires both GET and POST
+#[route("/oauth/userinfo", method = "GET", method = "POST")]
+pub async fn get_user_info(
+    access: ScopedAccessClaims,
+    pool: web::Data<PgPool>,
+) -> Result<Json<UserInfoDto>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let user_info =
+        web::block(move || service::oauth_service::get_user_info(&conn, &access.claims)).await?;
+
+    Ok(Json(user_info))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(authorize);
+    cfg.service(decide_consent);
+    cfg.service(create_token);
+    cfg.service(introspect);
+    cfg.service(get_user_info);
+}
@@ -0,0 +1,72 @@
+use crate::auth::{RequirePermission, RolesAdmin};
+use crate::db;
+use crate::db::PgPool;
+use crate::error::ApiError;
+use crate::model::roles::RoleDto;
+use crate::service;
+use actix_web::web::Json;
+use actix_web::{delete, get, put, web, HttpResponse};
+
+#[get("/admin/roles")]
+pub async fn get_roles(
+    _: RequirePermission<RolesAdmin>,
+    pool: web::Data<PgPool>,
+) -> Result<Json<Vec<RoleDto>>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let roles = web::block(move || service::role_service::get_roles(&conn)).await?;
+
+    Ok(Json(roles))
+}
+
+#[get("/admin/users/{id}/roles")]
+pub async fn get_users_roles(
+    _: RequirePermission<RolesAdmin>,
+    pool: web::Data<PgPool>,
+    user_id: web::Path<i64>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let roles =
+        web::block(move || service::role_service::get_users_roles(&conn, user_id.into_inner()))
+            .await?;
+
+    Ok(Json(roles))
+}
+
+#[put("/admin/users/{id}/roles/{role}")]
+pub async fn grant_role(
+    admin: RequirePermission<RolesAdmin>,
+    pool: web::Data<PgPool>,
+    path: web::Path<(i64, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, role_name) = path.into_inner();
+    let conn = db::get_conn(&pool)?;
+    web::block(move || {
+        service::role_service::grant_role(&conn, admin.claims.user_id, user_id, &role_name)
+    })
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/admin/users/{id}/roles/{role}")]
+pub async fn revoke_role(
+    admin: RequirePermission<RolesAdmin>,
+    pool: web::Data<PgPool>,
+    path: web::Path<(i64, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (user_id, role_name) = path.into_inner();
+    let conn = db::get_conn(&pool)?;
+    web::block(move || {
+        service::role_service::revoke_role(&conn, admin.claims.user_id, user_id, &role_name)
+    })
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_roles);
+    cfg.service(get_users_roles);
+    cfg.service(grant_role);
+    cfg.service(revoke_role);
+}
@@ -1,23 +1,30 @@
-use crate::auth::AccessClaims;
+use crate::auth::{AccessClaims, NotImpersonated};
 use crate::configuration::Configuration;
 use crate::configuration::Jwt;
 use crate::db;
 use crate::db::PgPool;
 use crate::error::ApiError;
-use crate::model::sessions::{LoginDto, Session};
+use crate::model::login_events::ClientInfo;
+use crate::model::sessions::{LoginDto, SessionDto};
 use crate::service;
+use crate::service::session_service::LoginOutcome;
 use actix_web::web::Json;
-use actix_web::{get, http, post, web, HttpMessage, HttpResponse};
+use actix_web::{delete, get, http, post, web, HttpMessage, HttpResponse, ResponseError};
 use chrono::Utc;
+use uuid::Uuid;
 
 #[get("/sessions")]
 pub async fn get_sessions(
     access_claims: AccessClaims,
     pool: web::Data<PgPool>,
-) -> Result<Json<Vec<Session>>, ApiError> {
+) -> Result<Json<Vec<SessionDto>>, ApiError> {
     let conn = db::get_conn(&pool)?;
     let sessions = web::block(move || {
-        service::session_service::get_users_sessions(&conn, access_claims.user_id)
+        service::session_service::get_users_sessions(
+            &conn,
+            access_claims.user_id,
+            access_claims.sid,
+        )
     })
     .await?;
 
@@ -29,13 +36,29 @@ pub async fn create_session(
     pool: web::Data<PgPool>,
     config: web::Data<Configuration>,
     login_dto: web::Json<LoginDto>,
+    client_info: ClientInfo,
 ) -> Result<HttpResponse, ApiError> {
     let conn = db::get_conn(&pool)?;
     let jwt_config = config.jwt.clone();
-    let token_pair = web::block(move || {
-        service::session_service::create_login_token_pair(&conn, &login_dto, &jwt_config)
+    let mfa_config = config.mfa.clone();
+    let lockout_config = config.lockout.clone();
+    let session_limits = config.session_limits.clone();
+    let login_outcome = web::block(move || {
+        service::session_service::create_login_token_pair(
+            &conn,
+            &login_dto,
+            &client_info,
+            &jwt_config,
+            &mfa_config,
+            &lockout_config,
+            &session_limits,
+        )
     })
     .await?;
+    let token_pair = match login_outcome {
+        LoginOutcome::Authenticated(token_pair) => token_pair,
+        LoginOutcome::MfaRequired(mfa_required) => return Ok(HttpResponse::Ok().json(mfa_required)),
+    };
 
     Ok(HttpResponse::Ok()
         .cookie(build_session_cookie(
@@ -51,12 +74,9 @@ pub async fn create_access_token(
     pool: web::Data<PgPool>,
     config: web::Data<Configuration>,
     req: actix_web::HttpRequest,
+    client_info: ClientInfo,
 ) -> Result<HttpResponse, ApiError> {
-    let session_token = req
-        .cookie(&config.jwt.session_cookie_name)
-        .ok_or(ApiError::MissingSessionCookie)?
-        .value()
-        .to_string();
+    let session_token = get_session_token(&req, &config.jwt)?;
 
     let conn = db::get_conn(&pool)?;
     let jwt_config = config.jwt.clone();
@@ -64,6 +84,7 @@ pub async fn create_access_token(
         service::session_service::create_access_token_and_refresh(
             &conn,
             &session_token,
+            &client_info,
             &jwt_config,
         )
     })
@@ -78,7 +99,98 @@ pub async fn create_access_token(
         .json(token_pair.access_token))
 }
 
-fn build_session_cookie(
+#[delete("/sessions/{id}")]
+pub async fn delete_session(
+    user: NotImpersonated,
+    pool: web::Data<PgPool>,
+    session_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    web::block(move || {
+        service::session_service::blacklist_session(
+            &conn,
+            user.claims.user_id,
+            session_id.into_inner(),
+        )
+    })
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/sessions/logout")]
+pub async fn logout(
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    req: actix_web::HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    // Without a cookie there is nothing to blacklist. The cookie is cleared in any case.
+    let result = match req.cookie(&config.jwt.session_cookie_name) {
+        Some(cookie) => blacklist_session_of_token(&pool, &config, cookie.value()).await,
+        None => Ok(()),
+    };
+    let mut response = match result {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => e.error_response(),
+    };
+
+    let removal_cookie = build_session_removal_cookie(config.jwt.clone());
+    response.add_cookie(&removal_cookie).map_err(|e| {
+        error!("{}", e);
+        ApiError::InternalServerError
+    })?;
+    Ok(response)
+}
+
+async fn blacklist_session_of_token(
+    pool: &PgPool,
+    config: &Configuration,
+    session_token: &str,
+) -> Result<(), ApiError> {
+    let conn = db::get_conn(pool)?;
+    let session_token = session_token.to_string();
+    let jwt_config = config.jwt.clone();
+    web::block(move || service::session_service::logout(&conn, &session_token, &jwt_config))
+        .await?;
+    Ok(())
+}
+
+#[post("/sessions/revoke-others")]
+pub async fn revoke_other_sessions(
+    user: NotImpersonated,
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    req: actix_web::HttpRequest,
+) -> Result<Json<usize>, ApiError> {
+    let session_token = get_session_token(&req, &config.jwt)?;
+
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let revoked = web::block(move || {
+        service::session_service::blacklist_other_sessions(
+            &conn,
+            user.claims.user_id,
+            &session_token,
+            &jwt_config,
+        )
+    })
+    .await?;
+
+    Ok(Json(revoked))
+}
+
+pub fn get_session_token(
+    req: &actix_web::HttpRequest,
+    jwt_config: &Jwt,
+) -> Result<String, ApiError> {
+    Ok(req
+        .cookie(&jwt_config.session_cookie_name)
+        .ok_or(ApiError::MissingSessionCookie)?
+        .value()
+        .to_string())
+}
+
+pub fn build_session_cookie(
     jwt_config: Jwt,
     token: String,
     exp_time: &chrono::DateTime<Utc>,
@@ -94,8 +206,21 @@ fn build_session_cookie(
         .finish()
 }
 
+/// Expired already, so the browser drops the session cookie
+fn build_session_removal_cookie(jwt_config: Jwt) -> actix_web::cookie::Cookie<'static> {
+    http::Cookie::build(jwt_config.session_cookie_name, "")
+        .domain(jwt_config.domain)
+        .path(jwt_config.path)
+        .max_age(time::Duration::zero())
+        .expires(time::OffsetDateTime::now_utc() - time::Duration::days(365))
+        .finish()
+}
+
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(get_sessions);
     cfg.service(create_session);
     cfg.service(create_access_token);
+    cfg.service(delete_session);
+    cfg.service(logout);
+    cfg.service(revoke_other_sessions);
 }
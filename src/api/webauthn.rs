@@ -0,0 +1,125 @@
+use crate::api::session::build_session_cookie;
+use crate::auth::NotImpersonated;
+use crate::configuration::Configuration;
+use crate::db;
+use crate::db::PgPool;
+use crate::error::ApiError;
+use crate::model::login_events::ClientInfo;
+use crate::model::webauthn::{
+    CreationOptionsDto, LoginOptionsRequestDto, RegisterCredentialDto, RequestOptionsDto,
+    WebAuthnLoginDto,
+};
+use crate::service;
+use crate::validator::Validate;
+use actix_web::web::Json;
+use actix_web::{post, web, HttpResponse};
+
+#[post("/users/me/webauthn/registration-options")]
+pub async fn registration_options(
+    user: NotImpersonated,
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+) -> Result<Json<CreationOptionsDto>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let webauthn_config = config.webauthn.clone();
+    let options = web::block(move || {
+        service::webauthn_service::registration_options(
+            &conn,
+            user.claims.user_id,
+            &webauthn_config,
+        )
+    })
+    .await?;
+
+    Ok(Json(options))
+}
+
+#[post("/users/me/webauthn/credentials")]
+pub async fn register_credential(
+    user: NotImpersonated,
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    argon2_config: web::Data<argon2::Config<'static>>,
+    register_dto: web::Json<RegisterCredentialDto>,
+) -> Result<HttpResponse, ApiError> {
+    register_dto.validate()?;
+
+    let conn = db::get_conn(&pool)?;
+    let webauthn_config = config.webauthn.clone();
+    let recovery_codes = web::block(move || {
+        service::webauthn_service::register_credential(
+            &conn,
+            user.claims.user_id,
+            &register_dto,
+            &webauthn_config,
+            &argon2_config,
+        )
+    })
+    .await?;
+
+    Ok(match recovery_codes {
+        Some(recovery_codes) => HttpResponse::Created().json(recovery_codes),
+        None => HttpResponse::Created().finish(),
+    })
+}
+
+#[post("/sessions/webauthn/options")]
+pub async fn login_options(
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    options_request: web::Json<LoginOptionsRequestDto>,
+) -> Result<Json<RequestOptionsDto>, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let webauthn_config = config.webauthn.clone();
+    let options = web::block(move || {
+        service::webauthn_service::login_options(
+            &conn,
+            &options_request,
+            &jwt_config,
+            &webauthn_config,
+        )
+    })
+    .await?;
+
+    Ok(Json(options))
+}
+
+#[post("/sessions/webauthn")]
+pub async fn login(
+    pool: web::Data<PgPool>,
+    config: web::Data<Configuration>,
+    login_dto: web::Json<WebAuthnLoginDto>,
+    client_info: ClientInfo,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db::get_conn(&pool)?;
+    let jwt_config = config.jwt.clone();
+    let webauthn_config = config.webauthn.clone();
+    let session_limits = config.session_limits.clone();
+    let token_pair = web::block(move || {
+        service::webauthn_service::login(
+            &conn,
+            &login_dto,
+            &client_info,
+            &jwt_config,
+            &webauthn_config,
+            &session_limits,
+        )
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .cookie(build_session_cookie(
+            config.jwt.clone(),
+            token_pair.session_token.token.clone(),
+            &token_pair.session_token.expiration,
+        ))
+        .json(token_pair.access_token))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(registration_options);
+    cfg.service(register_credential);
+    cfg.service(login_options);
+    cfg.service(login);
+}
@@ -0,0 +1,25 @@
+use crate::auth;
+use crate::configuration::Configuration;
+use crate::error::ApiError;
+use crate::model::oauth::OpenIdConfigurationDto;
+use crate::service;
+use actix_web::web::Json;
+use actix_web::{get, web};
+use jsonwebtoken::jwk::JwkSet;
+
+#[get("/.well-known/jwks.json")]
+pub async fn get_jwks(config: web::Data<Configuration>) -> Result<Json<JwkSet>, ApiError> {
+    Ok(Json(auth::access_jwk_set(&config.jwt)?))
+}
+
+#[get("/.well-known/openid-configuration")]
+pub async fn get_openid_configuration(
+    config: web::Data<Configuration>,
+) -> Json<OpenIdConfigurationDto> {
+    Json(service::oauth_service::openid_configuration(&config.jwt))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_jwks);
+    cfg.service(get_openid_configuration);
+}
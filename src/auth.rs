@@ -1,23 +1,40 @@
 use crate::configuration;
+use crate::configuration::JwtKey;
 use crate::error::ApiError;
+use crate::jwk;
+use crate::model::oauth::UserInfoDto;
 use actix_web::http::header::HeaderMap;
 use actix_web::{dev, FromRequest, HttpRequest};
-use futures::future::{err, ok, Ready};
-use jsonwebtoken::decode;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::future::{err, ok, ready, Ready};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::Algorithm;
 use jsonwebtoken::DecodingKey;
+use jsonwebtoken::EncodingKey;
+use jsonwebtoken::Header;
 use jsonwebtoken::Validation;
+use jsonwebtoken::{decode, decode_header, encode};
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
+use std::marker::PhantomData;
 
 #[derive(Debug)]
 pub enum AuthorizationError {
     NoAuthorizationForAction,
     UserDoesNotExist,
     PasswordInvalid,
+    AccountLocked(chrono::DateTime<chrono::Utc>),
     JwtValidationError(jsonwebtoken::errors::Error),
     SessionTokenBlacklisted,
+    SessionExpired, // Reached jwt.session_max_lifetime_ms
+    SessionLimitReached,
+    MfaTokenSpent, // The login was completed or ran out of second factor attempts
 }
 
 impl fmt::Display for AuthorizationError {
@@ -28,33 +45,264 @@ impl fmt::Display for AuthorizationError {
 
 impl error::Error for AuthorizationError {}
 
+impl AuthorizationError {
+    /// Without details, as stored in login events
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AuthorizationError::NoAuthorizationForAction => "NoAuthorizationForAction",
+            AuthorizationError::UserDoesNotExist => "UserDoesNotExist",
+            AuthorizationError::PasswordInvalid => "PasswordInvalid",
+            AuthorizationError::AccountLocked(_) => "AccountLocked",
+            AuthorizationError::JwtValidationError(_) => "JwtValidationError",
+            AuthorizationError::SessionTokenBlacklisted => "SessionTokenBlacklisted",
+            AuthorizationError::SessionExpired => "SessionExpired",
+            AuthorizationError::SessionLimitReached => "SessionLimitReached",
+            AuthorizationError::MfaTokenSpent => "MfaTokenSpent",
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SessionClaims {
     pub exp: i64, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
     pub iat: i64, // Optional. Issued at (as UTC timestamp)
-    pub iss: String, // Optional. Issuer
+    pub iss: String, // Required. Issuer, must match jwt.issuer
+    pub aud: Vec<String>, // Required. Audiences, one must be in jwt.audiences
     pub session_id: uuid::Uuid,
     pub user_id: i64,
+    // Tokens issued before rotation have none, their sessions are at generation 0
+    #[serde(default)]
+    pub generation: i32, // Incremented on every refresh, older generations are rejected
 }
+
+/// Proves the password was checked, the session is only created once the second factor is too
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MfaClaims {
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+    pub aud: Vec<String>,
+    pub jti: uuid::Uuid, // Id of the pending login, which limits the attempts and is used once
+    pub mfa_user_id: i64,
+    pub platform: String,
+    pub sub_platform: String,
+}
+
+/// The user was shown an OAuth authorization request, the code is only issued once they consent
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConsentClaims {
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+    pub aud: Vec<String>,
+    pub consent_sid: uuid::Uuid, // Session the request was shown to, only it can consent
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub code_challenge: String,
+    pub scope: String,
+    pub nonce: Option<String>,
+    pub state: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AccessClaims {
     pub exp: i64, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
     pub iat: i64, // Optional. Issued at (as UTC timestamp)
-    pub iss: String, // Optional. Issuer
+    pub iss: String, // Required. Issuer, must match jwt.issuer
+    pub aud: Vec<String>, // Required. Audiences, one must be in jwt.audiences
+    pub user_id: i64,
+    pub sid: uuid::Uuid, // Session the token was issued for, checked against revoked sessions
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub scope: String, // Permissions of the roles, space separated like OAuth scopes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>, // Set on impersonation tokens, the admin acting as the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>, // Set on tokens issued to an OAuth client for the user
+}
+
+/// Who really acts on behalf of the user (RFC 8693 section 4.1)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Actor {
     pub user_id: i64,
 }
 
+impl AccessClaims {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.scope.split_whitespace().any(|p| p == permission)
+    }
+}
+
+/// A permission granted by roles, handlers require it with RequirePermission
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+pub struct RolesAdmin;
+
+impl Permission for RolesAdmin {
+    const NAME: &'static str = "roles:admin";
+}
+
+pub struct UsersAdmin;
+
+impl Permission for UsersAdmin {
+    const NAME: &'static str = "users:admin";
+}
+
+/// Access claims of a user whose token carries the permission P, everyone else is rejected
+pub struct RequirePermission<P: Permission> {
+    pub claims: AccessClaims,
+    permission: PhantomData<P>,
+}
+
+/// Access claims of a token the user obtained themself. Impersonation tokens are rejected, for
+/// endpoints that change credentials or sessions or let others act for the user.
+pub struct NotImpersonated {
+    pub claims: AccessClaims,
+}
+
+/// Access claims of a user token, including those issued to OAuth clients. Only for endpoints
+/// that limit what they answer to the scope of the token, the other extractors reject them.
+pub struct ScopedAccessClaims {
+    pub claims: AccessClaims,
+}
+
+/// Claims of access tokens issued to a client itself (client_credentials grant), no user involved
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClientClaims {
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+    pub aud: Vec<String>,
+    pub client_id: String,
+}
+
+/// OpenID Connect ID token, tells a client who the user is (OpenID Connect Core section 2)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+    pub aud: String, // client_id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user_info: UserInfoDto,
+}
+
+/// An access token is either issued to a user or to a client, the claims tell them apart
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum AccessToken {
+    User(AccessClaims),
+    Client(ClientClaims),
+}
+
 impl FromRequest for AccessClaims {
     type Error = ApiError;
     type Future = Ready<Result<Self, Self::Error>>;
     type Config = ();
 
     fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
-        match req.extensions().get::<AccessClaims>() {
-            Some(claims) => ok(claims.clone()),
+        ready(user_access_claims(req))
+    }
+}
+
+impl<P: Permission> FromRequest for RequirePermission<P> {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        ready(user_access_claims(req).and_then(|claims| {
+            if !claims.has_permission(P::NAME) {
+                debug!("User {} lacks permission {}", claims.user_id, P::NAME);
+                return Err(ApiError::AuthorizationError);
+            }
+            Ok(RequirePermission {
+                claims,
+                permission: PhantomData,
+            })
+        }))
+    }
+}
+
+impl FromRequest for NotImpersonated {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        ready(
+            user_access_claims(req).and_then(|claims| match &claims.act {
+                Some(actor) => {
+                    warn!(
+                        "User {} impersonating user {} was denied {}",
+                        actor.user_id,
+                        claims.user_id,
+                        req.path()
+                    );
+                    Err(ApiError::ImpersonationDenied)
+                }
+                None => Ok(NotImpersonated { claims }),
+            }),
+        )
+    }
+}
+
+impl FromRequest for ScopedAccessClaims {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        ready(scoped_access_claims(req).map(|claims| ScopedAccessClaims { claims }))
+    }
+}
+
+/// Tokens of OAuth clients only carry the scope the user granted, they can't manage the account
+fn user_access_claims(req: &HttpRequest) -> Result<AccessClaims, ApiError> {
+    let claims = scoped_access_claims(req)?;
+    if let Some(client_id) = &claims.client_id {
+        debug!(
+            "OAuth client {} of user {} was denied {}",
+            client_id,
+            claims.user_id,
+            req.path()
+        );
+        return Err(ApiError::AuthorizationError);
+    }
+    Ok(claims)
+}
+
+fn scoped_access_claims(req: &HttpRequest) -> Result<AccessClaims, ApiError> {
+    match req.extensions().get::<AccessToken>() {
+        Some(AccessToken::User(claims)) => Ok(claims.clone()),
+        Some(AccessToken::Client(claims)) => {
+            debug!("Client {} used a user endpoint", claims.client_id);
+            Err(ApiError::AuthorizationError)
+        }
+        None => {
+            error!("Could not extract Claims from JWT (should have been added in middleware)");
+            Err(ApiError::InternalServerError) // TODO
+        }
+    }
+}
+
+impl FromRequest for ClientClaims {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        match req.extensions().get::<AccessToken>() {
+            Some(AccessToken::Client(claims)) => ok(claims.clone()),
+            Some(AccessToken::User(_)) => err(ApiError::AuthorizationError),
             None => {
                 error!("Could not extract Claims from JWT (should have been added in middleware)");
-                err(ApiError::InternalServerError) // TODO
+                err(ApiError::InternalServerError)
             }
         }
     }
@@ -70,27 +318,195 @@ pub fn verify_subject(user_id: i64, sub: i64) -> Result<(), AuthorizationError>
 pub fn decode_access_jwt(
     token: &str,
     jwt_config: &configuration::Jwt,
-) -> Result<AccessClaims, ApiError> {
-    let decoding_key = DecodingKey::from_secret(&jwt_config.access_secret.as_ref());
-    decode::<AccessClaims>(token, &decoding_key, &Validation::default())
-        .map(|data| data.claims)
-        .map_err(|e| {
-            error!("{}", e);
-            ApiError::JwtValidationError(e)
-        })
+) -> Result<AccessToken, ApiError> {
+    let validation = validation(jwt_config);
+    decode_jwt(
+        token,
+        &jwt_config.access_key_id,
+        &jwt_config.access_keys,
+        &validation,
+    )
+    .map_err(|e| {
+        error!("{}", e);
+        ApiError::JwtValidationError(e)
+    })
 }
 
 pub fn decode_session_jwt(
     token: &str,
     jwt_config: &configuration::Jwt,
 ) -> Result<SessionClaims, AuthorizationError> {
-    let decoding_key = DecodingKey::from_secret(&jwt_config.session_secret.as_ref());
-    decode::<SessionClaims>(token, &decoding_key, &Validation::default())
-        .map(|data| data.claims)
-        .map_err(|e| {
-            error!("{}", e);
-            AuthorizationError::JwtValidationError(e)
-        })
+    let validation = validation(jwt_config);
+    decode_jwt(
+        token,
+        &jwt_config.session_key_id,
+        &jwt_config.session_keys,
+        &validation,
+    )
+    .map_err(|e| {
+        error!("{}", e);
+        AuthorizationError::JwtValidationError(e)
+    })
+}
+
+pub fn decode_mfa_jwt(
+    token: &str,
+    jwt_config: &configuration::Jwt,
+) -> Result<MfaClaims, AuthorizationError> {
+    let validation = validation(jwt_config);
+    decode_jwt(
+        token,
+        &jwt_config.session_key_id,
+        &jwt_config.session_keys,
+        &validation,
+    )
+    .map_err(|e| {
+        error!("{}", e);
+        AuthorizationError::JwtValidationError(e)
+    })
+}
+
+pub fn decode_consent_jwt(
+    token: &str,
+    jwt_config: &configuration::Jwt,
+) -> Result<ConsentClaims, AuthorizationError> {
+    let validation = validation(jwt_config);
+    decode_jwt(
+        token,
+        &jwt_config.session_key_id,
+        &jwt_config.session_keys,
+        &validation,
+    )
+    .map_err(|e| {
+        error!("{}", e);
+        AuthorizationError::JwtValidationError(e)
+    })
+}
+
+pub fn encode_access_jwt(
+    claims: &impl Serialize,
+    jwt_config: &configuration::Jwt,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    encode_jwt(claims, &jwt_config.access_key_id, &jwt_config.access_keys)
+}
+
+pub fn encode_session_jwt(
+    claims: &SessionClaims,
+    jwt_config: &configuration::Jwt,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    encode_jwt(claims, &jwt_config.session_key_id, &jwt_config.session_keys)
+}
+
+pub fn encode_mfa_jwt(
+    claims: &MfaClaims,
+    jwt_config: &configuration::Jwt,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    encode_jwt(claims, &jwt_config.session_key_id, &jwt_config.session_keys)
+}
+
+pub fn encode_consent_jwt(
+    claims: &ConsentClaims,
+    jwt_config: &configuration::Jwt,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    encode_jwt(claims, &jwt_config.session_key_id, &jwt_config.session_keys)
+}
+
+/// Signs with the key `kid` and announces it in the header, so the key can be rotated later
+fn encode_jwt<T: Serialize>(
+    claims: &T,
+    kid: &str,
+    keys: &HashMap<String, JwtKey>,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let key = keys.get(kid).ok_or(ErrorKind::InvalidKeyFormat)?;
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(kid.to_owned());
+    encode(&header, claims, &encoding_key(key)?)
+}
+
+/// Only tokens issued by this deployment for one of its audiences are accepted
+fn validation(jwt_config: &configuration::Jwt) -> Validation {
+    let mut validation = Validation::default();
+    validation.leeway = jwt_config.leeway_s;
+    validation.set_issuer(&[&jwt_config.issuer]);
+    validation.set_audience(&jwt_config.audiences);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+    validation
+}
+
+/// Verifies with the key named by the `kid` header, as long as that key is not retired
+fn decode_jwt<T: DeserializeOwned>(
+    token: &str,
+    default_kid: &str,
+    keys: &HashMap<String, JwtKey>,
+    validation: &Validation,
+) -> Result<T, jsonwebtoken::errors::Error> {
+    let header = decode_header(token)?;
+    // Tokens issued before key ids were introduced are checked against the current key
+    let kid = header.kid.as_deref().unwrap_or(default_kid);
+    let key = match keys.get(kid) {
+        Some(key) if !key.is_retired() => key,
+        _ => return Err(ErrorKind::InvalidSignature.into()),
+    };
+    let mut validation = validation.clone();
+    validation.algorithms = vec![key.algorithm];
+    decode::<T>(token, &decoding_key(key)?, &validation).map(|data| data.claims)
+}
+
+fn encoding_key(key: &JwtKey) -> Result<EncodingKey, jsonwebtoken::errors::Error> {
+    let private_key = key.private_key.as_bytes();
+    match key.algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(EncodingKey::from_secret(
+            key.secret.as_deref().unwrap_or_default().as_ref(),
+        )),
+        Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(private_key),
+        Algorithm::EdDSA => EncodingKey::from_ed_pem(private_key),
+        _ => EncodingKey::from_rsa_pem(private_key),
+    }
+}
+
+fn decoding_key(key: &JwtKey) -> Result<DecodingKey, jsonwebtoken::errors::Error> {
+    let public_key = key.public_key.as_bytes();
+    match key.algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(DecodingKey::from_secret(
+            key.secret.as_deref().unwrap_or_default().as_ref(),
+        )),
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(public_key),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(public_key),
+        _ => DecodingKey::from_rsa_pem(public_key),
+    }
+}
+
+/// Parses every configured key once, so broken PEM files are noticed at startup
+pub fn verify_keys(jwt_config: &configuration::Jwt) -> Result<(), jsonwebtoken::errors::Error> {
+    for key in jwt_config
+        .access_keys
+        .values()
+        .chain(jwt_config.session_keys.values())
+    {
+        encoding_key(key)?;
+        decoding_key(key)?;
+    }
+    Ok(())
+}
+
+/// Public keys other services can use to verify access tokens. Shared secrets are never published.
+pub fn access_jwk_set(jwt_config: &configuration::Jwt) -> Result<JwkSet, jwk::JwkError> {
+    let mut kids = jwt_config
+        .access_keys
+        .iter()
+        .filter(|(_, key)| !key.uses_secret() && !key.is_retired())
+        .map(|(kid, _)| kid)
+        .collect::<Vec<&String>>();
+    kids.sort();
+
+    let mut keys = Vec::with_capacity(kids.len());
+    for kid in kids {
+        let key = &jwt_config.access_keys[kid];
+        let mut jwk = jwk::from_public_pem(key.algorithm, &key.public_key)?;
+        jwk.common.key_id = Some(kid.clone());
+        keys.push(jwk);
+    }
+    Ok(JwkSet { keys })
 }
 
 pub fn get_auth_token(headers: &HeaderMap) -> Option<&str> {
@@ -100,3 +516,271 @@ pub fn get_auth_token(headers: &HeaderMap) -> Option<&str> {
         .ok()
         .and_then(|s| s.strip_prefix("Bearer "))
 }
+
+/// Client id and secret of HTTP Basic authentication (RFC 7617). Clients form-urlencode both
+/// before joining them (RFC 6749 section 2.3.1), so ids and secrets may contain a colon.
+pub fn get_basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get("Authorization")?
+        .to_str()
+        .ok()
+        .and_then(|s| s.strip_prefix("Basic "))?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    Some((form_urldecode(client_id)?, form_urldecode(client_secret)?))
+}
+
+fn form_urldecode(value: &str) -> Option<String> {
+    let pairs: Vec<(String, String)> =
+        serde_urlencoded::from_str(&format!("value={}", value)).ok()?;
+    match pairs.as_slice() {
+        [(_, decoded)] => Some(decoded.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::Jwt;
+    use crate::test_support::secret_key;
+    use base64::Engine;
+    use jsonwebtoken::Validation;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Claims {
+        exp: i64,
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            exp: chrono::Utc::now().timestamp() + 60,
+        }
+    }
+
+    #[test]
+    fn token_carries_kid() {
+        let mut keys = HashMap::new();
+        keys.insert(String::from("v1"), secret_key("secret-1", None));
+        let token = super::encode_jwt(&claims(), "v1", &keys).unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(Some(String::from("v1")), header.kid);
+    }
+
+    #[test]
+    fn previous_key_is_accepted_until_retired() {
+        let mut keys = HashMap::new();
+        keys.insert(String::from("v1"), secret_key("secret-1", None));
+        let token = super::encode_jwt(&claims(), "v1", &keys).unwrap();
+
+        let tomorrow = chrono::Utc::now() + chrono::Duration::days(1);
+        keys.insert(String::from("v1"), secret_key("secret-1", Some(tomorrow)));
+        keys.insert(String::from("v2"), secret_key("secret-2", None));
+        assert!(super::decode_jwt::<Claims>(&token, "v2", &keys, &Validation::default()).is_ok());
+
+        let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+        keys.insert(String::from("v1"), secret_key("secret-1", Some(yesterday)));
+        assert!(super::decode_jwt::<Claims>(&token, "v2", &keys, &Validation::default()).is_err());
+    }
+
+    #[test]
+    fn unknown_kid_is_rejected() {
+        let mut keys = HashMap::new();
+        keys.insert(String::from("v1"), secret_key("secret-1", None));
+        let token = super::encode_jwt(&claims(), "v1", &keys).unwrap();
+
+        keys.remove("v1");
+        keys.insert(String::from("v2"), secret_key("secret-1", None));
+        assert!(super::decode_jwt::<Claims>(&token, "v2", &keys, &Validation::default()).is_err());
+    }
+
+    fn jwt_config(issuer: &str, audience: &str) -> Jwt {
+        Jwt {
+            issuer: issuer.to_owned(),
+            audience: audience.to_owned(),
+            audiences: vec![audience.to_owned()],
+            ..crate::test_support::jwt_config()
+        }
+    }
+
+    fn access_claims(config: &Jwt) -> super::AccessClaims {
+        super::AccessClaims {
+            exp: chrono::Utc::now().timestamp() + 60,
+            iat: chrono::Utc::now().timestamp(),
+            iss: config.issuer.clone(),
+            aud: config.audiences.clone(),
+            user_id: 2,
+            sid: uuid::Uuid::new_v4(),
+            roles: vec![String::from("admin")],
+            scope: String::from("roles:admin users:admin"),
+            act: None,
+            client_id: None,
+        }
+    }
+
+    #[test]
+    fn token_of_other_issuer_is_rejected() {
+        let staging = jwt_config("user-service-staging", "api");
+        let production = jwt_config("user-service", "api");
+        let token = super::encode_access_jwt(&access_claims(&staging), &staging).unwrap();
+        assert!(matches!(
+            super::decode_access_jwt(&token, &staging),
+            Ok(super::AccessToken::User(_))
+        ));
+        assert!(super::decode_access_jwt(&token, &production).is_err());
+    }
+
+    #[test]
+    fn token_of_other_audience_is_rejected() {
+        let api = jwt_config("user-service", "api");
+        let admin = jwt_config("user-service", "admin");
+        let token = super::encode_access_jwt(&access_claims(&api), &api).unwrap();
+        assert!(matches!(
+            super::decode_access_jwt(&token, &api),
+            Ok(super::AccessToken::User(_))
+        ));
+        assert!(super::decode_access_jwt(&token, &admin).is_err());
+    }
+
+    #[test]
+    fn require_permission() {
+        use actix_web::{dev::Payload, test::TestRequest, FromRequest};
+        let config = jwt_config("user-service", "api");
+        let extract = |claims: super::AccessClaims| {
+            let req = TestRequest::default().to_http_request();
+            req.extensions_mut()
+                .insert(super::AccessToken::User(claims));
+            futures::executor::block_on(
+                super::RequirePermission::<super::RolesAdmin>::from_request(
+                    &req,
+                    &mut Payload::None,
+                ),
+            )
+        };
+
+        let admin = extract(access_claims(&config));
+        assert_eq!(2, admin.unwrap().claims.user_id);
+        let mut claims = access_claims(&config);
+        claims.scope = String::from("users:admin");
+        assert!(extract(claims).is_err());
+    }
+
+    #[test]
+    fn impersonation_is_denied() {
+        use actix_web::{dev::Payload, test::TestRequest, FromRequest};
+        let config = jwt_config("user-service", "api");
+        let extract = |claims: super::AccessClaims| {
+            let req = TestRequest::default().to_http_request();
+            req.extensions_mut()
+                .insert(super::AccessToken::User(claims));
+            futures::executor::block_on(super::NotImpersonated::from_request(
+                &req,
+                &mut Payload::None,
+            ))
+        };
+
+        assert!(extract(access_claims(&config)).is_ok());
+        let mut claims = access_claims(&config);
+        claims.act = Some(super::Actor { user_id: 1 });
+        let token = super::encode_access_jwt(&claims, &config).unwrap();
+        match super::decode_access_jwt(&token, &config) {
+            Ok(super::AccessToken::User(claims)) => {
+                assert_eq!(Some(super::Actor { user_id: 1 }), claims.act);
+                assert!(matches!(
+                    extract(claims),
+                    Err(crate::error::ApiError::ImpersonationDenied)
+                ));
+            }
+            other => panic!("Expected user claims, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn oauth_client_token_is_scoped() {
+        use actix_web::{dev::Payload, test::TestRequest, FromRequest, HttpRequest};
+        let config = jwt_config("user-service", "api");
+        let request = |claims: super::AccessClaims| {
+            let req = TestRequest::default().to_http_request();
+            req.extensions_mut()
+                .insert(super::AccessToken::User(claims));
+            req
+        };
+        let extract = |req: &HttpRequest| {
+            use futures::executor::block_on;
+            (
+                block_on(super::AccessClaims::from_request(req, &mut Payload::None)).is_ok(),
+                block_on(super::NotImpersonated::from_request(
+                    req,
+                    &mut Payload::None,
+                ))
+                .is_ok(),
+                block_on(super::RequirePermission::<super::RolesAdmin>::from_request(
+                    req,
+                    &mut Payload::None,
+                ))
+                .is_ok(),
+                block_on(super::ScopedAccessClaims::from_request(
+                    req,
+                    &mut Payload::None,
+                ))
+                .is_ok(),
+            )
+        };
+
+        assert_eq!(
+            (true, true, true, true),
+            extract(&request(access_claims(&config)))
+        );
+        let mut claims = access_claims(&config);
+        claims.client_id = Some(String::from("shop"));
+        assert_eq!((false, false, false, true), extract(&request(claims)));
+    }
+
+    #[test]
+    fn client_token_is_told_apart() {
+        let config = jwt_config("user-service", "api");
+        let claims = super::ClientClaims {
+            exp: chrono::Utc::now().timestamp() + 60,
+            iat: chrono::Utc::now().timestamp(),
+            iss: config.issuer.clone(),
+            aud: config.audiences.clone(),
+            client_id: String::from("billing-job"),
+        };
+        let token = super::encode_access_jwt(&claims, &config).unwrap();
+        match super::decode_access_jwt(&token, &config) {
+            Ok(super::AccessToken::Client(claims)) => assert_eq!("billing-job", claims.client_id),
+            other => panic!("Expected client claims, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_basic_credentials() {
+        let mut headers = actix_web::http::HeaderMap::new();
+        let credentials = super::STANDARD.encode("my%20client:p%3Ass+w%26rd");
+        headers.insert(
+            actix_web::http::header::AUTHORIZATION,
+            actix_web::http::HeaderValue::from_str(&format!("Basic {}", credentials)).unwrap(),
+        );
+        assert_eq!(
+            Some((String::from("my client"), String::from("p:ss w&rd"))),
+            super::get_basic_credentials(&headers)
+        );
+    }
+
+    #[test]
+    fn session_token_without_generation() {
+        let config = jwt_config("user-service", "user-service");
+        let claims = serde_json::json!({
+            "exp": chrono::Utc::now().timestamp() + 60,
+            "iat": chrono::Utc::now().timestamp(),
+            "iss": "user-service",
+            "aud": ["user-service"],
+            "session_id": uuid::Uuid::new_v4(),
+            "user_id": 2,
+        });
+        let token = super::encode_jwt(&claims, "v1", &config.session_keys).unwrap();
+        let claims = super::decode_session_jwt(&token, &config).unwrap();
+        assert_eq!(0, claims.generation);
+    }
+}
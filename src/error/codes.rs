@@ -7,14 +7,25 @@ impl ErrorCode {
     pub const MISSING_FIELDS: ErrorCode = ErrorCode(4001, StatusCode::BAD_REQUEST);
     pub const JSON_VALIDATION_FAILED: ErrorCode = ErrorCode(4002, StatusCode::BAD_REQUEST);
 
+    pub const NOT_FOUND: ErrorCode = ErrorCode(4040, StatusCode::NOT_FOUND);
+
     pub const ENTITY_ALREADY_EXISTS: ErrorCode = ErrorCode(4900, StatusCode::CONFLICT);
 
     pub const MISSING_ACCESS_TOKEN_HEADER: ErrorCode = ErrorCode(4002, StatusCode::UNAUTHORIZED);
     pub const MISSION_SESSION_COOKIE: ErrorCode = ErrorCode(4003, StatusCode::UNAUTHORIZED);
     pub const JWT_VALIDATION_ERROR: ErrorCode = ErrorCode(4010, StatusCode::UNAUTHORIZED);
     pub const NOT_AUTHORIZED_FOR_ACTION: ErrorCode = ErrorCode(4011, StatusCode::UNAUTHORIZED);
+    pub const INVALID_CLIENT_CREDENTIALS: ErrorCode = ErrorCode(4012, StatusCode::UNAUTHORIZED);
     pub const PASSWORD_INVALID: ErrorCode = ErrorCode(4020, StatusCode::UNAUTHORIZED);
+    pub const TOTP_CODE_INVALID: ErrorCode = ErrorCode(4021, StatusCode::UNAUTHORIZED);
+    pub const WEBAUTHN_VERIFICATION_FAILED: ErrorCode = ErrorCode(4022, StatusCode::UNAUTHORIZED);
+    pub const ACCOUNT_LOCKED: ErrorCode = ErrorCode(4023, StatusCode::UNAUTHORIZED);
     pub const SESSION_TOKEN_BLACKLISTED: ErrorCode = ErrorCode(4030, StatusCode::UNAUTHORIZED);
+    pub const SESSION_EXPIRED: ErrorCode = ErrorCode(4031, StatusCode::UNAUTHORIZED);
+    pub const SESSION_LIMIT_REACHED: ErrorCode = ErrorCode(4032, StatusCode::FORBIDDEN);
+    pub const IMPERSONATION_DENIED: ErrorCode = ErrorCode(4033, StatusCode::FORBIDDEN);
+
+    pub const TOO_MANY_REQUESTS: ErrorCode = ErrorCode(4290, StatusCode::TOO_MANY_REQUESTS);
 
     pub const INTERNAL_SERVER_ERROR: ErrorCode = ErrorCode(5000, StatusCode::INTERNAL_SERVER_ERROR);
     pub const JWT_GENERATION_ERROR: ErrorCode = ErrorCode(5000, StatusCode::INTERNAL_SERVER_ERROR);
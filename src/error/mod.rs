@@ -6,9 +6,15 @@ use std::fmt::Debug;
 
 use crate::auth::AuthorizationError;
 use crate::error::codes::ErrorCode;
-use crate::error::responses::{DefaultErrorResponse, FieldErrorResponse};
+use crate::error::responses::{DefaultErrorResponse, FieldErrorResponse, OAuthErrorResponse};
+use crate::jwk::JwkError;
+use crate::service::admin_service::AdminServiceError;
+use crate::service::mfa_service::MfaServiceError;
+use crate::service::oauth_service::OAuthServiceError;
+use crate::service::role_service::RoleServiceError;
 use crate::service::session_service::SessionServiceError;
 use crate::service::user_service::UserServiceError;
+use crate::service::webauthn_service::WebAuthnServiceError;
 use actix_web::error::BlockingError;
 use actix_web::{HttpResponse, ResponseError};
 use serde::Serialize;
@@ -27,10 +33,20 @@ pub enum ApiError {
     JwtValidationError(jsonwebtoken::errors::Error),
     JwtGenerationError,
     EntityAlreadyExists,
+    NotFound,
     AuthorizationError,
     PasswordInvalid,
+    TotpCodeInvalid,
+    WebAuthnVerificationFailed,
+    AccountLocked(chrono::DateTime<chrono::Utc>),
+    TooManyRequests(std::time::Duration), // Until the next request is allowed
     SessionTokenBlacklisted,
+    SessionExpired,
+    SessionLimitReached,
+    ImpersonationDenied,
     MissingSessionCookie,
+    InvalidClientCredentials,
+    OAuthError(&'static str, String),
 }
 
 impl fmt::Display for ApiError {
@@ -101,6 +117,11 @@ impl From<&ApiError> for HttpResponse {
                 );
                 HttpResponse::build(resp.status_code).json(resp)
             }
+            ApiError::NotFound => {
+                let resp =
+                    DefaultErrorResponse::new(ErrorCode::NOT_FOUND, String::from("Not found"));
+                HttpResponse::build(resp.status_code).json(resp)
+            }
             ApiError::AuthorizationError => {
                 let resp = DefaultErrorResponse::new(
                     ErrorCode::NOT_AUTHORIZED_FOR_ACTION,
@@ -122,6 +143,53 @@ impl From<&ApiError> for HttpResponse {
                 );
                 HttpResponse::build(resp.status_code).json(resp)
             }
+            ApiError::TotpCodeInvalid => {
+                let resp = DefaultErrorResponse::new(
+                    ErrorCode::TOTP_CODE_INVALID,
+                    String::from("Invalid TOTP or recovery code"),
+                );
+                HttpResponse::build(resp.status_code).json(resp)
+            }
+            ApiError::AccountLocked(locked_until) => {
+                let resp = DefaultErrorResponse::new(
+                    ErrorCode::ACCOUNT_LOCKED,
+                    format!("Account locked until {}", locked_until.to_rfc3339()),
+                );
+                HttpResponse::build(resp.status_code).json(resp)
+            }
+            ApiError::TooManyRequests(retry_after) => {
+                let resp = DefaultErrorResponse::new(
+                    ErrorCode::TOO_MANY_REQUESTS,
+                    String::from("Too many requests"),
+                );
+                // Whole seconds, rounded up so clients don't retry too early
+                let retry_after_s = (retry_after.as_millis() as u64).div_ceil(1000);
+                HttpResponse::build(resp.status_code)
+                    .header("Retry-After", retry_after_s.max(1).to_string())
+                    .json(resp)
+            }
+            ApiError::WebAuthnVerificationFailed => {
+                let resp = DefaultErrorResponse::new(
+                    ErrorCode::WEBAUTHN_VERIFICATION_FAILED,
+                    String::from("WebAuthn Verification Failed"),
+                );
+                HttpResponse::build(resp.status_code).json(resp)
+            }
+            ApiError::InvalidClientCredentials => {
+                let resp = DefaultErrorResponse::new(
+                    ErrorCode::INVALID_CLIENT_CREDENTIALS,
+                    String::from("Invalid Client Credentials"),
+                );
+                HttpResponse::build(resp.status_code)
+                    .header("WWW-Authenticate", "Basic")
+                    .json(resp)
+            }
+            ApiError::OAuthError(error, description) => {
+                let resp = OAuthErrorResponse::new(error, description.clone());
+                HttpResponse::build(resp.status_code)
+                    .header("Cache-Control", "no-store")
+                    .json(resp)
+            }
             ApiError::SessionTokenBlacklisted => {
                 let resp = DefaultErrorResponse::new(
                     ErrorCode::SESSION_TOKEN_BLACKLISTED,
@@ -129,6 +197,27 @@ impl From<&ApiError> for HttpResponse {
                 );
                 HttpResponse::build(resp.status_code).json(resp)
             }
+            ApiError::SessionExpired => {
+                let resp = DefaultErrorResponse::new(
+                    ErrorCode::SESSION_EXPIRED,
+                    String::from("Session expired, log in again"),
+                );
+                HttpResponse::build(resp.status_code).json(resp)
+            }
+            ApiError::SessionLimitReached => {
+                let resp = DefaultErrorResponse::new(
+                    ErrorCode::SESSION_LIMIT_REACHED,
+                    String::from("Too many active sessions, log out of another device first"),
+                );
+                HttpResponse::build(resp.status_code).json(resp)
+            }
+            ApiError::ImpersonationDenied => {
+                let resp = DefaultErrorResponse::new(
+                    ErrorCode::IMPERSONATION_DENIED,
+                    String::from("Not allowed while impersonating the user"),
+                );
+                HttpResponse::build(resp.status_code).json(resp)
+            }
         }
     }
 }
@@ -167,18 +256,130 @@ impl From<SessionServiceError> for ApiError {
     }
 }
 
+impl From<OAuthServiceError> for ApiError {
+    fn from(error: OAuthServiceError) -> Self {
+        match error {
+            OAuthServiceError::GenericDatabaseError(e) => e.into(),
+            OAuthServiceError::InvalidClientCredentials => ApiError::InvalidClientCredentials,
+            OAuthServiceError::InvalidClient => ApiError::OAuthError(
+                "invalid_client",
+                String::from("Client authentication failed"),
+            ),
+            OAuthServiceError::InvalidRedirectUri => ApiError::OAuthError(
+                "invalid_request",
+                String::from("redirect_uri is not registered for the client"),
+            ),
+            OAuthServiceError::InvalidRequest(description) => {
+                ApiError::OAuthError("invalid_request", String::from(description))
+            }
+            OAuthServiceError::InvalidGrant => ApiError::OAuthError(
+                "invalid_grant",
+                String::from("Grant is invalid, expired or revoked"),
+            ),
+            OAuthServiceError::InvalidToken | OAuthServiceError::LoginRequired => {
+                ApiError::AuthorizationError
+            }
+            OAuthServiceError::UnauthorizedClient => ApiError::OAuthError(
+                "unauthorized_client",
+                String::from("Client is not allowed to use this grant type"),
+            ),
+            OAuthServiceError::UnsupportedGrantType => ApiError::OAuthError(
+                "unsupported_grant_type",
+                String::from("Grant type is not supported"),
+            ),
+            OAuthServiceError::SessionServiceError(e) => e.into(),
+            OAuthServiceError::HashingError => ApiError::InternalServerError,
+        }
+    }
+}
+
+impl From<MfaServiceError> for ApiError {
+    fn from(error: MfaServiceError) -> Self {
+        match error {
+            MfaServiceError::GenericDatabaseError(e) => e.into(),
+            MfaServiceError::AuthorizationError(e) => e.into(),
+            MfaServiceError::AlreadyEnrolled => ApiError::EntityAlreadyExists,
+            MfaServiceError::NotEnrolled => ApiError::AuthorizationError,
+            MfaServiceError::CodeInvalid => ApiError::TotpCodeInvalid,
+            MfaServiceError::TotpError(_) => ApiError::InternalServerError,
+            MfaServiceError::SessionServiceError(e) => e.into(),
+            MfaServiceError::UserServiceError(e) => e.into(),
+        }
+    }
+}
+
+impl From<WebAuthnServiceError> for ApiError {
+    fn from(error: WebAuthnServiceError) -> Self {
+        match error {
+            WebAuthnServiceError::GenericDatabaseError(e) => e.into(),
+            WebAuthnServiceError::AuthorizationError(e) => e.into(),
+            WebAuthnServiceError::MissingField(field_name) => {
+                ApiError::MissingFields(vec![Field {
+                    field_name: String::from(field_name),
+                }])
+            }
+            WebAuthnServiceError::VerificationFailed(reason) => {
+                debug!("WebAuthn verification failed: {}", reason);
+                ApiError::WebAuthnVerificationFailed
+            }
+            WebAuthnServiceError::CredentialAlreadyRegistered => ApiError::EntityAlreadyExists,
+            WebAuthnServiceError::SessionServiceError(e) => e.into(),
+            WebAuthnServiceError::MfaServiceError(e) => e.into(),
+        }
+    }
+}
+
+impl From<RoleServiceError> for ApiError {
+    fn from(error: RoleServiceError) -> Self {
+        match error {
+            RoleServiceError::GenericDatabaseError(e) => e.into(),
+            RoleServiceError::RoleDoesNotExist => ApiError::NotFound,
+            RoleServiceError::UserDoesNotExist => ApiError::NotFound,
+        }
+    }
+}
+
+impl From<AdminServiceError> for ApiError {
+    fn from(error: AdminServiceError) -> Self {
+        match error {
+            AdminServiceError::GenericDatabaseError(e) => e.into(),
+            AdminServiceError::UserDoesNotExist => ApiError::NotFound,
+            AdminServiceError::UserNotActive => ApiError::AuthorizationError,
+            AdminServiceError::JwtGenerationError => ApiError::JwtGenerationError,
+            AdminServiceError::InvalidStatusTransition => {
+                ApiError::JsonValidationFailed(vec![Field {
+                    field_name: String::from("status"),
+                }])
+            }
+        }
+    }
+}
+
 impl From<AuthorizationError> for ApiError {
     fn from(error: AuthorizationError) -> Self {
         match error {
             AuthorizationError::PasswordInvalid => ApiError::PasswordInvalid,
+            AuthorizationError::AccountLocked(locked_until) => {
+                ApiError::AccountLocked(locked_until)
+            }
             AuthorizationError::NoAuthorizationForAction => ApiError::AuthorizationError,
             AuthorizationError::UserDoesNotExist => ApiError::AuthorizationError,
             AuthorizationError::JwtValidationError(e) => ApiError::JwtValidationError(e),
             AuthorizationError::SessionTokenBlacklisted => ApiError::SessionTokenBlacklisted,
+            AuthorizationError::SessionExpired => ApiError::SessionExpired,
+            AuthorizationError::SessionLimitReached => ApiError::SessionLimitReached,
+            AuthorizationError::MfaTokenSpent => ApiError::AuthorizationError,
         }
     }
 }
 
+impl From<JwkError> for ApiError {
+    fn from(error: JwkError) -> Self {
+        error!("{}", error);
+        ApiError::InternalServerError
+    }
+}
+
 impl From<diesel::result::Error> for ApiError {
     fn from(error: diesel::result::Error) -> Self {
         match error {
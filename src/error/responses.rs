@@ -39,3 +39,26 @@ impl FieldErrorResponse {
         }
     }
 }
+
+/// Error response of the OAuth endpoints (RFC 6749 section 5.2)
+#[derive(Clone, Debug, Serialize)]
+pub struct OAuthErrorResponse {
+    #[serde(skip_serializing)]
+    pub status_code: StatusCode,
+    pub error: String,
+    pub error_description: String,
+}
+
+impl OAuthErrorResponse {
+    pub fn new(error: &str, error_description: String) -> Self {
+        Self {
+            // invalid_client is the only error answered with 401
+            status_code: match error {
+                "invalid_client" => StatusCode::UNAUTHORIZED,
+                _ => StatusCode::BAD_REQUEST,
+            },
+            error: error.to_owned(),
+            error_description,
+        }
+    }
+}
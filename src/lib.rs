@@ -13,11 +13,19 @@ mod auth;
 mod configuration;
 mod db;
 mod error;
+mod jwk;
 mod middleware;
 mod model;
+mod rate_limit;
 mod repository;
+mod revocation;
 mod schema;
 mod service;
+mod session_reaper;
+#[cfg(test)]
+mod test_support;
+mod totp;
+mod webauthn;
 
 #[actix_web::main]
 pub async fn run() -> std::io::Result<()> {
@@ -38,6 +46,35 @@ pub async fn run() -> std::io::Result<()> {
         .parse_filters(&config.logging.filters)
         .init();
 
+    if let Err(e) = auth::verify_keys(&config.jwt) {
+        error!("Invalid token signing key: {}", e);
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+    }
+    if let Err(e) = auth::access_jwk_set(&config.jwt) {
+        error!("Invalid access token public key: {}", e);
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+    }
+    if let Err(e) = totp::verify_encryption_key(&config.mfa.totp_encryption_key) {
+        error!("Invalid TOTP encryption key: {}", e);
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+    }
+    if config.session_reaper.interval_ms == 0 {
+        error!("Invalid session reaper interval: must be at least 1 ms");
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+    }
+    if config.jwt.revocation_refresh_ms == 0 {
+        error!("Invalid revocation refresh interval: must be at least 1 ms");
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+    }
+
+    let rate_limiter = match rate_limit::RateLimiter::new(&config.rate_limit) {
+        Ok(rate_limiter) => rate_limiter,
+        Err(e) => {
+            error!("Invalid rate limit rule: {}", e);
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+        }
+    };
+
     let manager = ConnectionManager::<PgConnection>::new(&config.database.url);
     let pool = Pool::builder()
         .build(manager)
@@ -45,27 +82,26 @@ pub async fn run() -> std::io::Result<()> {
     // test if db conn works
     pool.get().unwrap();
 
+    let revoked_sessions = revocation::RevokedSessions::default();
+    revocation::spawn_refresh(
+        pool.clone(),
+        revoked_sessions.clone(),
+        config.jwt.revocation_refresh_ms,
+        config.jwt.access_exp_ms,
+    );
+    session_reaper::spawn(
+        pool.clone(),
+        config.session_reaper.clone(),
+        config.jwt.clone(),
+    );
+
     let argon2_config = web::Data::new(argon2::Config::default());
     let port = config.app.port;
     let shared_config = web::Data::new(config.clone());
 
     info!("Initial setup took {} ms", start.elapsed().as_millis());
     HttpServer::new(move || {
-        let mut exempt_path = std::collections::HashMap::new();
-        exempt_path.insert(
-            String::from("/api/v1/users"),
-            vec![actix_web::http::Method::POST],
-        );
-        exempt_path.insert(
-            String::from("/api/v1/sessions"),
-            vec![actix_web::http::Method::POST],
-        );
-        exempt_path.insert(
-            String::from("/api/v1/sessions/access"),
-            vec![actix_web::http::Method::POST],
-        );
-
-        let exempt_path = std::rc::Rc::new(exempt_path);
+        let exempt_path = std::rc::Rc::new(exempt_paths());
         App::new()
             .data(pool.clone())
             .app_data(shared_config.clone())
@@ -87,12 +123,21 @@ pub async fn run() -> std::io::Result<()> {
             .wrap(middleware::jwt::JwtAuth::new(
                 config.jwt.clone(),
                 exempt_path.clone(),
+                revoked_sessions.clone(),
             ))
+            .wrap(middleware::rate_limit::RateLimit::new(rate_limiter.clone()))
             .wrap(actix_web::middleware::Logger::default())
+            .configure(api::well_known::init_routes)
             .service(
                 web::scope("/api/v1")
                     .configure(api::users::init_routes)
-                    .configure(api::session::init_routes),
+                    .configure(api::session::init_routes)
+                    .configure(api::mfa::init_routes)
+                    .configure(api::login_events::init_routes)
+                    .configure(api::webauthn::init_routes)
+                    .configure(api::oauth::init_routes)
+                    .configure(api::roles::init_routes)
+                    .configure(api::admin::init_routes),
             )
     })
     .bind(format!("127.0.0.1:{}", port))?
@@ -100,10 +145,130 @@ pub async fn run() -> std::io::Result<()> {
     .await
 }
 
+/// Paths and methods that need no access token
+fn exempt_paths() -> std::collections::HashMap<String, Vec<actix_web::http::Method>> {
+    let mut exempt_path = std::collections::HashMap::new();
+    exempt_path.insert(
+        String::from("/api/v1/users"),
+        vec![actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/api/v1/sessions"),
+        vec![actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/api/v1/sessions/access"),
+        vec![actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/api/v1/sessions/logout"),
+        vec![actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/api/v1/sessions/mfa"),
+        vec![actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/api/v1/sessions/webauthn/options"),
+        vec![actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/api/v1/sessions/webauthn"),
+        vec![actix_web::http::Method::POST],
+    );
+    // Authorization reads the session cookie, it is reached by redirecting the browser there
+    exempt_path.insert(
+        String::from("/api/v1/oauth/authorize"),
+        vec![actix_web::http::Method::GET, actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/api/v1/oauth/token"),
+        vec![actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/api/v1/oauth/introspect"),
+        vec![actix_web::http::Method::POST],
+    );
+    exempt_path.insert(
+        String::from("/.well-known/jwks.json"),
+        vec![actix_web::http::Method::GET],
+    );
+    exempt_path.insert(
+        String::from("/.well-known/openid-configuration"),
+        vec![actix_web::http::Method::GET],
+    );
+    exempt_path
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::api::session::{build_session_cookie, get_session_token};
+    use crate::configuration::Jwt;
+    use crate::error::ApiError;
+    use crate::middleware::jwt::JwtAuth;
+    use crate::revocation::RevokedSessions;
+    use crate::test_support::jwt_config;
+    use actix_service::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::{rt, test, web, App, HttpRequest, HttpResponse};
+    use std::rc::Rc;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    async fn echo_session_token(
+        req: HttpRequest,
+        jwt_config: web::Data<Jwt>,
+    ) -> Result<HttpResponse, ApiError> {
+        Ok(HttpResponse::Ok().body(get_session_token(&req, &jwt_config)?))
+    }
+
+    #[test]
+    fn oauth_authorize_is_reached_with_session_cookie() {
+        rt::System::new("test").block_on(async {
+            let config = jwt_config();
+            let expiration = chrono::Utc::now() + chrono::Duration::hours(1);
+            let cookie =
+                build_session_cookie(config.clone(), String::from("session-token"), &expiration);
+            assert!("/api/v1/oauth/authorize".starts_with(cookie.path().unwrap()));
+
+            let mut app = test::init_service(
+                App::new()
+                    .data(config.clone())
+                    .wrap(JwtAuth::new(
+                        config.clone(),
+                        Rc::new(super::exempt_paths()),
+                        RevokedSessions::default(),
+                    ))
+                    .route("/api/v1/oauth/authorize", web::get().to(echo_session_token))
+                    .route(
+                        "/api/v1/oauth/authorize",
+                        web::post().to(echo_session_token),
+                    ),
+            )
+            .await;
+            for request in [test::TestRequest::get(), test::TestRequest::post()] {
+                let req = request
+                    .uri("/api/v1/oauth/authorize")
+                    .cookie(cookie.clone())
+                    .to_request();
+                let res = app.call(req).await.unwrap();
+                assert_eq!(StatusCode::OK, res.status());
+                assert_eq!("session-token", test::read_body(res).await);
+            }
+
+            // Without the cookie the handler, not the middleware, rejects the request
+            let req = test::TestRequest::get()
+                .uri("/api/v1/oauth/authorize")
+                .to_request();
+            let res = app.call(req).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+            let body = test::read_body(res).await;
+            assert!(std::str::from_utf8(&body)
+                .unwrap()
+                .contains(r#""code":4003"#));
+        });
+    }
 }
@@ -4,6 +4,7 @@ use std::task::{Context, Poll};
 use crate::auth;
 use crate::configuration;
 use crate::error::ApiError;
+use crate::revocation::RevokedSessions;
 use actix_service::{Service, Transform};
 use actix_web::http::Method;
 use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, HttpMessage};
@@ -19,16 +20,19 @@ use std::rc::Rc;
 pub struct JwtAuth {
     jwt_config: configuration::Jwt,
     exempt_path: Rc<HashMap<String, Vec<Method>>>,
+    revoked_sessions: RevokedSessions,
 }
 
 impl JwtAuth {
     pub fn new(
         jwt_config: configuration::Jwt,
         exempt_path: Rc<HashMap<String, Vec<Method>>>,
+        revoked_sessions: RevokedSessions,
     ) -> Self {
         Self {
             jwt_config,
             exempt_path,
+            revoked_sessions,
         }
     }
 }
@@ -54,6 +58,7 @@ where
             service: service,
             jwt_config: self.jwt_config.clone(),
             exempt_path: self.exempt_path.clone(),
+            revoked_sessions: self.revoked_sessions.clone(),
         })
     }
 }
@@ -62,6 +67,7 @@ pub struct JwtAuthMiddleware<S> {
     service: S,
     jwt_config: configuration::Jwt,
     exempt_path: Rc<HashMap<String, Vec<Method>>>,
+    revoked_sessions: RevokedSessions,
 }
 
 impl<S, B> Service for JwtAuthMiddleware<S>
@@ -109,6 +115,16 @@ where
                 }
             };
 
+            if let auth::AccessToken::User(user_claims) = &claims {
+                if self.revoked_sessions.contains(&user_claims.sid) {
+                    debug!(
+                        "Access token of revoked session {} rejected",
+                        user_claims.sid
+                    );
+                    return Box::pin(async { Err(ApiError::SessionTokenBlacklisted.into()) });
+                }
+            }
+
             req.extensions_mut().insert(claims);
         }
 
@@ -120,3 +136,76 @@ where
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::JwtAuth;
+    use crate::auth::{self, AccessClaims};
+    use crate::error::ApiError;
+    use crate::revocation::RevokedSessions;
+    use crate::test_support::jwt_config;
+    use actix_service::Service;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::StatusCode;
+    use actix_web::{rt, test, web, App, Error, HttpResponse, ResponseError};
+    use std::collections::HashMap;
+    use std::rc::Rc;
+    use uuid::Uuid;
+
+    fn access_token(sid: Uuid) -> String {
+        let config = jwt_config();
+        let claims = AccessClaims {
+            exp: chrono::Utc::now().timestamp() + 60,
+            iat: chrono::Utc::now().timestamp(),
+            iss: config.issuer.clone(),
+            aud: config.audiences.clone(),
+            user_id: 2,
+            sid,
+            roles: vec![],
+            scope: String::new(),
+            act: None,
+            client_id: None,
+        };
+        auth::encode_access_jwt(&claims, &config).unwrap()
+    }
+
+    fn status(result: Result<ServiceResponse, Error>) -> StatusCode {
+        match result {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().error_response().status(),
+        }
+    }
+
+    #[test]
+    fn rejects_access_token_of_revoked_session() {
+        rt::System::new("test").block_on(async {
+            let revoked_sessions = RevokedSessions::default();
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(JwtAuth::new(
+                        jwt_config(),
+                        Rc::new(HashMap::new()),
+                        revoked_sessions.clone(),
+                    ))
+                    .route("/api/v1/users/me", web::get().to(HttpResponse::Ok)),
+            )
+            .await;
+            let request = |sid| {
+                test::TestRequest::get()
+                    .uri("/api/v1/users/me")
+                    .header("authorization", format!("Bearer {}", access_token(sid)))
+                    .to_request()
+            };
+            let revoked = Uuid::new_v4();
+            let active = Uuid::new_v4();
+            assert_eq!(StatusCode::OK, status(app.call(request(revoked)).await));
+
+            revoked_sessions.replace(vec![revoked].into_iter().collect());
+            assert_eq!(
+                ApiError::SessionTokenBlacklisted.error_response().status(),
+                status(app.call(request(revoked)).await)
+            );
+            assert_eq!(StatusCode::OK, status(app.call(request(active)).await));
+        });
+    }
+}
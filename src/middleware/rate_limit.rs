@@ -0,0 +1,254 @@
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::configuration::RateLimitKey;
+use crate::error::ApiError;
+use crate::rate_limit;
+use crate::rate_limit::RateLimiter;
+use actix_service::{Service, Transform};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage};
+use futures::future;
+use futures::{Future, StreamExt};
+use serde::Deserialize;
+use std::rc::Rc;
+use std::time::Instant;
+
+// Bodies of username limited routes are only inspected up to the JSON limit of the app
+const BODY_LIMIT: usize = 4096;
+
+#[derive(Deserialize)]
+struct UsernameBody {
+    username: String,
+}
+
+/// Rejects requests with 429 once a bucket of the route is empty, see rate_limit::RateLimiter
+pub struct RateLimit {
+    rate_limiter: RateLimiter,
+}
+
+impl RateLimit {
+    pub fn new(rate_limiter: RateLimiter) -> Self {
+        Self { rate_limiter }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(RateLimitMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            rate_limiter: self.rate_limiter.clone(),
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    // Shared with the response future, which calls it after the body was read
+    service: Rc<RefCell<S>>,
+    rate_limiter: RateLimiter,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let rules = self.rate_limiter.rules_for(req.path(), req.method());
+        if rules.is_empty() {
+            return Box::pin(self.service.borrow_mut().call(req));
+        }
+
+        let service = self.service.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let client_ip = rate_limit::client_ip(
+            req.peer_addr(),
+            req.headers(),
+            rate_limiter.trusted_proxies(),
+        )
+        .unwrap_or_else(|| String::from("unknown"));
+        Box::pin(async move {
+            let username = if rules.iter().any(|(_, key)| *key == RateLimitKey::Username) {
+                read_username(&mut req).await
+            } else {
+                None
+            };
+
+            let keys: Vec<(usize, String)> = rules
+                .into_iter()
+                .filter_map(|(index, key)| match key {
+                    RateLimitKey::Ip => Some((index, rate_limit::ip_key(&client_ip))),
+                    RateLimitKey::Username => username.clone().map(|username| (index, username)),
+                })
+                .collect();
+            if let Err(retry_after) = rate_limiter.acquire(&keys, Instant::now()) {
+                debug!("Rate limit exceeded for {} by {}", req.path(), client_ip);
+                return Err(ApiError::TooManyRequests(retry_after).into());
+            }
+
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
+
+/// Reads the username of a JSON body and puts the body back for the handler. Usernames are
+/// compared in upper case like in the users table.
+async fn read_username(req: &mut ServiceRequest) -> Option<String> {
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    let mut chunks: Vec<Result<Bytes, PayloadError>> = vec![];
+    while body.len() <= BODY_LIMIT {
+        match payload.next().await {
+            Some(Ok(chunk)) => {
+                body.extend_from_slice(&chunk);
+                chunks.push(Ok(chunk));
+            }
+            Some(Err(e)) => {
+                chunks.push(Err(e));
+                break;
+            }
+            None => break,
+        }
+    }
+
+    let username = serde_json::from_slice::<UsernameBody>(&body)
+        .ok()
+        .map(|body| body.username.to_uppercase());
+    // The handler gets what was read followed by anything left over
+    req.set_payload(Payload::Stream(Box::pin(
+        futures::stream::iter(chunks).chain(payload),
+    )));
+    username
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimit;
+    use crate::configuration::{self, RateLimitKey, RateLimitRule};
+    use crate::rate_limit::RateLimiter;
+    use actix_service::Service;
+    use actix_web::dev::ServiceResponse;
+    use actix_web::http::StatusCode;
+    use actix_web::{rt, test, web, App, Error, HttpResponse};
+
+    fn rate_limit(key: RateLimitKey, trusted_proxies: usize) -> RateLimit {
+        let rule = RateLimitRule {
+            path: String::from("/api/v1/sessions"),
+            method: String::from("POST"),
+            key,
+            capacity: 1,
+            refill_ms: 60_000,
+        };
+        RateLimit::new(
+            RateLimiter::new(&configuration::RateLimit {
+                trusted_proxies,
+                rules: vec![rule],
+            })
+            .unwrap(),
+        )
+    }
+
+    fn login(peer: &str, forwarded_for: &str, body: &str) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/api/v1/sessions")
+            .peer_addr(peer.parse().unwrap())
+            .header("x-forwarded-for", forwarded_for)
+            .set_payload(String::from(body))
+    }
+
+    /// Status and body of the response, the handler echoes the body it got
+    async fn response(result: Result<ServiceResponse, Error>) -> (StatusCode, String) {
+        match result {
+            Ok(res) => {
+                let status = res.status();
+                let body = test::read_body(res).await;
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+            Err(e) => (
+                e.as_response_error().error_response().status(),
+                String::new(),
+            ),
+        }
+    }
+
+    #[test]
+    fn limits_username_and_restores_body() {
+        rt::System::new("test").block_on(async {
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(rate_limit(RateLimitKey::Username, 0))
+                    .route(
+                        "/api/v1/sessions",
+                        web::post().to(|body: web::Bytes| HttpResponse::Ok().body(body)),
+                    ),
+            )
+            .await;
+            let body = r#"{"username":"alice","password":"secret"}"#;
+            let req = login("10.0.0.1:50000", "", body).to_request();
+            assert_eq!(
+                (StatusCode::OK, String::from(body)),
+                response(app.call(req).await).await
+            );
+            // Usernames are compared in upper case, whatever the ip
+            let req = login("10.0.0.2:50000", "", r#"{"username":"Alice"}"#).to_request();
+            assert_eq!(
+                StatusCode::TOO_MANY_REQUESTS,
+                response(app.call(req).await).await.0
+            );
+            let req = login("10.0.0.1:50000", "", r#"{"username":"bob"}"#).to_request();
+            assert_eq!(StatusCode::OK, response(app.call(req).await).await.0);
+        });
+    }
+
+    #[test]
+    fn limits_ip_appended_by_trusted_proxy() {
+        rt::System::new("test").block_on(async {
+            let mut app = test::init_service(
+                App::new()
+                    .wrap(rate_limit(RateLimitKey::Ip, 1))
+                    .route("/api/v1/sessions", web::post().to(HttpResponse::Ok)),
+            )
+            .await;
+            let expected = [
+                ("1.1.1.1, 2.2.2.2", StatusCode::OK),
+                // Entries left of the one appended by the proxy are the client's choice
+                ("9.9.9.9, 2.2.2.2", StatusCode::TOO_MANY_REQUESTS),
+                ("1.1.1.1", StatusCode::OK),
+                // Addresses of an IPv6 /64 share a bucket
+                ("2001:db8::1", StatusCode::OK),
+                ("2001:db8::2", StatusCode::TOO_MANY_REQUESTS),
+                ("2001:db8:0:1::1", StatusCode::OK),
+            ];
+            for (forwarded_for, status) in expected.iter() {
+                let req = login("10.0.0.1:50000", forwarded_for, "{}").to_request();
+                assert_eq!(*status, response(app.call(req).await).await.0);
+            }
+        });
+    }
+}
@@ -0,0 +1,15 @@
+use crate::schema::impersonations;
+use chrono::Utc;
+use uuid::Uuid;
+
+/// Audit record of an admin starting to act as another user, the id is the `sid` of the token
+#[derive(Insertable, Debug)]
+#[table_name = "impersonations"]
+pub struct NewImpersonation {
+    pub id: Uuid,
+    pub admin_id: i64,
+    pub user_id: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub expires_at: chrono::DateTime<Utc>,
+}
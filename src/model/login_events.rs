@@ -0,0 +1,66 @@
+use crate::schema::login_events;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum LoginEventType {
+    Password = 1,
+    Refresh = 2,      // New access token with the session token
+    SecondFactor = 3, // TOTP or recovery code after the password
+    WebAuthn = 4,     // Passkey, as second factor or passwordless
+}
+
+#[derive(Queryable, Serialize, Deserialize, Clone, Debug)]
+pub struct LoginEvent {
+    pub id: i64,
+    pub user_id: Option<i64>, // None if the user doesn't exist
+    pub event_type: i32,
+    pub success: bool,
+    pub failure_reason: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub platform: Option<String>,
+    pub sub_platform: Option<String>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Insertable, Debug)]
+#[table_name = "login_events"]
+pub struct NewLoginEvent {
+    pub user_id: Option<i64>,
+    pub event_type: i32,
+    pub success: bool,
+    pub failure_reason: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub platform: Option<String>,
+    pub sub_platform: Option<String>,
+}
+
+/// Where a request came from, extracted from the request in api
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl NewLoginEvent {
+    /// A failed event of an unknown user, user and platform are filled in as the login proceeds
+    pub fn new(event_type: LoginEventType, client_info: &ClientInfo) -> Self {
+        NewLoginEvent {
+            user_id: None,
+            event_type: event_type as i32,
+            success: false,
+            failure_reason: None,
+            ip: client_info.ip.clone(),
+            user_agent: client_info.user_agent.clone(),
+            platform: None,
+            sub_platform: None,
+        }
+    }
+
+    pub fn set_platform(&mut self, platform: &str, sub_platform: &str) {
+        self.platform = Some(platform.to_owned());
+        self.sub_platform = Some(sub_platform.to_owned());
+    }
+}
@@ -1,2 +1,10 @@
+pub mod impersonations;
+pub mod login_events;
+pub mod oauth;
+pub mod pending_logins;
+pub mod recovery_codes;
+pub mod roles;
 pub mod sessions;
+pub mod totp;
 pub mod users;
+pub mod webauthn;
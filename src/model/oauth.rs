@@ -0,0 +1,184 @@
+use crate::schema::oauth_authorization_codes;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+pub const OAUTH_PLATFORM: &str = "oauth"; // Platform of sessions created by OAuth clients
+
+pub const GRANT_AUTHORIZATION_CODE: &str = "authorization_code";
+pub const GRANT_REFRESH_TOKEN: &str = "refresh_token";
+pub const GRANT_CLIENT_CREDENTIALS: &str = "client_credentials";
+
+pub const SCOPE_OPENID: &str = "openid";
+pub const SCOPE_PROFILE: &str = "profile";
+pub const SCOPE_EMAIL: &str = "email";
+pub const SUPPORTED_SCOPES: [&str; 3] = [SCOPE_OPENID, SCOPE_PROFILE, SCOPE_EMAIL];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClientStatus {
+    Active = 1,
+    Disabled = 2,
+}
+
+#[derive(Queryable, Serialize, Deserialize, Clone, Debug)]
+pub struct OAuthClient {
+    pub id: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub client_secret: Option<String>, // Argon2 hash, None for public clients (SPAs, native apps)
+    pub redirect_uris: Vec<String>,
+    pub status: i32,
+    pub created_at: chrono::DateTime<Utc>,
+    pub updated_at: chrono::DateTime<Utc>,
+    pub grant_types: Vec<String>, // Grants the client may use at the token endpoint
+}
+
+#[derive(Queryable, Serialize, Deserialize, Debug)]
+pub struct AuthorizationCode {
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: i64,
+    pub redirect_uri: String,
+    pub code_challenge: String,
+    pub expires_at: chrono::DateTime<Utc>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub scope: String, // Granted scopes, space separated
+    pub nonce: Option<String>,
+}
+
+#[derive(Insertable, Serialize, Deserialize, Debug)]
+#[table_name = "oauth_authorization_codes"]
+pub struct NewAuthorizationCode {
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: i64,
+    pub redirect_uri: String,
+    pub code_challenge: String,
+    pub expires_at: chrono::DateTime<Utc>,
+    pub scope: String,
+    pub nonce: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuthorizeRequestDto {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub state: Option<String>,
+    pub scope: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// What the user is asked to consent to. The consent token is posted back with the decision.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConsentDto {
+    pub client_id: String,
+    pub client_name: String,
+    pub scope: String,
+    pub consent_token: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ConsentDecisionDto {
+    pub consent_token: String,
+    pub approve: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TokenRequestDto {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// Successful token response (RFC 6749 section 5.1)
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OAuthTokenDto {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TokenTypeHint {
+    #[serde(rename = "access_token")]
+    AccessToken,
+    #[serde(rename = "refresh_token")]
+    RefreshToken,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IntrospectionRequestDto {
+    pub token: String,
+    pub token_type_hint: Option<TokenTypeHint>,
+}
+
+/// Introspection response (RFC 7662). Inactive tokens only carry `active: false`.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct IntrospectionDto {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<TokenTypeHint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Vec<String>>,
+}
+
+impl IntrospectionDto {
+    pub fn inactive() -> Self {
+        IntrospectionDto::default()
+    }
+}
+
+/// Standard claims about the user (OpenID Connect Core section 5.1), limited by the scopes
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserInfoDto {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>, // email scope
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>, // email scope
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub birthdate: Option<String>, // profile scope
+}
+
+/// Provider metadata (OpenID Connect Discovery section 3)
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OpenIdConfigurationDto {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub introspection_endpoint: String,
+    pub scopes_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
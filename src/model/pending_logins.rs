@@ -0,0 +1,12 @@
+use crate::schema::pending_logins;
+use chrono::Utc;
+use uuid::Uuid;
+
+/// A login whose password was checked, the id is the jti of the mfa token handed out for it
+#[derive(Insertable, Debug)]
+#[table_name = "pending_logins"]
+pub struct NewPendingLogin {
+    pub id: Uuid,
+    pub user_id: i64,
+    pub expires_at: chrono::DateTime<Utc>,
+}
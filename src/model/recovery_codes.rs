@@ -0,0 +1,26 @@
+use crate::schema::recovery_codes;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Queryable, Serialize, Deserialize, Clone, Debug)]
+pub struct RecoveryCode {
+    pub id: i64,
+    pub user_id: i64,
+    #[serde(skip_serializing)]
+    pub code_hash: String, // Argon2, like passwords
+    pub used_at: Option<chrono::DateTime<Utc>>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_codes"]
+pub struct NewRecoveryCode {
+    pub user_id: i64,
+    pub code_hash: String,
+}
+
+/// Shown once, only the hashes are stored
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecoveryCodesDto {
+    pub recovery_codes: Vec<String>,
+}
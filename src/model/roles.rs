@@ -0,0 +1,38 @@
+use crate::schema::{role_permissions, user_roles};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Queryable, Serialize, Deserialize, Clone, Debug)]
+pub struct Role {
+    pub id: i32,
+    pub name: String,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Queryable, Insertable, Clone, Debug)]
+#[table_name = "role_permissions"]
+pub struct RolePermission {
+    pub role_id: i32,
+    pub permission: String, // e.g. users:admin, emitted in the scope claim of access tokens
+}
+
+#[derive(Insertable)]
+#[table_name = "user_roles"]
+pub struct NewUserRole {
+    pub user_id: i64,
+    pub role_id: i32,
+}
+
+/// A role with the permissions it grants
+#[derive(Deserialize, Serialize)]
+pub struct RoleDto {
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+/// Roles of a user and the permissions they grant, as emitted into access tokens
+#[derive(Default)]
+pub struct Authorities {
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
@@ -21,6 +21,11 @@ pub struct Session {
     pub status: i32,
     pub created_at: chrono::DateTime<Utc>,
     pub updated_at: chrono::DateTime<Utc>,
+    pub generation: i32,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub last_seen_at: chrono::DateTime<Utc>, // Login or last refresh
+    pub scope: Option<String>,               // Scopes granted to the OAuth client, space separated
 }
 
 #[derive(Serialize, Deserialize, Insertable, Debug)]
@@ -33,6 +38,18 @@ pub struct NewSession {
     pub refreshed_at: chrono::DateTime<Utc>,
     pub expires_at: chrono::DateTime<Utc>,
     pub status: i32,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub last_seen_at: chrono::DateTime<Utc>,
+    pub scope: Option<String>,
+}
+
+/// A session as listed to its user, `current` marks the session of the request
+#[derive(Deserialize, Serialize)]
+pub struct SessionDto {
+    #[serde(flatten)]
+    pub session: Session,
+    pub current: bool,
 }
 
 #[derive(Debug, Validate, Deserialize, Serialize)]
@@ -56,3 +73,10 @@ pub struct TokenPairDto {
     pub session_token: TokenDto,
     pub access_token: TokenDto,
 }
+
+/// Returned by the login instead of a token pair if the user has a second factor
+#[derive(Deserialize, Serialize)]
+pub struct MfaRequiredDto {
+    pub mfa_token: TokenDto,
+    pub methods: Vec<String>, // Second factors the user can complete the login with
+}
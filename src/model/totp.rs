@@ -0,0 +1,40 @@
+use crate::schema::user_totp;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Queryable, Serialize, Deserialize, Clone, Debug)]
+pub struct UserTotp {
+    pub user_id: i64,
+    #[serde(skip_serializing)]
+    pub secret: Vec<u8>, // Encrypted, see totp::encrypt
+    pub confirmed_at: Option<chrono::DateTime<Utc>>, // None until the first code was entered
+    pub last_used_step: Option<i64>, // Codes of this time step and earlier are spent
+    pub created_at: chrono::DateTime<Utc>,
+    pub updated_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_totp"]
+pub struct NewUserTotp {
+    pub user_id: i64,
+    pub secret: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TotpEnrollmentDto {
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct TotpCodeDto {
+    #[validate(length(equal = 6))]
+    pub code: String,
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct MfaLoginDto {
+    pub mfa_token: String,
+    #[validate(length(min = 6, max = 32))]
+    pub code: String, // TOTP code or recovery code
+}
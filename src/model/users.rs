@@ -1,3 +1,4 @@
+use crate::model::sessions::Session;
 use crate::schema::users;
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
@@ -27,6 +28,8 @@ pub struct User {
     pub status: i32,
     pub created_at: chrono::DateTime<Utc>,
     pub updated_at: chrono::DateTime<Utc>,
+    pub failed_login_attempts: i32, // Since the last successful login
+    pub locked_until: Option<chrono::DateTime<Utc>>,
 }
 
 #[derive(Insertable)]
@@ -63,3 +66,48 @@ impl RegisterUserDto {
         }
     }
 }
+
+/// Filters of the admin user listing, every one is optional
+#[derive(Debug, Default)]
+pub struct UserFilter {
+    pub status: Option<i32>,
+    pub created_after: Option<chrono::DateTime<Utc>>,
+    pub created_before: Option<chrono::DateTime<Utc>>,
+    pub username_prefix: Option<String>,
+}
+
+/// Query of GET /admin/users, created_after is inclusive and created_before exclusive
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct UserSearchDto {
+    pub status: Option<UserStatus>,
+    pub created_after: Option<chrono::DateTime<Utc>>,
+    pub created_before: Option<chrono::DateTime<Utc>>,
+    #[validate(length(min = 1, max = 255))]
+    pub username_prefix: Option<String>,
+    #[validate(range(min = 1, max = 1000000))] // Keeps the offset from overflowing
+    pub page: Option<i64>,
+    #[validate(range(min = 1, max = 100))]
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserPageDto {
+    pub users: Vec<User>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+/// A user as seen by admins, sessions include blacklisted ones
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserDetailDto {
+    #[serde(flatten)]
+    pub user: User,
+    pub roles: Vec<String>,
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserStatusDto {
+    pub status: UserStatus,
+}
@@ -0,0 +1,159 @@
+use crate::schema::{webauthn_challenges, webauthn_credentials};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChallengePurpose {
+    Registration = 1,
+    Authentication = 2,
+}
+
+#[derive(Queryable, Serialize, Deserialize, Clone, Debug)]
+pub struct WebAuthnCredential {
+    pub id: Vec<u8>,
+    pub user_id: i64,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub public_key: Vec<u8>, // COSE_Key
+    pub sign_count: i64,
+    pub last_used_at: Option<chrono::DateTime<Utc>>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub updated_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "webauthn_credentials"]
+pub struct NewWebAuthnCredential {
+    pub id: Vec<u8>,
+    pub user_id: i64,
+    pub name: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+}
+
+#[derive(Queryable, Serialize, Deserialize, Debug)]
+pub struct WebAuthnChallenge {
+    pub challenge: String,
+    pub user_id: Option<i64>, // None for passwordless logins, the credential tells the user
+    pub purpose: i32,
+    pub expires_at: chrono::DateTime<Utc>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "webauthn_challenges"]
+pub struct NewWebAuthnChallenge {
+    pub challenge: String,
+    pub user_id: Option<i64>,
+    pub purpose: i32,
+    pub expires_at: chrono::DateTime<Utc>,
+}
+
+// The DTOs below follow the JSON forms of the WebAuthn API (binary values base64url encoded),
+// so browsers can use PublicKeyCredential.parseCreationOptionsFromJSON() and toJSON()
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RelyingPartyDto {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntityDto {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CredentialParameterDto {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CredentialDescriptorDto {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelectionDto {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptionsDto {
+    pub rp: RelyingPartyDto,
+    pub user: UserEntityDto,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameterDto>,
+    pub timeout: i64,
+    pub exclude_credentials: Vec<CredentialDescriptorDto>,
+    pub authenticator_selection: AuthenticatorSelectionDto,
+    pub attestation: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptionsDto {
+    pub challenge: String,
+    pub timeout: i64,
+    pub rp_id: String,
+    pub allow_credentials: Vec<CredentialDescriptorDto>,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponseDto {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponseDto {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PublicKeyCredentialDto<T> {
+    pub id: String,
+    pub response: T,
+}
+
+#[derive(Debug, Validate, Deserialize, Serialize)]
+pub struct RegisterCredentialDto {
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+    pub credential: PublicKeyCredentialDto<AttestationResponseDto>,
+}
+
+/// Without an mfa token the login is passwordless and needs a discoverable credential
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LoginOptionsRequestDto {
+    pub mfa_token: Option<String>,
+}
+
+/// Either completes a login started with the password (mfa_token) or is a passwordless login,
+/// which names platform and sub_platform like LoginDto
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WebAuthnLoginDto {
+    pub credential: PublicKeyCredentialDto<AssertionResponseDto>,
+    pub mfa_token: Option<String>,
+    pub platform: Option<String>,
+    pub sub_platform: Option<String>,
+}
@@ -0,0 +1,366 @@
+use crate::configuration::{RateLimit, RateLimitKey, RateLimitRule};
+use actix_web::http;
+use actix_web::http::header::HeaderMap;
+use actix_web::http::Method;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+// Beyond this many buckets the least recently used ones are dropped
+const MAX_BUCKETS: usize = 100_000;
+
+type BucketKey = (usize, String);
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Buckets and the order they were used in. Every use queues the key again, older entries of
+/// the key are skipped once they reach the front.
+#[derive(Default)]
+struct BucketMap {
+    buckets: HashMap<BucketKey, Bucket>,
+    used: VecDeque<(Instant, BucketKey)>,
+}
+
+impl BucketMap {
+    /// Drops the buckets at the front of the queue that are full again, a new bucket starts
+    /// full anyway, and the least recently used ones beyond MAX_BUCKETS
+    fn evict(&mut self, rules: &[(Method, RateLimitRule)], now: Instant) {
+        while let Some((used_at, key)) = self.used.front() {
+            let bucket = match self.buckets.get(key) {
+                Some(bucket) if bucket.updated_at == *used_at => bucket,
+                _ => {
+                    self.used.pop_front();
+                    continue;
+                }
+            };
+            let rule = &rules[key.0].1;
+            let full = bucket.tokens + refill(bucket, rule, now) >= rule.capacity as f64;
+            if !full && self.buckets.len() <= MAX_BUCKETS && self.used.len() <= 2 * MAX_BUCKETS {
+                break;
+            }
+            if let Some((_, key)) = self.used.pop_front() {
+                self.buckets.remove(&key);
+            }
+        }
+    }
+}
+
+/// Token buckets of the configured rules, keyed by rule and client ip or username.
+/// Kept in memory and shared between all workers, so every instance limits on its own.
+#[derive(Clone)]
+pub struct RateLimiter {
+    rules: Arc<Vec<(Method, RateLimitRule)>>,
+    trusted_proxies: usize,
+    buckets: Arc<Mutex<BucketMap>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimit) -> Result<Self, http::Error> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| Ok((Method::from_bytes(rule.method.as_bytes())?, rule.clone())))
+            .collect::<Result<Vec<_>, http::Error>>()?;
+        Ok(RateLimiter {
+            rules: Arc::new(rules),
+            trusted_proxies: config.trusted_proxies,
+            buckets: Arc::new(Mutex::new(BucketMap::default())),
+        })
+    }
+
+    pub fn trusted_proxies(&self) -> usize {
+        self.trusted_proxies
+    }
+
+    /// Indexes and keys of the rules that apply to the route
+    pub fn rules_for(&self, path: &str, method: &Method) -> Vec<(usize, RateLimitKey)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .filter(|(_, (rule_method, rule))| rule.path == path && rule_method == method)
+            .map(|(index, (_, rule))| (index, rule.key))
+            .collect()
+    }
+
+    /// Takes a token from the bucket of every (rule index, key), or from none of them if one is
+    /// empty. In that case returns how long it takes until all of them have a token again.
+    pub fn acquire(&self, keys: &[(usize, String)], now: Instant) -> Result<(), Duration> {
+        let mut map = match self.buckets.lock() {
+            Ok(map) => map,
+            Err(e) => {
+                error!("{}", e);
+                return Ok(());
+            }
+        };
+        map.evict(&self.rules, now);
+
+        let mut wait_ms: f64 = 0.0;
+        for (index, key) in keys {
+            let rule = &self.rules[*index].1;
+            let bucket_key = (*index, key.clone());
+            let queued = map
+                .buckets
+                .get(&bucket_key)
+                .is_some_and(|bucket| bucket.updated_at == now);
+            let bucket = map
+                .buckets
+                .entry(bucket_key.clone())
+                .or_insert_with(|| Bucket {
+                    tokens: rule.capacity as f64,
+                    updated_at: now,
+                });
+            bucket.tokens = (bucket.tokens + refill(bucket, rule, now)).min(rule.capacity as f64);
+            bucket.updated_at = now;
+            if bucket.tokens < 1.0 {
+                wait_ms = wait_ms.max((1.0 - bucket.tokens) * rule.refill_ms as f64);
+            }
+            if !queued {
+                map.used.push_back((now, bucket_key));
+            }
+        }
+        if wait_ms > 0.0 {
+            return Err(Duration::from_millis(wait_ms.ceil() as u64));
+        }
+
+        for key in keys {
+            if let Some(bucket) = map.buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ip of the client without the port, which changes with every connection. Behind trusted
+/// proxies it is the X-Forwarded-For entry appended by the outermost one, the entries left of
+/// it are set by the client. Without the header the peer is the client.
+pub fn client_ip(
+    peer_addr: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: usize,
+) -> Option<String> {
+    if trusted_proxies == 0 {
+        return peer_addr.map(|address| address.ip().to_string());
+    }
+    let mut lines: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    // The HeaderMap of actix-http puts the second line of a repeated header before the first
+    if lines.len() > 1 {
+        lines.swap(0, 1);
+    }
+    let forwarded_for: Vec<&str> = lines
+        .into_iter()
+        .flat_map(|line| line.split(','))
+        .map(str::trim)
+        .collect();
+    // With fewer entries than proxies all of them were appended by trusted proxies
+    forwarded_for
+        .get(forwarded_for.len().saturating_sub(trusted_proxies))
+        .and_then(|address| parse_ip(address))
+        .or_else(|| peer_addr.map(|address| address.ip().to_string()))
+}
+
+/// Bucket key of a client ip. The addresses of an IPv6 /64 share a bucket as a client usually
+/// gets the whole prefix.
+pub fn ip_key(ip: &str) -> String {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) if ip.to_ipv4_mapped().is_none() => {
+            let segments = ip.segments();
+            let prefix = Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                segments[3],
+                0,
+                0,
+                0,
+                0,
+            );
+            format!("{}/64", prefix)
+        }
+        _ => String::from(ip),
+    }
+}
+
+fn parse_ip(address: &str) -> Option<String> {
+    address
+        .parse::<IpAddr>()
+        .or_else(|_| address.parse::<SocketAddr>().map(|address| address.ip()))
+        .ok()
+        .map(|ip| ip.to_string())
+}
+
+/// Tokens gained since the bucket was last updated
+fn refill(bucket: &Bucket, rule: &RateLimitRule, now: Instant) -> f64 {
+    let elapsed_ms = now.saturating_duration_since(bucket.updated_at).as_millis() as f64;
+    elapsed_ms / rule.refill_ms.max(1) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{RateLimit, RateLimitKey, RateLimitRule};
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+    use actix_web::http::Method;
+    use std::time::{Duration, Instant};
+
+    fn rate_limiter() -> super::RateLimiter {
+        let rule = |key, capacity| RateLimitRule {
+            path: String::from("/api/v1/sessions"),
+            method: String::from("POST"),
+            key,
+            capacity,
+            refill_ms: 1000,
+        };
+        super::RateLimiter::new(&RateLimit {
+            trusted_proxies: 0,
+            rules: vec![rule(RateLimitKey::Ip, 3), rule(RateLimitKey::Username, 2)],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn rules_for() {
+        let rate_limiter = rate_limiter();
+        assert_eq!(
+            vec![(0, RateLimitKey::Ip), (1, RateLimitKey::Username)],
+            rate_limiter.rules_for("/api/v1/sessions", &Method::POST)
+        );
+        assert!(rate_limiter
+            .rules_for("/api/v1/sessions", &Method::GET)
+            .is_empty());
+    }
+
+    #[test]
+    fn empty_bucket_refills() {
+        let rate_limiter = rate_limiter();
+        let keys = [(0, String::from("10.0.0.1"))];
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(rate_limiter.acquire(&keys, now).is_ok());
+        }
+        assert_eq!(
+            Err(Duration::from_millis(1000)),
+            rate_limiter.acquire(&keys, now)
+        );
+        assert_eq!(
+            Err(Duration::from_millis(400)),
+            rate_limiter.acquire(&keys, now + Duration::from_millis(600))
+        );
+        assert!(rate_limiter
+            .acquire(&keys, now + Duration::from_millis(1000))
+            .is_ok());
+
+        // Other clients have their own bucket
+        assert!(rate_limiter
+            .acquire(&[(0, String::from("10.0.0.2"))], now)
+            .is_ok());
+    }
+
+    #[test]
+    fn rejected_request_takes_no_token() {
+        let rate_limiter = rate_limiter();
+        let keys = [(0, String::from("10.0.0.1")), (1, String::from("ALICE"))];
+        let now = Instant::now();
+        assert!(rate_limiter.acquire(&keys, now).is_ok());
+        assert!(rate_limiter.acquire(&keys, now).is_ok());
+        assert!(rate_limiter.acquire(&keys, now).is_err());
+
+        // The ip bucket still has the token the rejected request didn't take
+        assert!(rate_limiter
+            .acquire(&[(0, String::from("10.0.0.1"))], now)
+            .is_ok());
+    }
+
+    #[test]
+    fn client_ip() {
+        let peer = Some("10.0.0.1:50000".parse().unwrap());
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            Some(String::from("10.0.0.1")),
+            super::client_ip(peer, &headers, 1)
+        );
+
+        // A proxy may add its own line, the decoder appends repeated lines like this
+        headers.append(
+            HeaderName::from_static(super::X_FORWARDED_FOR),
+            HeaderValue::from_static("1.1.1.1, 2.2.2.2"),
+        );
+        headers.append(
+            HeaderName::from_static(super::X_FORWARDED_FOR),
+            HeaderValue::from_static("3.3.3.3"),
+        );
+        assert_eq!(
+            Some(String::from("10.0.0.1")),
+            super::client_ip(peer, &headers, 0)
+        );
+        // The client can put anything left of what the trusted proxies appended
+        assert_eq!(
+            Some(String::from("3.3.3.3")),
+            super::client_ip(peer, &headers, 1)
+        );
+        assert_eq!(
+            Some(String::from("2.2.2.2")),
+            super::client_ip(peer, &headers, 2)
+        );
+        assert_eq!(
+            Some(String::from("1.1.1.1")),
+            super::client_ip(peer, &headers, 5)
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static(super::X_FORWARDED_FOR),
+            HeaderValue::from_static("[2001:db8::1]:443"),
+        );
+        assert_eq!(
+            Some(String::from("2001:db8::1")),
+            super::client_ip(peer, &headers, 1)
+        );
+    }
+
+    #[test]
+    fn full_buckets_are_evicted() {
+        let rate_limiter = rate_limiter();
+        let now = Instant::now();
+        assert!(rate_limiter
+            .acquire(&[(0, String::from("10.0.0.1"))], now)
+            .is_ok());
+        assert!(rate_limiter
+            .acquire(
+                &[(0, String::from("10.0.0.2"))],
+                now + Duration::from_millis(500)
+            )
+            .is_ok());
+
+        // The first bucket is full again, the second one still misses half a token
+        assert!(rate_limiter
+            .acquire(
+                &[(1, String::from("ALICE"))],
+                now + Duration::from_millis(1000)
+            )
+            .is_ok());
+        let map = rate_limiter.buckets.lock().unwrap();
+        let mut keys: Vec<_> = map.buckets.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            vec![(0, String::from("10.0.0.2")), (1, String::from("ALICE"))],
+            keys
+        );
+    }
+
+    #[test]
+    fn ip_key() {
+        assert_eq!("10.0.0.1", super::ip_key("10.0.0.1"));
+        assert_eq!("2001:db8:0:1::/64", super::ip_key("2001:db8:0:1:a:b:c:d"));
+        assert_eq!("::ffff:10.0.0.1", super::ip_key("::ffff:10.0.0.1"));
+        assert_eq!("unknown", super::ip_key("unknown"));
+    }
+}
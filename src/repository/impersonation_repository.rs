@@ -0,0 +1,30 @@
+use crate::db::PgPooledConnection;
+use crate::model::impersonations::NewImpersonation;
+use crate::schema::impersonations;
+use diesel::prelude::*;
+use diesel::{QueryResult, RunQueryDsl};
+
+pub trait ImpersonationRepository {
+    fn create_impersonation(&self, impersonation: &NewImpersonation) -> QueryResult<usize>;
+    fn get_revoked_impersonation_ids(&self) -> QueryResult<Vec<uuid::Uuid>>;
+}
+
+impl ImpersonationRepository for PgPooledConnection {
+    fn create_impersonation(&self, impersonation: &NewImpersonation) -> QueryResult<usize> {
+        diesel::insert_into(impersonations::table)
+            .values(impersonation)
+            .execute(self)
+    }
+
+    /// Only unexpired ones, the tokens of the others are rejected anyway
+    fn get_revoked_impersonation_ids(&self) -> QueryResult<Vec<uuid::Uuid>> {
+        impersonations::table
+            .select(impersonations::id)
+            .filter(
+                impersonations::revoked_at
+                    .is_not_null()
+                    .and(impersonations::expires_at.gt(chrono::Utc::now())),
+            )
+            .load::<uuid::Uuid>(self)
+    }
+}
@@ -0,0 +1,32 @@
+use crate::db::PgPooledConnection;
+use crate::model::login_events::{LoginEvent, NewLoginEvent};
+use crate::schema::login_events;
+use diesel::prelude::*;
+use diesel::{QueryResult, RunQueryDsl};
+
+pub trait LoginEventRepository {
+    fn create_login_event(&self, login_event: &NewLoginEvent) -> QueryResult<usize>;
+    fn get_login_events_by_user_id(&self, user_id: i64, limit: i64)
+        -> QueryResult<Vec<LoginEvent>>;
+}
+
+impl LoginEventRepository for PgPooledConnection {
+    fn create_login_event(&self, login_event: &NewLoginEvent) -> QueryResult<usize> {
+        diesel::insert_into(login_events::table)
+            .values(login_event)
+            .execute(self)
+    }
+
+    /// Newest first
+    fn get_login_events_by_user_id(
+        &self,
+        user_id: i64,
+        limit: i64,
+    ) -> QueryResult<Vec<LoginEvent>> {
+        login_events::table
+            .filter(login_events::user_id.eq(user_id))
+            .order(login_events::created_at.desc())
+            .limit(limit)
+            .load::<LoginEvent>(self)
+    }
+}
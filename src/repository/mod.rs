@@ -1,2 +1,10 @@
+pub mod impersonation_repository;
+pub mod login_event_repository;
+pub mod oauth_repository;
+pub mod pending_login_repository;
+pub mod recovery_code_repository;
+pub mod role_repository;
 pub mod session_repository;
+pub mod totp_repository;
 pub mod user_repository;
+pub mod webauthn_repository;
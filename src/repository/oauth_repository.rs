@@ -0,0 +1,49 @@
+use crate::db::PgPooledConnection;
+use crate::model::oauth::{AuthorizationCode, NewAuthorizationCode, OAuthClient};
+use crate::schema::{oauth_authorization_codes, oauth_clients};
+use diesel::prelude::*;
+use diesel::{QueryResult, RunQueryDsl};
+
+pub trait OAuthRepository {
+    fn get_client_by_id(&self, id: &str) -> QueryResult<Option<OAuthClient>>;
+    fn create_authorization_code(&self, code: &NewAuthorizationCode) -> QueryResult<usize>;
+    fn consume_authorization_code(&self, code_hash: &str)
+        -> QueryResult<Option<AuthorizationCode>>;
+    fn delete_expired_authorization_codes(&self) -> QueryResult<usize>;
+}
+
+impl OAuthRepository for PgPooledConnection {
+    fn get_client_by_id(&self, id: &str) -> QueryResult<Option<OAuthClient>> {
+        oauth_clients::table
+            .filter(oauth_clients::id.eq(id))
+            .first::<OAuthClient>(self)
+            .optional()
+    }
+
+    fn create_authorization_code(&self, code: &NewAuthorizationCode) -> QueryResult<usize> {
+        diesel::insert_into(oauth_authorization_codes::table)
+            .values(code)
+            .execute(self)
+    }
+
+    /// Deletes and returns the code in one statement, so it can be redeemed only once
+    fn consume_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> QueryResult<Option<AuthorizationCode>> {
+        diesel::delete(
+            oauth_authorization_codes::table
+                .filter(oauth_authorization_codes::code_hash.eq(code_hash)),
+        )
+        .get_result::<AuthorizationCode>(self)
+        .optional()
+    }
+
+    fn delete_expired_authorization_codes(&self) -> QueryResult<usize> {
+        diesel::delete(
+            oauth_authorization_codes::table
+                .filter(oauth_authorization_codes::expires_at.lt(chrono::Utc::now())),
+        )
+        .execute(self)
+    }
+}
@@ -0,0 +1,48 @@
+use crate::db::PgPooledConnection;
+use crate::model::pending_logins::NewPendingLogin;
+use crate::schema::pending_logins;
+use diesel::prelude::*;
+use diesel::{QueryResult, RunQueryDsl};
+use uuid::Uuid;
+
+pub trait PendingLoginRepository {
+    fn create_pending_login(&self, pending_login: &NewPendingLogin) -> QueryResult<usize>;
+    fn count_pending_login_attempt(&self, id: Uuid) -> QueryResult<Option<i32>>;
+    fn delete_pending_login(&self, id: Uuid) -> QueryResult<usize>;
+    fn delete_expired_pending_logins(&self) -> QueryResult<usize>;
+}
+
+impl PendingLoginRepository for PgPooledConnection {
+    fn create_pending_login(&self, pending_login: &NewPendingLogin) -> QueryResult<usize> {
+        diesel::insert_into(pending_logins::table)
+            .values(pending_login)
+            .execute(self)
+    }
+
+    /// Returns the attempts including this one, None if the login was completed or expired
+    fn count_pending_login_attempt(&self, id: Uuid) -> QueryResult<Option<i32>> {
+        diesel::update(
+            pending_logins::table.filter(
+                pending_logins::id
+                    .eq(id)
+                    .and(pending_logins::expires_at.gt(chrono::Utc::now())),
+            ),
+        )
+        .set(pending_logins::attempts.eq(pending_logins::attempts + 1))
+        .returning(pending_logins::attempts)
+        .get_result(self)
+        .optional()
+    }
+
+    /// Returns 0 if a concurrent request completed the login
+    fn delete_pending_login(&self, id: Uuid) -> QueryResult<usize> {
+        diesel::delete(pending_logins::table.filter(pending_logins::id.eq(id))).execute(self)
+    }
+
+    fn delete_expired_pending_logins(&self) -> QueryResult<usize> {
+        diesel::delete(
+            pending_logins::table.filter(pending_logins::expires_at.lt(chrono::Utc::now())),
+        )
+        .execute(self)
+    }
+}
@@ -0,0 +1,52 @@
+use crate::db::PgPooledConnection;
+use crate::model::recovery_codes::{NewRecoveryCode, RecoveryCode};
+use crate::schema::recovery_codes;
+use diesel::prelude::*;
+use diesel::{QueryResult, RunQueryDsl};
+
+pub trait RecoveryCodeRepository {
+    fn get_unused_recovery_codes(&self, user_id: i64) -> QueryResult<Vec<RecoveryCode>>;
+    fn replace_recovery_codes(&self, user_id: i64, codes: &[NewRecoveryCode])
+        -> QueryResult<usize>;
+    fn use_recovery_code(&self, id: i64) -> QueryResult<usize>;
+}
+
+impl RecoveryCodeRepository for PgPooledConnection {
+    fn get_unused_recovery_codes(&self, user_id: i64) -> QueryResult<Vec<RecoveryCode>> {
+        recovery_codes::table
+            .filter(
+                recovery_codes::user_id
+                    .eq(user_id)
+                    .and(recovery_codes::used_at.is_null()),
+            )
+            .load::<RecoveryCode>(self)
+    }
+
+    /// Deletes the previous set, used codes included
+    fn replace_recovery_codes(
+        &self,
+        user_id: i64,
+        codes: &[NewRecoveryCode],
+    ) -> QueryResult<usize> {
+        self.transaction(|| {
+            diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                .execute(self)?;
+            diesel::insert_into(recovery_codes::table)
+                .values(codes)
+                .execute(self)
+        })
+    }
+
+    /// Returns 0 if the code was already used
+    fn use_recovery_code(&self, id: i64) -> QueryResult<usize> {
+        diesel::update(
+            recovery_codes::table.filter(
+                recovery_codes::id
+                    .eq(id)
+                    .and(recovery_codes::used_at.is_null()),
+            ),
+        )
+        .set(recovery_codes::used_at.eq(chrono::Utc::now()))
+        .execute(self)
+    }
+}
@@ -0,0 +1,75 @@
+use crate::db::PgPooledConnection;
+use crate::model::roles::{NewUserRole, Role, RolePermission};
+use crate::schema::{role_permissions, roles, user_roles};
+use diesel::prelude::*;
+use diesel::{QueryResult, RunQueryDsl};
+
+pub trait RoleRepository {
+    fn get_roles(&self) -> QueryResult<Vec<Role>>;
+    fn get_role_by_name(&self, name: &str) -> QueryResult<Option<Role>>;
+    fn get_role_permissions(&self) -> QueryResult<Vec<RolePermission>>;
+    fn get_role_names_by_user_id(&self, user_id: i64) -> QueryResult<Vec<String>>;
+    fn get_permissions_by_user_id(&self, user_id: i64) -> QueryResult<Vec<String>>;
+    fn add_user_role(&self, user_role: &NewUserRole) -> QueryResult<usize>;
+    fn remove_user_role(&self, user_id: i64, role_id: i32) -> QueryResult<usize>;
+}
+
+impl RoleRepository for PgPooledConnection {
+    fn get_roles(&self) -> QueryResult<Vec<Role>> {
+        roles::table.order(roles::name).load::<Role>(self)
+    }
+
+    fn get_role_by_name(&self, name: &str) -> QueryResult<Option<Role>> {
+        roles::table
+            .filter(roles::name.eq(name))
+            .first::<Role>(self)
+            .optional()
+    }
+
+    fn get_role_permissions(&self) -> QueryResult<Vec<RolePermission>> {
+        role_permissions::table
+            .order(role_permissions::permission)
+            .load::<RolePermission>(self)
+    }
+
+    fn get_role_names_by_user_id(&self, user_id: i64) -> QueryResult<Vec<String>> {
+        user_roles::table
+            .inner_join(roles::table)
+            .filter(user_roles::user_id.eq(user_id))
+            .select(roles::name)
+            .order(roles::name)
+            .load::<String>(self)
+    }
+
+    /// Permissions of all roles of the user, without duplicates
+    fn get_permissions_by_user_id(&self, user_id: i64) -> QueryResult<Vec<String>> {
+        user_roles::table
+            .inner_join(
+                role_permissions::table.on(role_permissions::role_id.eq(user_roles::role_id)),
+            )
+            .filter(user_roles::user_id.eq(user_id))
+            .select(role_permissions::permission)
+            .distinct()
+            .order(role_permissions::permission)
+            .load::<String>(self)
+    }
+
+    /// Returns 0 if the user already has the role
+    fn add_user_role(&self, user_role: &NewUserRole) -> QueryResult<usize> {
+        diesel::insert_into(user_roles::table)
+            .values(user_role)
+            .on_conflict_do_nothing()
+            .execute(self)
+    }
+
+    fn remove_user_role(&self, user_id: i64, role_id: i32) -> QueryResult<usize> {
+        diesel::delete(
+            user_roles::table.filter(
+                user_roles::user_id
+                    .eq(user_id)
+                    .and(user_roles::role_id.eq(role_id)),
+            ),
+        )
+        .execute(self)
+    }
+}
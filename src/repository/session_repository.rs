@@ -1,5 +1,6 @@
 use crate::db::PgPooledConnection;
-use crate::model::sessions::{NewSession, Session};
+use crate::model::login_events::ClientInfo;
+use crate::model::sessions::{NewSession, Session, SessionStatus};
 use crate::schema::sessions;
 use chrono::Utc;
 use diesel::prelude::*;
@@ -10,11 +11,24 @@ pub trait SessionRepository {
     fn get_sessions_by_user_id(&self, user_id: i64) -> QueryResult<Vec<Session>>;
     fn create_session(&self, session: &NewSession) -> QueryResult<usize>;
     fn delete_expired_active_sessions(&self, user_id: i64) -> QueryResult<usize>;
-    fn update_refreshed_timestamps(
+    fn rotate_session(
         &self,
         id: uuid::Uuid,
+        generation: i32,
         refreshed_at: chrono::DateTime<Utc>,
         expires_at: chrono::DateTime<Utc>,
+        client_info: &ClientInfo,
+    ) -> QueryResult<usize>;
+    fn update_session_status(&self, id: uuid::Uuid, status: SessionStatus) -> QueryResult<usize>;
+    fn blacklist_other_active_sessions(&self, user_id: i64, id: uuid::Uuid) -> QueryResult<usize>;
+    fn get_blacklisted_session_ids_since(
+        &self,
+        since: chrono::DateTime<Utc>,
+    ) -> QueryResult<Vec<uuid::Uuid>>;
+    fn delete_stale_sessions(
+        &self,
+        before: chrono::DateTime<Utc>,
+        limit: i64,
     ) -> QueryResult<usize>;
 }
 
@@ -29,6 +43,7 @@ impl SessionRepository for PgPooledConnection {
     fn get_sessions_by_user_id(&self, user_id: i64) -> QueryResult<Vec<Session>> {
         sessions::table
             .filter(sessions::user_id.eq(user_id))
+            .order(sessions::last_seen_at.desc())
             .load::<Session>(self)
     }
 
@@ -49,17 +64,79 @@ impl SessionRepository for PgPooledConnection {
         .execute(self)
     }
 
-    fn update_refreshed_timestamps(
+    /// Moves the session to the next generation, only if it is still at `generation`.
+    /// Returns 0 if another refresh already rotated the session.
+    fn rotate_session(
         &self,
         id: uuid::Uuid,
+        generation: i32,
         refreshed_at: chrono::DateTime<Utc>,
         expires_at: chrono::DateTime<Utc>,
+        client_info: &ClientInfo,
     ) -> QueryResult<usize> {
+        diesel::update(
+            sessions::table.filter(sessions::id.eq(id).and(sessions::generation.eq(generation))),
+        )
+        .set((
+            sessions::generation.eq(generation + 1),
+            sessions::refreshed_at.eq(refreshed_at),
+            sessions::expires_at.eq(expires_at),
+            sessions::ip.eq(&client_info.ip),
+            sessions::user_agent.eq(&client_info.user_agent),
+            sessions::last_seen_at.eq(refreshed_at),
+        ))
+        .execute(self)
+    }
+
+    fn update_session_status(&self, id: uuid::Uuid, status: SessionStatus) -> QueryResult<usize> {
         diesel::update(sessions::table.filter(sessions::id.eq(id)))
-            .set((
-                sessions::refreshed_at.eq(refreshed_at),
-                sessions::expires_at.eq(expires_at),
-            ))
+            .set(sessions::status.eq(status as i32))
             .execute(self)
     }
+
+    fn blacklist_other_active_sessions(&self, user_id: i64, id: uuid::Uuid) -> QueryResult<usize> {
+        diesel::update(
+            sessions::table.filter(
+                sessions::user_id
+                    .eq(user_id)
+                    .and(sessions::id.ne(id))
+                    .and(sessions::status.eq(SessionStatus::Active as i32)),
+            ),
+        )
+        .set(sessions::status.eq(SessionStatus::Blacklisted as i32))
+        .execute(self)
+    }
+
+    fn get_blacklisted_session_ids_since(
+        &self,
+        since: chrono::DateTime<Utc>,
+    ) -> QueryResult<Vec<uuid::Uuid>> {
+        // updated_at is set by trigger, so it marks the time the session was blacklisted
+        sessions::table
+            .select(sessions::id)
+            .filter(
+                sessions::status
+                    .eq(SessionStatus::Blacklisted as i32)
+                    .and(sessions::updated_at.ge(since)),
+            )
+            .load::<uuid::Uuid>(self)
+    }
+
+    /// Deletes up to `limit` sessions that expired or were blacklisted before `before`
+    fn delete_stale_sessions(
+        &self,
+        before: chrono::DateTime<Utc>,
+        limit: i64,
+    ) -> QueryResult<usize> {
+        let stale_session_ids = sessions::table
+            .select(sessions::id)
+            .filter(
+                sessions::expires_at.lt(before).or(sessions::status
+                    .eq(SessionStatus::Blacklisted as i32)
+                    .and(sessions::updated_at.lt(before))),
+            )
+            .limit(limit)
+            .load::<uuid::Uuid>(self)?;
+        diesel::delete(sessions::table.filter(sessions::id.eq_any(stale_session_ids))).execute(self)
+    }
 }
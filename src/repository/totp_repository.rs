@@ -0,0 +1,70 @@
+use crate::db::PgPooledConnection;
+use crate::model::totp::{NewUserTotp, UserTotp};
+use crate::schema::user_totp;
+use diesel::prelude::*;
+use diesel::{QueryResult, RunQueryDsl};
+
+pub trait TotpRepository {
+    fn get_totp_by_user_id(&self, user_id: i64) -> QueryResult<Option<UserTotp>>;
+    fn create_or_replace_unconfirmed_totp(&self, totp: &NewUserTotp) -> QueryResult<usize>;
+    fn confirm_totp(&self, user_id: i64, step: i64) -> QueryResult<usize>;
+    fn use_totp_step(&self, user_id: i64, step: i64) -> QueryResult<usize>;
+}
+
+impl TotpRepository for PgPooledConnection {
+    fn get_totp_by_user_id(&self, user_id: i64) -> QueryResult<Option<UserTotp>> {
+        user_totp::table
+            .filter(user_totp::user_id.eq(user_id))
+            .first::<UserTotp>(self)
+            .optional()
+    }
+
+    /// A confirmed secret is never overwritten, returns 0 in that case
+    fn create_or_replace_unconfirmed_totp(&self, totp: &NewUserTotp) -> QueryResult<usize> {
+        self.transaction(|| {
+            diesel::delete(
+                user_totp::table.filter(
+                    user_totp::user_id
+                        .eq(totp.user_id)
+                        .and(user_totp::confirmed_at.is_null()),
+                ),
+            )
+            .execute(self)?;
+            diesel::insert_into(user_totp::table)
+                .values(totp)
+                .on_conflict_do_nothing()
+                .execute(self)
+        })
+    }
+
+    fn confirm_totp(&self, user_id: i64, step: i64) -> QueryResult<usize> {
+        diesel::update(
+            user_totp::table.filter(
+                user_totp::user_id
+                    .eq(user_id)
+                    .and(user_totp::confirmed_at.is_null()),
+            ),
+        )
+        .set((
+            user_totp::confirmed_at.eq(chrono::Utc::now()),
+            user_totp::last_used_step.eq(step),
+        ))
+        .execute(self)
+    }
+
+    /// Marks the time step as spent, only if no code of it or a later one was used yet.
+    /// Returns 0 if the code was already used.
+    fn use_totp_step(&self, user_id: i64, step: i64) -> QueryResult<usize> {
+        diesel::update(
+            user_totp::table.filter(
+                user_totp::user_id.eq(user_id).and(
+                    user_totp::last_used_step
+                        .is_null()
+                        .or(user_totp::last_used_step.lt(step)),
+                ),
+            ),
+        )
+        .set(user_totp::last_used_step.eq(step))
+        .execute(self)
+    }
+}
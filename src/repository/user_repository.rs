@@ -1,7 +1,10 @@
 // Definitions
 use crate::db::PgPooledConnection;
-use crate::model::users::{NewUser, User};
-use crate::schema::users;
+use crate::model::sessions::SessionStatus;
+use crate::model::users::{NewUser, User, UserFilter, UserStatus};
+use crate::schema::{impersonations, sessions, users};
+use chrono::Utc;
+use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::{QueryResult, RunQueryDsl};
 
@@ -9,6 +12,13 @@ pub trait UserRepository {
     fn get_user_by_id(&self, id: i64) -> QueryResult<Option<User>>;
     fn get_user_by_username(&self, username: &str) -> QueryResult<Option<User>>;
     fn create_user(&self, new_user: &mut NewUser) -> QueryResult<usize>;
+    fn increment_failed_login_attempts(&self, id: i64) -> QueryResult<i32>;
+    fn lock_user(&self, id: i64, locked_until: chrono::DateTime<Utc>) -> QueryResult<usize>;
+    fn reset_failed_login_attempts(&self, id: i64) -> QueryResult<usize>;
+    fn get_users(&self, filter: &UserFilter, offset: i64, limit: i64) -> QueryResult<Vec<User>>;
+    fn count_users(&self, filter: &UserFilter) -> QueryResult<i64>;
+    fn update_user_status(&self, id: i64, status: UserStatus) -> QueryResult<usize>;
+    fn suspend_user(&self, id: i64) -> QueryResult<usize>;
 }
 
 impl UserRepository for PgPooledConnection {
@@ -34,4 +44,101 @@ impl UserRepository for PgPooledConnection {
             .values(&*new_user)
             .execute(self)
     }
+
+    /// Returns the new count, concurrent failures are all counted
+    fn increment_failed_login_attempts(&self, id: i64) -> QueryResult<i32> {
+        diesel::update(users::table.filter(users::id.eq(id)))
+            .set(users::failed_login_attempts.eq(users::failed_login_attempts + 1))
+            .returning(users::failed_login_attempts)
+            .get_result(self)
+    }
+
+    fn lock_user(&self, id: i64, locked_until: chrono::DateTime<Utc>) -> QueryResult<usize> {
+        diesel::update(users::table.filter(users::id.eq(id)))
+            .set(users::locked_until.eq(locked_until))
+            .execute(self)
+    }
+
+    fn reset_failed_login_attempts(&self, id: i64) -> QueryResult<usize> {
+        diesel::update(users::table.filter(users::id.eq(id)))
+            .set((
+                users::failed_login_attempts.eq(0),
+                users::locked_until.eq(None::<chrono::DateTime<Utc>>),
+            ))
+            .execute(self)
+    }
+
+    /// Newest first
+    fn get_users(&self, filter: &UserFilter, offset: i64, limit: i64) -> QueryResult<Vec<User>> {
+        filtered_users(filter)
+            .order((users::created_at.desc(), users::id.desc()))
+            .offset(offset)
+            .limit(limit)
+            .load::<User>(self)
+    }
+
+    fn count_users(&self, filter: &UserFilter) -> QueryResult<i64> {
+        filtered_users(filter).count().get_result(self)
+    }
+
+    fn update_user_status(&self, id: i64, status: UserStatus) -> QueryResult<usize> {
+        diesel::update(users::table.filter(users::id.eq(id)))
+            .set(users::status.eq(status as i32))
+            .execute(self)
+    }
+
+    /// Blacklists all active sessions of the user in the same transaction, so no session can be
+    /// refreshed once the suspension is visible. Returns the number of blacklisted sessions.
+    /// Also revokes the running impersonations of and by the user
+    fn suspend_user(&self, id: i64) -> QueryResult<usize> {
+        self.transaction(|| {
+            let now = chrono::Utc::now();
+            diesel::update(users::table.filter(users::id.eq(id)))
+                .set(users::status.eq(UserStatus::Suspended as i32))
+                .execute(self)?;
+            diesel::update(
+                impersonations::table.filter(
+                    impersonations::user_id
+                        .eq(id)
+                        .or(impersonations::admin_id.eq(id))
+                        .and(impersonations::revoked_at.is_null())
+                        .and(impersonations::expires_at.gt(now)),
+                ),
+            )
+            .set(impersonations::revoked_at.eq(now))
+            .execute(self)?;
+            diesel::update(
+                sessions::table.filter(
+                    sessions::user_id
+                        .eq(id)
+                        .and(sessions::status.eq(SessionStatus::Active as i32)),
+                ),
+            )
+            .set(sessions::status.eq(SessionStatus::Blacklisted as i32))
+            .execute(self)
+        })
+    }
+}
+
+fn filtered_users(filter: &UserFilter) -> users::BoxedQuery<'static, Pg> {
+    let mut query = users::table.into_boxed();
+    if let Some(status) = filter.status {
+        query = query.filter(users::status.eq(status));
+    }
+    if let Some(created_after) = filter.created_after {
+        query = query.filter(users::created_at.ge(created_after));
+    }
+    if let Some(created_before) = filter.created_before {
+        query = query.filter(users::created_at.lt(created_before));
+    }
+    if let Some(username_prefix) = &filter.username_prefix {
+        // Usernames are stored upper case, like and its wildcards are escaped with backslashes
+        let escaped = username_prefix
+            .to_uppercase()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        query = query.filter(users::username.like(format!("{}%", escaped)));
+    }
+    query
 }
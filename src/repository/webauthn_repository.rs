@@ -0,0 +1,99 @@
+use crate::db::PgPooledConnection;
+use crate::model::webauthn::{
+    NewWebAuthnChallenge, NewWebAuthnCredential, WebAuthnChallenge, WebAuthnCredential,
+};
+use crate::schema::{webauthn_challenges, webauthn_credentials};
+use diesel::prelude::*;
+use diesel::{QueryResult, RunQueryDsl};
+
+pub trait WebAuthnRepository {
+    fn get_credential_by_id(&self, id: &[u8]) -> QueryResult<Option<WebAuthnCredential>>;
+    fn get_credentials_by_user_id(&self, user_id: i64) -> QueryResult<Vec<WebAuthnCredential>>;
+    fn create_credential(&self, credential: &NewWebAuthnCredential) -> QueryResult<usize>;
+    fn update_credential_sign_count(
+        &self,
+        id: &[u8],
+        old_sign_count: i64,
+        sign_count: i64,
+    ) -> QueryResult<usize>;
+    fn create_challenge(&self, challenge: &NewWebAuthnChallenge) -> QueryResult<usize>;
+    fn consume_challenge(
+        &self,
+        challenge: &str,
+        purpose: i32,
+    ) -> QueryResult<Option<WebAuthnChallenge>>;
+    fn delete_expired_challenges(&self) -> QueryResult<usize>;
+}
+
+impl WebAuthnRepository for PgPooledConnection {
+    fn get_credential_by_id(&self, id: &[u8]) -> QueryResult<Option<WebAuthnCredential>> {
+        webauthn_credentials::table
+            .filter(webauthn_credentials::id.eq(id))
+            .first::<WebAuthnCredential>(self)
+            .optional()
+    }
+
+    fn get_credentials_by_user_id(&self, user_id: i64) -> QueryResult<Vec<WebAuthnCredential>> {
+        webauthn_credentials::table
+            .filter(webauthn_credentials::user_id.eq(user_id))
+            .load::<WebAuthnCredential>(self)
+    }
+
+    fn create_credential(&self, credential: &NewWebAuthnCredential) -> QueryResult<usize> {
+        diesel::insert_into(webauthn_credentials::table)
+            .values(credential)
+            .execute(self)
+    }
+
+    /// Only updates if the count is still `old_sign_count`, returns 0 if a concurrent login won
+    fn update_credential_sign_count(
+        &self,
+        id: &[u8],
+        old_sign_count: i64,
+        sign_count: i64,
+    ) -> QueryResult<usize> {
+        diesel::update(
+            webauthn_credentials::table.filter(
+                webauthn_credentials::id
+                    .eq(id)
+                    .and(webauthn_credentials::sign_count.eq(old_sign_count)),
+            ),
+        )
+        .set((
+            webauthn_credentials::sign_count.eq(sign_count),
+            webauthn_credentials::last_used_at.eq(chrono::Utc::now()),
+        ))
+        .execute(self)
+    }
+
+    fn create_challenge(&self, challenge: &NewWebAuthnChallenge) -> QueryResult<usize> {
+        diesel::insert_into(webauthn_challenges::table)
+            .values(challenge)
+            .execute(self)
+    }
+
+    /// Deletes and returns the challenge in one statement, so it can be answered only once
+    fn consume_challenge(
+        &self,
+        challenge: &str,
+        purpose: i32,
+    ) -> QueryResult<Option<WebAuthnChallenge>> {
+        diesel::delete(
+            webauthn_challenges::table.filter(
+                webauthn_challenges::challenge
+                    .eq(challenge)
+                    .and(webauthn_challenges::purpose.eq(purpose)),
+            ),
+        )
+        .get_result::<WebAuthnChallenge>(self)
+        .optional()
+    }
+
+    fn delete_expired_challenges(&self) -> QueryResult<usize> {
+        diesel::delete(
+            webauthn_challenges::table
+                .filter(webauthn_challenges::expires_at.lt(chrono::Utc::now())),
+        )
+        .execute(self)
+    }
+}
@@ -0,0 +1,92 @@
+use crate::db;
+use crate::db::PgPool;
+use crate::repository::impersonation_repository::ImpersonationRepository;
+use crate::repository::session_repository::SessionRepository;
+use actix_web::{rt, web};
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Ids of blacklisted sessions whose access tokens may still be unexpired, and of revoked
+/// impersonations, whose token `sid` is the impersonation id. Shared between all workers and
+/// refreshed from the sessions and impersonations tables in the background.
+#[derive(Clone, Default)]
+pub struct RevokedSessions {
+    session_ids: Arc<RwLock<HashSet<Uuid>>>,
+}
+
+impl RevokedSessions {
+    pub fn contains(&self, session_id: &Uuid) -> bool {
+        match self.session_ids.read() {
+            Ok(session_ids) => session_ids.contains(session_id),
+            Err(e) => {
+                error!("{}", e);
+                false
+            }
+        }
+    }
+
+    pub(crate) fn replace(&self, session_ids: HashSet<Uuid>) {
+        match self.session_ids.write() {
+            Ok(mut current) => *current = session_ids,
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+/// Reloads the revoked sessions every `refresh_ms`. Sessions blacklisted longer ago than
+/// `access_exp_ms` are left out, since every access token issued for them has expired by then.
+/// Impersonations expire on their own and are left out once they have.
+pub fn spawn_refresh(
+    pool: PgPool,
+    revoked_sessions: RevokedSessions,
+    refresh_ms: u64,
+    access_exp_ms: i64,
+) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_millis(refresh_ms));
+        loop {
+            interval.tick().await;
+            let pool = pool.clone();
+            let result = web::block(move || {
+                let conn = db::get_conn(&pool)?;
+                let since = chrono::Utc::now() - chrono::Duration::milliseconds(access_exp_ms);
+                let mut revoked_ids = conn.get_blacklisted_session_ids_since(since)?;
+                revoked_ids.append(&mut conn.get_revoked_impersonation_ids()?);
+                Ok::<_, diesel::result::Error>(revoked_ids)
+            })
+            .await;
+
+            match result {
+                Ok(session_ids) => revoked_sessions.replace(session_ids.into_iter().collect()),
+                Err(e) => error!("Could not refresh revoked sessions: {:?}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RevokedSessions;
+    use uuid::Uuid;
+
+    #[test]
+    fn replace_drops_previous_ids() {
+        let revoked_sessions = RevokedSessions::default();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        assert!(!revoked_sessions.contains(&first));
+
+        revoked_sessions.replace(vec![first].into_iter().collect());
+        assert!(revoked_sessions.contains(&first));
+        assert!(!revoked_sessions.contains(&second));
+
+        // Clones share the set, like the workers do
+        revoked_sessions
+            .clone()
+            .replace(vec![second].into_iter().collect());
+        assert!(!revoked_sessions.contains(&first));
+        assert!(revoked_sessions.contains(&second));
+    }
+}
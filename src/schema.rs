@@ -1,3 +1,93 @@
+table! {
+    impersonations (id) {
+        id -> Uuid,
+        admin_id -> Int8,
+        user_id -> Int8,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    login_events (id) {
+        id -> Int8,
+        user_id -> Nullable<Int8>,
+        event_type -> Int4,
+        success -> Bool,
+        failure_reason -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        platform -> Nullable<Varchar>,
+        sub_platform -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    oauth_authorization_codes (code_hash) {
+        code_hash -> Varchar,
+        client_id -> Varchar,
+        user_id -> Int8,
+        redirect_uri -> Text,
+        code_challenge -> Varchar,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+        scope -> Text,
+        nonce -> Nullable<Text>,
+    }
+}
+
+table! {
+    oauth_clients (id) {
+        id -> Varchar,
+        name -> Varchar,
+        client_secret -> Nullable<Varchar>,
+        redirect_uris -> Array<Text>,
+        status -> Int4,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        grant_types -> Array<Text>,
+    }
+}
+
+table! {
+    pending_logins (id) {
+        id -> Uuid,
+        user_id -> Int8,
+        attempts -> Int4,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Int8,
+        user_id -> Int8,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    role_permissions (role_id, permission) {
+        role_id -> Int4,
+        permission -> Varchar,
+    }
+}
+
+table! {
+    roles (id) {
+        id -> Int4,
+        name -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
 table! {
     sessions (id) {
         id -> Uuid,
@@ -9,6 +99,30 @@ table! {
         status -> Int4,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        generation -> Int4,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        last_seen_at -> Timestamptz,
+        scope -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    user_roles (user_id, role_id) {
+        user_id -> Int8,
+        role_id -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    user_totp (user_id) {
+        user_id -> Int8,
+        secret -> Bytea,
+        confirmed_at -> Nullable<Timestamptz>,
+        last_used_step -> Nullable<Int8>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -23,7 +137,58 @@ table! {
         status -> Int4,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        failed_login_attempts -> Int4,
+        locked_until -> Nullable<Timestamptz>,
     }
 }
 
-allow_tables_to_appear_in_same_query!(sessions, users,);
+table! {
+    webauthn_challenges (challenge) {
+        challenge -> Varchar,
+        user_id -> Nullable<Int8>,
+        purpose -> Int4,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    webauthn_credentials (id) {
+        id -> Bytea,
+        user_id -> Int8,
+        name -> Varchar,
+        public_key -> Bytea,
+        sign_count -> Int8,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+joinable!(login_events -> users (user_id));
+joinable!(oauth_authorization_codes -> oauth_clients (client_id));
+joinable!(pending_logins -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(role_permissions -> roles (role_id));
+joinable!(user_roles -> roles (role_id));
+joinable!(user_roles -> users (user_id));
+joinable!(user_totp -> users (user_id));
+joinable!(webauthn_challenges -> users (user_id));
+joinable!(webauthn_credentials -> users (user_id));
+
+allow_tables_to_appear_in_same_query!(
+    impersonations,
+    login_events,
+    oauth_authorization_codes,
+    oauth_clients,
+    pending_logins,
+    recovery_codes,
+    role_permissions,
+    roles,
+    sessions,
+    user_roles,
+    user_totp,
+    users,
+    webauthn_challenges,
+    webauthn_credentials,
+);
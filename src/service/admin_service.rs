@@ -0,0 +1,326 @@
+use crate::auth;
+use crate::configuration::{Impersonation, Jwt};
+use crate::model::impersonations::NewImpersonation;
+use crate::model::login_events::ClientInfo;
+use crate::model::sessions::TokenDto;
+use crate::model::users::{UserDetailDto, UserFilter, UserPageDto, UserSearchDto, UserStatus};
+use crate::repository::impersonation_repository::ImpersonationRepository;
+use crate::repository::role_repository::RoleRepository;
+use crate::repository::session_repository::SessionRepository;
+use crate::repository::user_repository::UserRepository;
+use uuid::Uuid;
+
+const DEFAULT_PER_PAGE: i64 = 50;
+
+#[derive(Debug, PartialEq)]
+pub enum AdminServiceError {
+    GenericDatabaseError(diesel::result::Error),
+    UserDoesNotExist,
+    UserNotActive,
+    InvalidStatusTransition,
+    JwtGenerationError,
+}
+
+impl From<diesel::result::Error> for AdminServiceError {
+    fn from(error: diesel::result::Error) -> AdminServiceError {
+        AdminServiceError::GenericDatabaseError(error)
+    }
+}
+
+/// Pages start at 1, the search is expected to be validated
+pub fn search_users(
+    user_repository: &impl UserRepository,
+    search: UserSearchDto,
+) -> Result<UserPageDto, AdminServiceError> {
+    let page = search.page.unwrap_or(1);
+    let per_page = search.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    let filter = UserFilter {
+        status: search.status.map(|status| status as i32),
+        created_after: search.created_after,
+        created_before: search.created_before,
+        username_prefix: search.username_prefix,
+    };
+
+    Ok(UserPageDto {
+        users: user_repository.get_users(&filter, (page - 1) * per_page, per_page)?,
+        total: user_repository.count_users(&filter)?,
+        page,
+        per_page,
+    })
+}
+
+pub fn get_user_detail<R>(
+    repositories: &R,
+    user_id: i64,
+) -> Result<UserDetailDto, AdminServiceError>
+where
+    R: UserRepository + RoleRepository + SessionRepository,
+{
+    let user = repositories
+        .get_user_by_id(user_id)?
+        .ok_or(AdminServiceError::UserDoesNotExist)?;
+    Ok(UserDetailDto {
+        user,
+        roles: repositories.get_role_names_by_user_id(user_id)?,
+        sessions: repositories.get_sessions_by_user_id(user_id)?,
+    })
+}
+
+/// Users can be activated and suspended from every status, but never go back to not verified.
+/// Suspending a user blacklists all of their sessions and revokes impersonations of and by them.
+pub fn update_user_status(
+    user_repository: &impl UserRepository,
+    admin_id: i64,
+    user_id: i64,
+    status: UserStatus,
+) -> Result<(), AdminServiceError> {
+    let user = user_repository
+        .get_user_by_id(user_id)?
+        .ok_or(AdminServiceError::UserDoesNotExist)?;
+    let status_id = status.clone() as i32;
+    if user.status == status_id {
+        return Ok(());
+    }
+    if status_id == UserStatus::NotVerified as i32 {
+        return Err(AdminServiceError::InvalidStatusTransition);
+    }
+
+    if let UserStatus::Suspended = status {
+        let blacklisted = user_repository.suspend_user(user_id)?;
+        info!(
+            "User {} suspended user {}, blacklisting {} sessions",
+            admin_id, user_id, blacklisted
+        );
+        return Ok(());
+    }
+    user_repository.update_user_status(user_id, status.clone())?;
+    info!(
+        "User {} changed the status of user {} to {:?}",
+        admin_id, user_id, status
+    );
+    Ok(())
+}
+
+/// Issues an access token for the user that names the admin in its `act` claim. It carries no
+/// roles and has no session, so it can't be refreshed and ends after impersonation.exp_ms.
+/// The start is recorded before the token is handed out, suspending either user revokes it.
+pub fn impersonate<R>(
+    repositories: &R,
+    admin_id: i64,
+    user_id: i64,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+    impersonation_config: &Impersonation,
+) -> Result<TokenDto, AdminServiceError>
+where
+    R: UserRepository + ImpersonationRepository,
+{
+    let user = repositories
+        .get_user_by_id(user_id)?
+        .ok_or(AdminServiceError::UserDoesNotExist)?;
+    if user.status != UserStatus::Active as i32 {
+        return Err(AdminServiceError::UserNotActive);
+    }
+
+    let now = chrono::Utc::now();
+    let expiration = now + chrono::Duration::milliseconds(impersonation_config.exp_ms);
+    let impersonation_id = Uuid::new_v4();
+    let claims = auth::AccessClaims {
+        exp: expiration.timestamp(),
+        iat: now.timestamp(),
+        iss: token_config.issuer.clone(),
+        aud: vec![token_config.audience.clone()],
+        user_id,
+        sid: impersonation_id,
+        roles: vec![],
+        scope: String::new(),
+        act: Some(auth::Actor { user_id: admin_id }),
+        client_id: None,
+    };
+    let token = auth::encode_access_jwt(&claims, token_config).map_err(|e| {
+        error!("{}", e);
+        AdminServiceError::JwtGenerationError
+    })?;
+
+    repositories.create_impersonation(&NewImpersonation {
+        id: impersonation_id,
+        admin_id,
+        user_id,
+        ip: client_info.ip.clone(),
+        user_agent: client_info.user_agent.clone(),
+        expires_at: expiration,
+    })?;
+    warn!(
+        "User {} started impersonating user {} until {}",
+        admin_id, user_id, expiration
+    );
+    Ok(TokenDto { token, expiration })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdminServiceError;
+    use crate::configuration::Impersonation;
+    use crate::model::login_events::ClientInfo;
+    use crate::model::users::{UserSearchDto, UserStatus};
+    use crate::repository::impersonation_repository::ImpersonationRepository;
+    use crate::test_support::{jwt_config, user, MockRepo};
+    use chrono::Utc;
+    use validator::Validate;
+
+    /// Users 1, 2, ... with the given statuses
+    fn seeded_repo(statuses: &[UserStatus]) -> MockRepo {
+        MockRepo::with_users(
+            statuses
+                .iter()
+                .enumerate()
+                .map(|(i, status)| user(i as i64 + 1, status.clone()))
+                .collect(),
+        )
+    }
+
+    fn search(page: Option<i64>, per_page: Option<i64>) -> UserSearchDto {
+        UserSearchDto {
+            status: None,
+            created_after: None,
+            created_before: None,
+            username_prefix: None,
+            page,
+            per_page,
+        }
+    }
+
+    #[test]
+    fn search_users_pages() {
+        let repo = seeded_repo(&vec![UserStatus::Active; 5]);
+
+        let first = super::search_users(&repo, search(None, None)).unwrap();
+        assert_eq!(
+            (1, 50, 5, 5),
+            (first.page, first.per_page, first.total, first.users.len())
+        );
+
+        let last = super::search_users(&repo, search(Some(3), Some(2))).unwrap();
+        assert_eq!(5, last.total);
+        assert_eq!(1, last.users.len());
+        // Newest first, so the last page has the oldest user
+        assert_eq!(1, last.users[0].id);
+    }
+
+    #[test]
+    fn search_page_is_bounded() {
+        assert!(search(Some(1000000), Some(100)).validate().is_ok());
+        assert!(search(Some(0), None).validate().is_err());
+        assert!(search(Some(i64::MAX / 50 + 1), None).validate().is_err());
+    }
+
+    #[test]
+    fn update_user_status_transitions() {
+        let repo = seeded_repo(&[UserStatus::NotVerified, UserStatus::Active]);
+
+        super::update_user_status(&repo, 9, 2, UserStatus::Suspended).unwrap();
+        assert_eq!(UserStatus::Suspended as i32, repo.user(2).status);
+        super::update_user_status(&repo, 9, 2, UserStatus::Active).unwrap();
+        assert_eq!(UserStatus::Active as i32, repo.user(2).status);
+
+        // Not verified is only ever left
+        assert_eq!(
+            Err(AdminServiceError::InvalidStatusTransition),
+            super::update_user_status(&repo, 9, 2, UserStatus::NotVerified)
+        );
+        super::update_user_status(&repo, 9, 1, UserStatus::NotVerified).unwrap();
+        super::update_user_status(&repo, 9, 1, UserStatus::Active).unwrap();
+        assert_eq!(UserStatus::Active as i32, repo.user(1).status);
+
+        assert_eq!(
+            Err(AdminServiceError::UserDoesNotExist),
+            super::update_user_status(&repo, 9, 3, UserStatus::Active)
+        );
+    }
+
+    #[test]
+    fn impersonate_records_actor() {
+        let repo = seeded_repo(&[UserStatus::Active, UserStatus::Active]);
+        let config = jwt_config();
+        let impersonation = Impersonation { exp_ms: 600000 };
+
+        let token =
+            super::impersonate(&repo, 1, 2, &ClientInfo::default(), &config, &impersonation)
+                .unwrap();
+        assert!(token.expiration <= Utc::now() + chrono::Duration::minutes(10));
+        let claims = match crate::auth::decode_access_jwt(&token.token, &config) {
+            Ok(crate::auth::AccessToken::User(claims)) => claims,
+            other => panic!("Expected user claims, got {:?}", other),
+        };
+        assert_eq!(2, claims.user_id);
+        assert_eq!(Some(1), claims.act.map(|actor| actor.user_id));
+        assert!(claims.scope.is_empty());
+        let recorded: Vec<_> = repo
+            .impersonations
+            .borrow()
+            .iter()
+            .map(|(i, _)| (i.id, i.admin_id, i.user_id))
+            .collect();
+        assert_eq!(vec![(claims.sid, 1, 2)], recorded);
+
+        assert_eq!(
+            Err(AdminServiceError::UserDoesNotExist),
+            super::impersonate(&repo, 1, 3, &ClientInfo::default(), &config, &impersonation)
+                .map(|_| ())
+        );
+        assert_eq!(1, repo.impersonations.borrow().len());
+    }
+
+    #[test]
+    fn impersonate_requires_active_user() {
+        let repo = seeded_repo(&[UserStatus::Active, UserStatus::Suspended]);
+        let impersonation = Impersonation { exp_ms: 600000 };
+        assert_eq!(
+            Err(AdminServiceError::UserNotActive),
+            super::impersonate(
+                &repo,
+                1,
+                2,
+                &ClientInfo::default(),
+                &jwt_config(),
+                &impersonation
+            )
+            .map(|_| ())
+        );
+        assert!(repo.impersonations.borrow().is_empty());
+    }
+
+    #[test]
+    fn suspension_revokes_impersonation() {
+        let repo = seeded_repo(&[UserStatus::Active, UserStatus::Active, UserStatus::Active]);
+        let config = jwt_config();
+        let impersonation = Impersonation { exp_ms: 600000 };
+        let start = |admin_id, user_id| {
+            let token = super::impersonate(
+                &repo,
+                admin_id,
+                user_id,
+                &ClientInfo::default(),
+                &config,
+                &impersonation,
+            )
+            .unwrap();
+            match crate::auth::decode_access_jwt(&token.token, &config) {
+                Ok(crate::auth::AccessToken::User(claims)) => claims.sid,
+                other => panic!("Expected user claims, got {:?}", other),
+            }
+        };
+        let of_user = start(1, 2);
+        let by_admin = start(3, 1);
+        let of_other_user = start(1, 3);
+
+        super::update_user_status(&repo, 9, 2, UserStatus::Suspended).unwrap();
+        assert_eq!(vec![of_user], repo.get_revoked_impersonation_ids().unwrap());
+        // Suspending the admin ends their impersonations too
+        super::update_user_status(&repo, 9, 3, UserStatus::Suspended).unwrap();
+        assert_eq!(
+            vec![of_user, by_admin, of_other_user],
+            repo.get_revoked_impersonation_ids().unwrap()
+        );
+    }
+}
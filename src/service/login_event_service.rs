@@ -0,0 +1,25 @@
+use crate::model::login_events::{LoginEvent, NewLoginEvent};
+use crate::repository::login_event_repository::LoginEventRepository;
+
+const LOGIN_EVENTS_LIMIT: i64 = 100;
+
+/// Records the outcome of a login, None as failure reason is a success. The audit trail must not
+/// turn a login into an error, so failing to record is only logged.
+pub fn record_login_event(
+    login_event_repository: &impl LoginEventRepository,
+    mut login_event: NewLoginEvent,
+    failure_reason: Option<&str>,
+) {
+    login_event.success = failure_reason.is_none();
+    login_event.failure_reason = failure_reason.map(String::from);
+    if let Err(e) = login_event_repository.create_login_event(&login_event) {
+        error!("Could not record login event {:?}: {}", login_event, e);
+    }
+}
+
+pub fn get_users_login_events(
+    login_event_repository: &impl LoginEventRepository,
+    user_id: i64,
+) -> Result<Vec<LoginEvent>, diesel::result::Error> {
+    login_event_repository.get_login_events_by_user_id(user_id, LOGIN_EVENTS_LIMIT)
+}
@@ -0,0 +1,599 @@
+use crate::auth;
+use crate::auth::AuthorizationError;
+use crate::configuration::{Jwt, Lockout, Mfa, SessionLimits};
+use crate::model::login_events::{ClientInfo, LoginEventType, NewLoginEvent};
+use crate::model::recovery_codes::{NewRecoveryCode, RecoveryCodesDto};
+use crate::model::sessions::TokenPairDto;
+use crate::model::totp::{MfaLoginDto, NewUserTotp, TotpEnrollmentDto, UserTotp};
+use crate::model::users::UserStatus;
+use crate::repository::login_event_repository::LoginEventRepository;
+use crate::repository::pending_login_repository::PendingLoginRepository;
+use crate::repository::recovery_code_repository::RecoveryCodeRepository;
+use crate::repository::role_repository::RoleRepository;
+use crate::repository::session_repository::SessionRepository;
+use crate::repository::totp_repository::TotpRepository;
+use crate::repository::user_repository::UserRepository;
+use crate::repository::webauthn_repository::WebAuthnRepository;
+use crate::service;
+use crate::service::session_service::SessionServiceError;
+use crate::service::user_service::UserServiceError;
+use crate::totp;
+use crate::totp::TotpError;
+use rand::Rng;
+
+const MAX_ATTEMPTS: i32 = 5; // Second factor attempts per mfa token, the lockout counts them too
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LEN: usize = 10; // Shown as two groups of five, e.g. "k7m2p-x9rtq"
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789"; // Without look-alikes
+
+#[derive(Debug)]
+pub enum MfaServiceError {
+    GenericDatabaseError(diesel::result::Error),
+    AuthorizationError(AuthorizationError),
+    AlreadyEnrolled,
+    NotEnrolled,
+    CodeInvalid,
+    TotpError(TotpError),
+    SessionServiceError(SessionServiceError),
+    UserServiceError(UserServiceError),
+}
+
+impl From<diesel::result::Error> for MfaServiceError {
+    fn from(error: diesel::result::Error) -> MfaServiceError {
+        MfaServiceError::GenericDatabaseError(error)
+    }
+}
+
+impl From<TotpError> for MfaServiceError {
+    fn from(error: TotpError) -> MfaServiceError {
+        error!("{}", error);
+        MfaServiceError::TotpError(error)
+    }
+}
+
+impl From<SessionServiceError> for MfaServiceError {
+    fn from(error: SessionServiceError) -> MfaServiceError {
+        MfaServiceError::SessionServiceError(error)
+    }
+}
+
+impl From<UserServiceError> for MfaServiceError {
+    fn from(error: UserServiceError) -> MfaServiceError {
+        MfaServiceError::UserServiceError(error)
+    }
+}
+
+impl MfaServiceError {
+    /// Without details, as stored in login events
+    pub fn reason(&self) -> &'static str {
+        match self {
+            MfaServiceError::AuthorizationError(e) => e.reason(),
+            MfaServiceError::CodeInvalid => "CodeInvalid",
+            MfaServiceError::SessionServiceError(e) => e.reason(),
+            _ => "InternalError",
+        }
+    }
+}
+
+/// Starts enrollment with a new secret. Until it is confirmed with a first code, enrolling again
+/// replaces the secret and logins don't ask for it.
+pub fn enroll_totp<R>(
+    repositories: &R,
+    user_id: i64,
+    mfa_config: &Mfa,
+) -> Result<TotpEnrollmentDto, MfaServiceError>
+where
+    R: UserRepository + TotpRepository,
+{
+    let user = repositories
+        .get_user_by_id(user_id)?
+        .ok_or(MfaServiceError::AuthorizationError(
+            AuthorizationError::UserDoesNotExist,
+        ))?;
+
+    let secret = totp::generate_secret()?;
+    let new_totp = NewUserTotp {
+        user_id,
+        secret: totp::encrypt(&mfa_config.totp_encryption_key, user_id, &secret)?,
+    };
+    if repositories.create_or_replace_unconfirmed_totp(&new_totp)? == 0 {
+        return Err(MfaServiceError::AlreadyEnrolled);
+    }
+
+    Ok(TotpEnrollmentDto {
+        otpauth_uri: totp::otpauth_uri(&mfa_config.totp_issuer, &user.username, &secret),
+    })
+}
+
+/// Activates the secret and hands out a new set of recovery codes
+pub fn confirm_totp<R>(
+    repositories: &R,
+    user_id: i64,
+    code: &str,
+    mfa_config: &Mfa,
+    argon2_config: &argon2::Config,
+) -> Result<RecoveryCodesDto, MfaServiceError>
+where
+    R: TotpRepository + RecoveryCodeRepository,
+{
+    let user_totp = repositories
+        .get_totp_by_user_id(user_id)?
+        .ok_or(MfaServiceError::CodeInvalid)?;
+    if user_totp.confirmed_at.is_some() {
+        return Err(MfaServiceError::AlreadyEnrolled);
+    }
+
+    let step = verify_code(&user_totp, code, mfa_config)?;
+    if repositories.confirm_totp(user_id, step)? == 0 {
+        return Err(MfaServiceError::AlreadyEnrolled);
+    }
+    create_recovery_codes(repositories, user_id, argon2_config)
+}
+
+/// Replaces all recovery codes of the user, only for users with a second factor
+pub fn regenerate_recovery_codes<R>(
+    repositories: &R,
+    user_id: i64,
+    argon2_config: &argon2::Config,
+) -> Result<RecoveryCodesDto, MfaServiceError>
+where
+    R: TotpRepository + WebAuthnRepository + RecoveryCodeRepository,
+{
+    let totp_confirmed = repositories
+        .get_totp_by_user_id(user_id)?
+        .is_some_and(|user_totp| user_totp.confirmed_at.is_some());
+    if !totp_confirmed && repositories.get_credentials_by_user_id(user_id)?.is_empty() {
+        return Err(MfaServiceError::NotEnrolled);
+    }
+    create_recovery_codes(repositories, user_id, argon2_config)
+}
+
+/// Second step of the login, exchanges the mfa token of the first step and a TOTP or recovery
+/// code for a session. The token is spent by a successful login or after MAX_ATTEMPTS codes.
+pub fn complete_mfa_login<R>(
+    repositories: &R,
+    mfa_login_dto: &MfaLoginDto,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+    mfa_config: &Mfa,
+    lockout_config: &Lockout,
+    session_limits: &SessionLimits,
+) -> Result<TokenPairDto, MfaServiceError>
+where
+    R: UserRepository
+        + SessionRepository
+        + RoleRepository
+        + TotpRepository
+        + RecoveryCodeRepository
+        + PendingLoginRepository
+        + LoginEventRepository,
+{
+    let mut login_event = NewLoginEvent::new(LoginEventType::SecondFactor, client_info);
+    let result = verify_second_factor(
+        repositories,
+        mfa_login_dto,
+        client_info,
+        &mut login_event,
+        token_config,
+        mfa_config,
+        lockout_config,
+        session_limits,
+    );
+    let failure_reason = result.as_ref().err().map(MfaServiceError::reason);
+    service::login_event_service::record_login_event(repositories, login_event, failure_reason);
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+fn verify_second_factor<R>(
+    repositories: &R,
+    mfa_login_dto: &MfaLoginDto,
+    client_info: &ClientInfo,
+    login_event: &mut NewLoginEvent,
+    token_config: &Jwt,
+    mfa_config: &Mfa,
+    lockout_config: &Lockout,
+    session_limits: &SessionLimits,
+) -> Result<TokenPairDto, MfaServiceError>
+where
+    R: UserRepository
+        + SessionRepository
+        + RoleRepository
+        + TotpRepository
+        + RecoveryCodeRepository
+        + PendingLoginRepository,
+{
+    let claims = auth::decode_mfa_jwt(&mfa_login_dto.mfa_token, token_config)
+        .map_err(MfaServiceError::AuthorizationError)?;
+    login_event.user_id = Some(claims.mfa_user_id);
+    login_event.set_platform(&claims.platform, &claims.sub_platform);
+    let user = match repositories.get_user_by_id(claims.mfa_user_id)? {
+        Some(user) if user.status == UserStatus::Active as i32 => user,
+        _ => {
+            return Err(MfaServiceError::AuthorizationError(
+                AuthorizationError::UserDoesNotExist,
+            ))
+        }
+    };
+
+    service::session_service::verify_not_locked(&user)
+        .map_err(MfaServiceError::AuthorizationError)?;
+    match repositories.count_pending_login_attempt(claims.jti)? {
+        Some(attempts) if attempts <= MAX_ATTEMPTS => {}
+        _ => {
+            return Err(MfaServiceError::AuthorizationError(
+                AuthorizationError::MfaTokenSpent,
+            ))
+        }
+    }
+
+    match use_second_factor(repositories, user.id, &mfa_login_dto.code, mfa_config) {
+        Err(MfaServiceError::CodeInvalid) => {
+            let locked_until = service::session_service::record_failed_attempt(
+                repositories,
+                user.id,
+                lockout_config,
+            )?;
+            return Err(match locked_until {
+                Some(locked_until) => MfaServiceError::AuthorizationError(
+                    AuthorizationError::AccountLocked(locked_until),
+                ),
+                None => MfaServiceError::CodeInvalid,
+            });
+        }
+        result => result?,
+    }
+    if repositories.delete_pending_login(claims.jti)? == 0 {
+        // A concurrent request completed the login with another code
+        return Err(MfaServiceError::AuthorizationError(
+            AuthorizationError::MfaTokenSpent,
+        ));
+    }
+    service::session_service::reset_failed_attempts(repositories, &user)?;
+
+    Ok(service::session_service::create_session_token_pair(
+        repositories,
+        user.id,
+        &claims.platform,
+        &claims.sub_platform,
+        client_info,
+        token_config,
+        session_limits,
+    )?)
+}
+
+/// Spends a TOTP or recovery code of the user
+fn use_second_factor<R>(
+    repositories: &R,
+    user_id: i64,
+    code: &str,
+    mfa_config: &Mfa,
+) -> Result<(), MfaServiceError>
+where
+    R: TotpRepository + RecoveryCodeRepository,
+{
+    let recovery_code = normalize_recovery_code(code);
+    if recovery_code.len() == RECOVERY_CODE_LEN {
+        return use_recovery_code(repositories, user_id, &recovery_code);
+    }
+    let user_totp = match repositories.get_totp_by_user_id(user_id)? {
+        Some(user_totp) if user_totp.confirmed_at.is_some() => user_totp,
+        _ => return Err(MfaServiceError::CodeInvalid),
+    };
+    let step = verify_code(&user_totp, code, mfa_config)?;
+    if repositories.use_totp_step(user_id, step)? == 0 {
+        // A concurrent login used the same code
+        return Err(MfaServiceError::CodeInvalid);
+    }
+    Ok(())
+}
+
+/// Returns the time step of the code. Codes are single use, so nothing at or before the last
+/// used step is accepted.
+fn verify_code(user_totp: &UserTotp, code: &str, mfa_config: &Mfa) -> Result<i64, MfaServiceError> {
+    let secret = totp::decrypt(
+        &mfa_config.totp_encryption_key,
+        user_totp.user_id,
+        &user_totp.secret,
+    )?;
+    match totp::verify(&secret, code, chrono::Utc::now().timestamp()) {
+        Some(step) if user_totp.last_used_step.is_none_or(|last| step > last) => Ok(step),
+        _ => Err(MfaServiceError::CodeInvalid),
+    }
+}
+
+/// Also called when the first passkey is registered
+pub fn create_recovery_codes(
+    recovery_code_repository: &impl RecoveryCodeRepository,
+    user_id: i64,
+    argon2_config: &argon2::Config,
+) -> Result<RecoveryCodesDto, MfaServiceError> {
+    let mut rng = rand::rngs::OsRng;
+    let mut recovery_codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    let mut new_recovery_codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code: String = (0..RECOVERY_CODE_LEN)
+            .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0, RECOVERY_CODE_ALPHABET.len())] as char)
+            .collect();
+        new_recovery_codes.push(NewRecoveryCode {
+            user_id,
+            code_hash: service::user_service::hash_password(code.as_bytes(), argon2_config)?,
+        });
+        let (first, second) = code.split_at(RECOVERY_CODE_LEN / 2);
+        recovery_codes.push(format!("{}-{}", first, second));
+    }
+
+    recovery_code_repository.replace_recovery_codes(user_id, &new_recovery_codes)?;
+    Ok(RecoveryCodesDto { recovery_codes })
+}
+
+/// Codes may be typed without the dash, in upper case or with spaces
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn use_recovery_code(
+    recovery_code_repository: &impl RecoveryCodeRepository,
+    user_id: i64,
+    code: &str,
+) -> Result<(), MfaServiceError> {
+    for recovery_code in recovery_code_repository.get_unused_recovery_codes(user_id)? {
+        if service::user_service::validate_password(&recovery_code.code_hash, code.as_bytes())? {
+            if recovery_code_repository.use_recovery_code(recovery_code.id)? == 0 {
+                // A concurrent login used the same code
+                return Err(MfaServiceError::CodeInvalid);
+            }
+            return Ok(());
+        }
+    }
+    Err(MfaServiceError::CodeInvalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{self, AuthorizationError};
+    use crate::configuration::{Lockout, Mfa, SessionLimits};
+    use crate::model::login_events::ClientInfo;
+    use crate::model::pending_logins::NewPendingLogin;
+    use crate::model::sessions::TokenPairDto;
+    use crate::model::totp::MfaLoginDto;
+    use crate::model::users::UserStatus;
+    use crate::repository::pending_login_repository::PendingLoginRepository;
+    use crate::repository::totp_repository::TotpRepository;
+    use crate::test_support::{argon2_config, jwt_config, mfa_config, user, MockRepo};
+    use crate::totp;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn seeded_repo() -> MockRepo {
+        MockRepo::with_users(vec![user(2, UserStatus::Active)])
+    }
+
+    /// Enrolled with a secret whose current code is still unused
+    fn enrolled_repo(config: &Mfa) -> MockRepo {
+        let repo = seeded_repo();
+        super::enroll_totp(&repo, 2, config).unwrap();
+        repo.totp.borrow_mut()[0].confirmed_at = Some(Utc::now());
+        repo
+    }
+
+    /// What the password step of the login hands out
+    fn mfa_token(repo: &MockRepo) -> String {
+        let pending_login = NewPendingLogin {
+            id: Uuid::new_v4(),
+            user_id: 2,
+            expires_at: Utc::now() + chrono::Duration::minutes(5),
+        };
+        repo.create_pending_login(&pending_login).unwrap();
+        let config = jwt_config();
+        let claims = auth::MfaClaims {
+            exp: pending_login.expires_at.timestamp(),
+            iat: Utc::now().timestamp(),
+            iss: config.issuer.clone(),
+            aud: config.audiences.clone(),
+            jti: pending_login.id,
+            mfa_user_id: 2,
+            platform: String::from("web"),
+            sub_platform: String::from("firefox"),
+        };
+        auth::encode_mfa_jwt(&claims, &config).unwrap()
+    }
+
+    fn lockout_config(threshold: i32) -> Lockout {
+        Lockout {
+            threshold,
+            base_duration_ms: 60000,
+            max_duration_ms: 3600000,
+        }
+    }
+
+    fn complete_mfa_login(
+        repo: &MockRepo,
+        mfa_token: &str,
+        code: &str,
+        lockout_config: &Lockout,
+    ) -> Result<TokenPairDto, super::MfaServiceError> {
+        let mfa_login_dto = MfaLoginDto {
+            mfa_token: mfa_token.to_owned(),
+            code: code.to_owned(),
+        };
+        super::complete_mfa_login(
+            repo,
+            &mfa_login_dto,
+            &ClientInfo::default(),
+            &jwt_config(),
+            &mfa_config(),
+            lockout_config,
+            &SessionLimits::default(),
+        )
+    }
+
+    /// What the authenticator app would show right now
+    fn current_code(repo: &MockRepo, config: &Mfa) -> String {
+        let totp = repo.get_totp_by_user_id(2).unwrap().unwrap();
+        let secret = totp::decrypt(&config.totp_encryption_key, 2, &totp.secret).unwrap();
+        totp::current_code(&secret)
+    }
+
+    #[test]
+    fn enroll_and_confirm_totp() {
+        let repo = seeded_repo();
+        let config = mfa_config();
+        let enrollment = super::enroll_totp(&repo, 2, &config).unwrap();
+        assert!(enrollment
+            .otpauth_uri
+            .starts_with("otpauth://totp/User%20Service:USER2?secret="));
+
+        let argon2_config = argon2_config();
+        let result = super::confirm_totp(&repo, 2, "000000", &config, &argon2_config);
+        assert!(matches!(result, Err(super::MfaServiceError::CodeInvalid)));
+
+        let code = current_code(&repo, &config);
+        let recovery_codes = super::confirm_totp(&repo, 2, &code, &config, &argon2_config).unwrap();
+        assert!(repo.totp.borrow()[0].confirmed_at.is_some());
+        assert_eq!(10, recovery_codes.recovery_codes.len());
+        assert_eq!(10, repo.recovery_codes.borrow().len());
+
+        // A confirmed secret is not replaced
+        let result = super::enroll_totp(&repo, 2, &config);
+        assert!(matches!(
+            result,
+            Err(super::MfaServiceError::AlreadyEnrolled)
+        ));
+    }
+
+    #[test]
+    fn used_code_is_rejected() {
+        let repo = seeded_repo();
+        let config = mfa_config();
+        super::enroll_totp(&repo, 2, &config).unwrap();
+        let code = current_code(&repo, &config);
+        super::confirm_totp(&repo, 2, &code, &config, &argon2_config()).unwrap();
+
+        let totp = repo.get_totp_by_user_id(2).unwrap().unwrap();
+        let result = super::verify_code(&totp, &code, &config);
+        assert!(matches!(result, Err(super::MfaServiceError::CodeInvalid)));
+    }
+
+    #[test]
+    fn recovery_code_is_single_use() {
+        let repo = seeded_repo();
+        let recovery_codes = super::create_recovery_codes(&repo, 2, &argon2_config()).unwrap();
+        let code = &recovery_codes.recovery_codes[3];
+        assert_eq!(11, code.len());
+        assert!(repo
+            .recovery_codes
+            .borrow()
+            .iter()
+            .all(|stored| !stored.code_hash.contains(code.as_str())));
+
+        let normalized = super::normalize_recovery_code(&code.to_uppercase());
+        // Another user's code is not accepted
+        let result = super::use_recovery_code(&repo, 3, &normalized);
+        assert!(matches!(result, Err(super::MfaServiceError::CodeInvalid)));
+        super::use_recovery_code(&repo, 2, &normalized).unwrap();
+        let result = super::use_recovery_code(&repo, 2, &normalized);
+        assert!(matches!(result, Err(super::MfaServiceError::CodeInvalid)));
+
+        let other = super::normalize_recovery_code(&recovery_codes.recovery_codes[4]);
+        super::use_recovery_code(&repo, 2, &other).unwrap();
+    }
+
+    #[test]
+    fn complete_mfa_login_with_totp() {
+        let config = mfa_config();
+        let repo = enrolled_repo(&config);
+        repo.users.borrow_mut()[0].failed_login_attempts = 2;
+        let token = mfa_token(&repo);
+
+        let code = current_code(&repo, &config);
+        let token_pair = complete_mfa_login(&repo, &token, &code, &lockout_config(5)).unwrap();
+        assert_eq!(0, repo.user(2).failed_login_attempts);
+        let claims = auth::decode_session_jwt(&token_pair.session_token.token, &jwt_config());
+        assert_eq!(2, claims.unwrap().user_id);
+        assert_eq!(1, repo.sessions.borrow().len());
+        assert!(repo.pending_logins.borrow().is_empty());
+        assert_eq!(vec![None], repo.failure_reasons());
+
+        // The mfa token is spent, even with another valid code
+        let recovery_codes = super::create_recovery_codes(&repo, 2, &argon2_config()).unwrap();
+        let result = complete_mfa_login(
+            &repo,
+            &token,
+            &recovery_codes.recovery_codes[0],
+            &lockout_config(5),
+        );
+        assert!(matches!(
+            result,
+            Err(super::MfaServiceError::AuthorizationError(
+                AuthorizationError::MfaTokenSpent
+            ))
+        ));
+        assert_eq!(1, repo.sessions.borrow().len());
+    }
+
+    #[test]
+    fn wrong_codes_lock_user() {
+        let config = mfa_config();
+        let repo = enrolled_repo(&config);
+        let token = mfa_token(&repo);
+
+        for failed_attempts in 1..3 {
+            let result = complete_mfa_login(&repo, &token, "aaaaa-aaaaa", &lockout_config(3));
+            assert!(matches!(result, Err(super::MfaServiceError::CodeInvalid)));
+            assert_eq!(failed_attempts, repo.user(2).failed_login_attempts);
+        }
+        let result = complete_mfa_login(&repo, &token, "aaaaa-aaaaa", &lockout_config(3));
+        assert!(matches!(
+            result,
+            Err(super::MfaServiceError::AuthorizationError(
+                AuthorizationError::AccountLocked(_)
+            ))
+        ));
+
+        // Locked, so even the right code is rejected without being checked
+        let code = current_code(&repo, &config);
+        let result = complete_mfa_login(&repo, &token, &code, &lockout_config(3));
+        assert!(matches!(
+            result,
+            Err(super::MfaServiceError::AuthorizationError(
+                AuthorizationError::AccountLocked(_)
+            ))
+        ));
+        assert!(repo
+            .get_totp_by_user_id(2)
+            .unwrap()
+            .unwrap()
+            .last_used_step
+            .is_none());
+        assert!(repo.sessions.borrow().is_empty());
+    }
+
+    #[test]
+    fn mfa_token_attempts_are_capped() {
+        let config = mfa_config();
+        let repo = enrolled_repo(&config);
+        let token = mfa_token(&repo);
+
+        for _ in 0..super::MAX_ATTEMPTS {
+            let result = complete_mfa_login(&repo, &token, "aaaaa-aaaaa", &lockout_config(100));
+            assert!(matches!(result, Err(super::MfaServiceError::CodeInvalid)));
+        }
+        let code = current_code(&repo, &config);
+        let result = complete_mfa_login(&repo, &token, &code, &lockout_config(100));
+        assert!(matches!(
+            result,
+            Err(super::MfaServiceError::AuthorizationError(
+                AuthorizationError::MfaTokenSpent
+            ))
+        ));
+        assert!(repo.sessions.borrow().is_empty());
+
+        // A new password login hands out a new token
+        let token = mfa_token(&repo);
+        assert!(complete_mfa_login(&repo, &token, &code, &lockout_config(100)).is_ok());
+    }
+}
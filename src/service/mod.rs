@@ -1,2 +1,8 @@
+pub mod admin_service;
+pub mod login_event_service;
+pub mod mfa_service;
+pub mod oauth_service;
+pub mod role_service;
 pub mod session_service;
 pub mod user_service;
+pub mod webauthn_service;
@@ -0,0 +1,1400 @@
+use crate::auth;
+use crate::configuration::{Jwt, JwtKey, OAuth, SessionLimits};
+use crate::model::login_events::ClientInfo;
+use crate::model::oauth::{
+    AuthorizeRequestDto, ClientStatus, ConsentDecisionDto, ConsentDto, IntrospectionDto,
+    IntrospectionRequestDto, NewAuthorizationCode, OAuthClient, OAuthTokenDto,
+    OpenIdConfigurationDto, TokenRequestDto, TokenTypeHint, UserInfoDto, GRANT_AUTHORIZATION_CODE,
+    GRANT_CLIENT_CREDENTIALS, GRANT_REFRESH_TOKEN, OAUTH_PLATFORM, SCOPE_EMAIL, SCOPE_OPENID,
+    SCOPE_PROFILE, SUPPORTED_SCOPES,
+};
+use crate::model::sessions::{Session, SessionStatus, TokenPairDto};
+use crate::model::users::{User, UserStatus};
+use crate::repository::login_event_repository::LoginEventRepository;
+use crate::repository::oauth_repository::OAuthRepository;
+use crate::repository::role_repository::RoleRepository;
+use crate::repository::session_repository::SessionRepository;
+use crate::repository::user_repository::UserRepository;
+use crate::service;
+use crate::service::session_service::SessionServiceError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::Rng;
+use uuid::Uuid;
+
+/// Answer to an authorization request
+#[derive(Debug)]
+pub enum AuthorizeOutcome {
+    ConsentRequired(ConsentDto),
+    Redirect(String), // Errors the client learns about at its redirect URI
+}
+
+#[derive(Debug)]
+pub enum OAuthServiceError {
+    GenericDatabaseError(diesel::result::Error),
+    InvalidClientCredentials,
+    InvalidClient,
+    InvalidRedirectUri,
+    InvalidRequest(&'static str),
+    InvalidGrant,
+    InvalidToken,
+    LoginRequired,
+    UnauthorizedClient,
+    UnsupportedGrantType,
+    SessionServiceError(SessionServiceError),
+    HashingError,
+}
+
+impl From<diesel::result::Error> for OAuthServiceError {
+    fn from(error: diesel::result::Error) -> OAuthServiceError {
+        OAuthServiceError::GenericDatabaseError(error)
+    }
+}
+
+impl From<SessionServiceError> for OAuthServiceError {
+    fn from(error: SessionServiceError) -> OAuthServiceError {
+        match error {
+            // An unusable refresh token is an invalid grant for the client (RFC 6749 section 5.2)
+            SessionServiceError::AuthorizationError(_) => OAuthServiceError::InvalidGrant,
+            _ => OAuthServiceError::SessionServiceError(error),
+        }
+    }
+}
+
+pub fn authenticate_introspection_client(
+    oauth_config: &OAuth,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<(), OAuthServiceError> {
+    match oauth_config.introspection_clients.get(client_id) {
+        Some(secret) if constant_time_eq(secret.as_bytes(), client_secret.as_bytes()) => Ok(()),
+        _ => Err(OAuthServiceError::InvalidClientCredentials),
+    }
+}
+
+pub fn introspect<R>(
+    repositories: &R,
+    request: &IntrospectionRequestDto,
+    token_config: &Jwt,
+) -> Result<IntrospectionDto, OAuthServiceError>
+where
+    R: OAuthRepository + SessionRepository,
+{
+    // The hint only decides which token type is tried first (RFC 7662 section 2.1)
+    let introspection = match request.token_type_hint {
+        Some(TokenTypeHint::RefreshToken) => {
+            match introspect_session_token(repositories, &request.token, token_config)? {
+                Some(introspection) => Some(introspection),
+                None => introspect_access_token(repositories, &request.token, token_config)?,
+            }
+        }
+        _ => match introspect_access_token(repositories, &request.token, token_config)? {
+            Some(introspection) => Some(introspection),
+            None => introspect_session_token(repositories, &request.token, token_config)?,
+        },
+    };
+
+    Ok(introspection.unwrap_or_else(IntrospectionDto::inactive))
+}
+
+/// None if the token is no valid access token at all
+fn introspect_access_token<R>(
+    repositories: &R,
+    token: &str,
+    token_config: &Jwt,
+) -> Result<Option<IntrospectionDto>, OAuthServiceError>
+where
+    R: OAuthRepository + SessionRepository,
+{
+    let introspection = match auth::decode_access_jwt(token, token_config) {
+        Ok(auth::AccessToken::User(claims)) => {
+            if active_session(repositories, claims.sid, claims.user_id, None)?.is_none() {
+                return Ok(Some(IntrospectionDto::inactive()));
+            }
+            IntrospectionDto {
+                active: true,
+                scope: non_empty(&claims.scope),
+                client_id: None,
+                token_type: Some(TokenTypeHint::AccessToken),
+                sub: Some(claims.user_id.to_string()),
+                exp: Some(claims.exp),
+                iat: Some(claims.iat),
+                iss: Some(claims.iss),
+                aud: Some(claims.aud),
+            }
+        }
+        // Client tokens have no session, they die with their client
+        Ok(auth::AccessToken::Client(claims)) => {
+            if get_active_client(repositories, &claims.client_id).is_err() {
+                return Ok(Some(IntrospectionDto::inactive()));
+            }
+            IntrospectionDto {
+                active: true,
+                scope: None,
+                client_id: Some(claims.client_id.clone()),
+                token_type: Some(TokenTypeHint::AccessToken),
+                sub: Some(claims.client_id),
+                exp: Some(claims.exp),
+                iat: Some(claims.iat),
+                iss: Some(claims.iss),
+                aud: Some(claims.aud),
+            }
+        }
+        Err(_) => return Ok(None),
+    };
+    Ok(Some(introspection))
+}
+
+/// None if the token is no valid session token at all
+fn introspect_session_token(
+    session_repository: &impl SessionRepository,
+    token: &str,
+    token_config: &Jwt,
+) -> Result<Option<IntrospectionDto>, OAuthServiceError> {
+    let claims = match auth::decode_session_jwt(token, token_config) {
+        Ok(claims) => claims,
+        Err(_) => return Ok(None),
+    };
+    let generation = Some(claims.generation);
+    let session = match active_session(
+        session_repository,
+        claims.session_id,
+        claims.user_id,
+        generation,
+    )? {
+        Some(session) => session,
+        None => return Ok(Some(IntrospectionDto::inactive())),
+    };
+
+    Ok(Some(IntrospectionDto {
+        active: true,
+        scope: session.scope.as_deref().and_then(non_empty),
+        client_id: None,
+        token_type: Some(TokenTypeHint::RefreshToken),
+        sub: Some(claims.user_id.to_string()),
+        exp: Some(claims.exp),
+        iat: Some(claims.iat),
+        iss: Some(claims.iss),
+        aud: Some(claims.aud),
+    }))
+}
+
+fn active_session(
+    session_repository: &impl SessionRepository,
+    session_id: Uuid,
+    user_id: i64,
+    generation: Option<i32>,
+) -> Result<Option<Session>, OAuthServiceError> {
+    Ok(session_repository
+        .get_session_by_id(session_id)?
+        .filter(|session| {
+            session.user_id == user_id
+                && session.status == SessionStatus::Active as i32
+                && session.expires_at > chrono::Utc::now()
+                && generation.is_none_or(|generation| generation == session.generation)
+        }))
+}
+
+/// Tokens without scopes are reported without the field
+fn non_empty(scope: &str) -> Option<String> {
+    Some(scope.to_owned()).filter(|scope| !scope.is_empty())
+}
+
+/// Validates an authorization request (RFC 6749 section 4.1.1) of the user logged in with the
+/// session cookie and asks for their consent. Errors are only reported to the redirect URI once
+/// it has been verified, otherwise they are returned directly.
+pub fn authorize<R>(
+    repositories: &R,
+    session_token: &str,
+    request: &AuthorizeRequestDto,
+    token_config: &Jwt,
+    oauth_config: &OAuth,
+) -> Result<AuthorizeOutcome, OAuthServiceError>
+where
+    R: OAuthRepository + UserRepository + SessionRepository,
+{
+    let client = get_active_client(repositories, &request.client_id)?;
+    if !client.redirect_uris.contains(&request.redirect_uri) {
+        return Err(OAuthServiceError::InvalidRedirectUri);
+    }
+    let code_challenge = match validate_code_challenge(request) {
+        Ok(code_challenge) => code_challenge,
+        Err((error, description)) => {
+            return Ok(AuthorizeOutcome::Redirect(redirect_location(
+                &request.redirect_uri,
+                vec![
+                    ("error", error.to_owned()),
+                    ("error_description", description.to_owned()),
+                ],
+                &request.state,
+            )?))
+        }
+    };
+    let session = authenticate_user(repositories, session_token, token_config)?;
+
+    let now = chrono::Utc::now();
+    let claims = auth::ConsentClaims {
+        exp: (now + chrono::Duration::milliseconds(oauth_config.consent_exp_ms)).timestamp(),
+        iat: now.timestamp(),
+        iss: token_config.issuer.clone(),
+        aud: vec![token_config.audience.clone()],
+        consent_sid: session.id,
+        client_id: client.id.clone(),
+        redirect_uri: request.redirect_uri.clone(),
+        code_challenge: code_challenge.to_owned(),
+        scope: granted_scope(request.scope.as_deref(), token_config),
+        nonce: request.nonce.clone(),
+        state: request.state.clone(),
+    };
+    let consent_token = auth::encode_consent_jwt(&claims, token_config).map_err(|e| {
+        error!("{}", e);
+        OAuthServiceError::SessionServiceError(SessionServiceError::JwtGenerationError)
+    })?;
+
+    Ok(AuthorizeOutcome::ConsentRequired(ConsentDto {
+        client_id: client.id,
+        client_name: client.name,
+        scope: claims.scope,
+        consent_token,
+    }))
+}
+
+/// Issues a code for the request the user consented to, or tells the client they refused.
+/// Returns the location the user agent is sent to.
+pub fn decide_consent<R>(
+    repositories: &R,
+    session_token: &str,
+    decision: &ConsentDecisionDto,
+    token_config: &Jwt,
+    oauth_config: &OAuth,
+) -> Result<String, OAuthServiceError>
+where
+    R: OAuthRepository + UserRepository + SessionRepository,
+{
+    let session = authenticate_user(repositories, session_token, token_config)?;
+    let claims = auth::decode_consent_jwt(&decision.consent_token, token_config)
+        .map_err(|_| OAuthServiceError::InvalidRequest("consent_token is invalid"))?;
+    if claims.consent_sid != session.id {
+        return Err(OAuthServiceError::InvalidRequest(
+            "consent_token is invalid",
+        ));
+    }
+    // The client may have been disabled or changed since the request was shown
+    let client = get_active_client(repositories, &claims.client_id)?;
+    if !client.redirect_uris.contains(&claims.redirect_uri) {
+        return Err(OAuthServiceError::InvalidRedirectUri);
+    }
+
+    let params = if decision.approve {
+        let code = generate_code();
+        repositories.delete_expired_authorization_codes()?;
+        repositories.create_authorization_code(&NewAuthorizationCode {
+            code_hash: hash_code(&code),
+            client_id: client.id,
+            user_id: session.user_id,
+            redirect_uri: claims.redirect_uri.clone(),
+            code_challenge: claims.code_challenge,
+            expires_at: chrono::Utc::now()
+                + chrono::Duration::milliseconds(oauth_config.authorization_code_exp_ms),
+            scope: claims.scope,
+            nonce: claims.nonce,
+        })?;
+        vec![("code", code)]
+    } else {
+        vec![
+            ("error", String::from("access_denied")),
+            (
+                "error_description",
+                String::from("The user denied the request"),
+            ),
+        ]
+    };
+    redirect_location(&claims.redirect_uri, params, &claims.state)
+}
+
+/// Refresh tokens of OAuth clients are session tokens too, but only logins count here
+fn authenticate_user<R>(
+    repositories: &R,
+    session_token: &str,
+    token_config: &Jwt,
+) -> Result<Session, OAuthServiceError>
+where
+    R: UserRepository + SessionRepository,
+{
+    match service::session_service::authenticate_session(repositories, session_token, token_config)
+    {
+        Ok(session) if session.platform != OAUTH_PLATFORM => Ok(session),
+        Ok(_) | Err(SessionServiceError::AuthorizationError(_)) => {
+            Err(OAuthServiceError::LoginRequired)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn redirect_location(
+    redirect_uri: &str,
+    mut params: Vec<(&str, String)>,
+    state: &Option<String>,
+) -> Result<String, OAuthServiceError> {
+    if let Some(state) = state {
+        params.push(("state", state.clone()));
+    }
+    let query = serde_urlencoded::to_string(&params).map_err(|e| {
+        error!("{}", e);
+        OAuthServiceError::InvalidRequest("Invalid parameters")
+    })?;
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    Ok(format!("{}{}{}", redirect_uri, separator, query))
+}
+
+/// Clients with a secret have to present it, public clients only identify themselves
+pub fn authenticate_client(
+    oauth_repository: &impl OAuthRepository,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> Result<OAuthClient, OAuthServiceError> {
+    let client = get_active_client(oauth_repository, client_id)?;
+    if let Some(hash) = &client.client_secret {
+        let secret = client_secret.ok_or(OAuthServiceError::InvalidClient)?;
+        let valid = argon2::verify_encoded(hash, secret.as_bytes()).map_err(|e| {
+            error!("{}", e);
+            OAuthServiceError::HashingError
+        })?;
+        if !valid {
+            return Err(OAuthServiceError::InvalidClient);
+        }
+    }
+    Ok(client)
+}
+
+pub fn create_token<R>(
+    repositories: &R,
+    client: &OAuthClient,
+    request: &TokenRequestDto,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+    session_limits: &SessionLimits,
+) -> Result<OAuthTokenDto, OAuthServiceError>
+where
+    R: OAuthRepository + UserRepository + SessionRepository + RoleRepository + LoginEventRepository,
+{
+    let grant_type = request.grant_type.as_str();
+    if ![
+        GRANT_AUTHORIZATION_CODE,
+        GRANT_REFRESH_TOKEN,
+        GRANT_CLIENT_CREDENTIALS,
+    ]
+    .contains(&grant_type)
+    {
+        return Err(OAuthServiceError::UnsupportedGrantType);
+    }
+    if !client
+        .grant_types
+        .iter()
+        .any(|allowed| allowed == grant_type)
+    {
+        return Err(OAuthServiceError::UnauthorizedClient);
+    }
+
+    match grant_type {
+        GRANT_AUTHORIZATION_CODE => exchange_authorization_code(
+            repositories,
+            client,
+            request,
+            client_info,
+            token_config,
+            session_limits,
+        ),
+        GRANT_REFRESH_TOKEN => Ok(token_response(refresh(
+            repositories,
+            client,
+            request,
+            client_info,
+            token_config,
+        )?)),
+        _ => issue_client_token(client, token_config),
+    }
+}
+
+/// OAuth clients get the claims of the scopes the user granted them and need `openid`, the
+/// user's own apps get all claims
+pub fn get_user_info<R>(
+    repositories: &R,
+    claims: &auth::AccessClaims,
+) -> Result<UserInfoDto, OAuthServiceError>
+where
+    R: UserRepository + SessionRepository,
+{
+    let user = match repositories.get_user_by_id(claims.user_id)? {
+        Some(user) if user.status == UserStatus::Active as i32 => user,
+        _ => return Err(OAuthServiceError::InvalidToken),
+    };
+    let scope = match repositories.get_session_by_id(claims.sid)? {
+        Some(session) if session.platform == OAUTH_PLATFORM => session.scope.unwrap_or_default(),
+        _ => SUPPORTED_SCOPES.join(" "),
+    };
+    let scopes: Vec<&str> = scope.split_whitespace().collect();
+    if !scopes.contains(&SCOPE_OPENID) {
+        return Err(OAuthServiceError::InvalidToken);
+    }
+    Ok(user_info(&user, &scopes))
+}
+
+/// Endpoints are derived from the issuer, which has to be the public URL of the service for
+/// OpenID Connect clients to accept it
+pub fn openid_configuration(token_config: &Jwt) -> OpenIdConfigurationDto {
+    let issuer = token_config.issuer.trim_end_matches('/');
+    let strings = |values: &[&str]| values.iter().map(|&v| v.to_owned()).collect();
+    let signing_algorithms = id_token_key(token_config)
+        .map(|key| vec![format!("{:?}", key.algorithm)])
+        .unwrap_or_default();
+
+    OpenIdConfigurationDto {
+        issuer: token_config.issuer.clone(),
+        authorization_endpoint: format!("{}/api/v1/oauth/authorize", issuer),
+        token_endpoint: format!("{}/api/v1/oauth/token", issuer),
+        userinfo_endpoint: format!("{}/api/v1/oauth/userinfo", issuer),
+        jwks_uri: format!("{}/.well-known/jwks.json", issuer),
+        introspection_endpoint: format!("{}/api/v1/oauth/introspect", issuer),
+        scopes_supported: strings(&supported_scopes(token_config)),
+        response_types_supported: strings(&["code"]),
+        grant_types_supported: strings(&[
+            GRANT_AUTHORIZATION_CODE,
+            GRANT_REFRESH_TOKEN,
+            GRANT_CLIENT_CREDENTIALS,
+        ]),
+        subject_types_supported: strings(&["public"]),
+        id_token_signing_alg_values_supported: signing_algorithms,
+        token_endpoint_auth_methods_supported: strings(&[
+            "client_secret_basic",
+            "client_secret_post",
+            "none",
+        ]),
+        code_challenge_methods_supported: strings(&["S256"]),
+        claims_supported: strings(&[
+            "iss",
+            "sub",
+            "aud",
+            "exp",
+            "iat",
+            "nonce",
+            "email",
+            "email_verified",
+            "birthdate",
+        ]),
+    }
+}
+
+fn exchange_authorization_code<R>(
+    repositories: &R,
+    client: &OAuthClient,
+    request: &TokenRequestDto,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+    session_limits: &SessionLimits,
+) -> Result<OAuthTokenDto, OAuthServiceError>
+where
+    R: OAuthRepository + UserRepository + SessionRepository + RoleRepository,
+{
+    let code = required(&request.code, "code is missing")?;
+    let redirect_uri = required(&request.redirect_uri, "redirect_uri is missing")?;
+    let code_verifier = required(&request.code_verifier, "code_verifier is missing")?;
+
+    let authorization_code = repositories
+        .consume_authorization_code(&hash_code(code))?
+        .ok_or(OAuthServiceError::InvalidGrant)?;
+    if authorization_code.client_id != client.id
+        || &authorization_code.redirect_uri != redirect_uri
+        || authorization_code.expires_at < chrono::Utc::now()
+        || !verify_code_verifier(code_verifier, &authorization_code.code_challenge)
+    {
+        return Err(OAuthServiceError::InvalidGrant);
+    }
+
+    let user = repositories
+        .get_user_by_id(authorization_code.user_id)?
+        .ok_or(OAuthServiceError::InvalidGrant)?;
+    if user.status != UserStatus::Active as i32 {
+        return Err(OAuthServiceError::InvalidGrant);
+    }
+
+    let mut response = token_response(service::session_service::create_oauth_session_token_pair(
+        repositories,
+        user.id,
+        &client.id,
+        &authorization_code.scope,
+        client_info,
+        token_config,
+        session_limits,
+    )?);
+    let scopes: Vec<&str> = authorization_code.scope.split_whitespace().collect();
+    // The access key may have been changed to a secret since the code was issued
+    if scopes.contains(&SCOPE_OPENID) && id_token_key(token_config).is_some() {
+        response.id_token = Some(generate_id_token(
+            &user,
+            client,
+            authorization_code.nonce.clone(),
+            &scopes,
+            token_config,
+        )?);
+    }
+    if !scopes.is_empty() {
+        response.scope = Some(authorization_code.scope);
+    }
+    Ok(response)
+}
+
+fn refresh<R>(
+    repositories: &R,
+    client: &OAuthClient,
+    request: &TokenRequestDto,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+) -> Result<TokenPairDto, OAuthServiceError>
+where
+    R: UserRepository + SessionRepository + RoleRepository + LoginEventRepository,
+{
+    let refresh_token = required(&request.refresh_token, "refresh_token is missing")?;
+    // Refresh tokens are bound to the client they were issued to
+    let claims = auth::decode_session_jwt(refresh_token, token_config)
+        .map_err(|_| OAuthServiceError::InvalidGrant)?;
+    match repositories.get_session_by_id(claims.session_id)? {
+        Some(session)
+            if session.platform == OAUTH_PLATFORM && session.sub_platform == client.id => {}
+        _ => return Err(OAuthServiceError::InvalidGrant),
+    }
+
+    Ok(service::session_service::create_access_token_and_refresh(
+        repositories,
+        refresh_token,
+        client_info,
+        token_config,
+    )?)
+}
+
+/// Client credentials grant (RFC 6749 section 4.4): the client acts on its own behalf, so there is
+/// no session and no refresh token. Public clients can't prove who they are.
+fn issue_client_token(
+    client: &OAuthClient,
+    token_config: &Jwt,
+) -> Result<OAuthTokenDto, OAuthServiceError> {
+    if client.client_secret.is_none() {
+        return Err(OAuthServiceError::UnauthorizedClient);
+    }
+
+    let now = chrono::Utc::now();
+    let expiration = now + chrono::Duration::milliseconds(token_config.access_exp_ms);
+    let claims = auth::ClientClaims {
+        exp: expiration.timestamp(),
+        iat: now.timestamp(),
+        iss: token_config.issuer.clone(),
+        aud: vec![token_config.audience.clone()],
+        client_id: client.id.clone(),
+    };
+
+    Ok(OAuthTokenDto {
+        expires_in: token_config.access_exp_ms / 1000,
+        access_token: sign(&claims, token_config)?,
+        token_type: String::from("Bearer"),
+        refresh_token: None,
+        scope: None,
+        id_token: None,
+    })
+}
+
+fn token_response(token_pair: TokenPairDto) -> OAuthTokenDto {
+    OAuthTokenDto {
+        expires_in: (token_pair.access_token.expiration - chrono::Utc::now()).num_seconds(),
+        access_token: token_pair.access_token.token,
+        token_type: String::from("Bearer"),
+        refresh_token: Some(token_pair.session_token.token),
+        scope: None,
+        id_token: None,
+    }
+}
+
+fn generate_id_token(
+    user: &User,
+    client: &OAuthClient,
+    nonce: Option<String>,
+    scopes: &[&str],
+    token_config: &Jwt,
+) -> Result<String, OAuthServiceError> {
+    let now = chrono::Utc::now();
+    let claims = auth::IdTokenClaims {
+        exp: (now + chrono::Duration::milliseconds(token_config.access_exp_ms)).timestamp(),
+        iat: now.timestamp(),
+        iss: token_config.issuer.clone(),
+        aud: client.id.clone(),
+        nonce,
+        user_info: user_info(user, scopes),
+    };
+    sign(&claims, token_config)
+}
+
+/// ID tokens are signed with the current access key, so clients can verify them against the
+/// JWKS. Secrets aren't published there, without an asymmetric key there is no `openid` scope.
+fn id_token_key(token_config: &Jwt) -> Option<&JwtKey> {
+    token_config
+        .access_keys
+        .get(&token_config.access_key_id)
+        .filter(|key| !key.uses_secret())
+}
+
+fn supported_scopes(token_config: &Jwt) -> Vec<&'static str> {
+    let openid = id_token_key(token_config).is_some();
+    SUPPORTED_SCOPES
+        .iter()
+        .copied()
+        .filter(|&scope| openid || scope != SCOPE_OPENID)
+        .collect()
+}
+
+fn sign(claims: &impl serde::Serialize, token_config: &Jwt) -> Result<String, OAuthServiceError> {
+    auth::encode_access_jwt(claims, token_config).map_err(|e| {
+        error!("{}", e);
+        OAuthServiceError::SessionServiceError(SessionServiceError::JwtGenerationError)
+    })
+}
+
+/// Claims the scopes allow (OpenID Connect Core section 5.4)
+fn user_info(user: &User, scopes: &[&str]) -> UserInfoDto {
+    let email = scopes.contains(&SCOPE_EMAIL);
+    UserInfoDto {
+        sub: user.id.to_string(),
+        email: email.then(|| user.email.clone()),
+        email_verified: email.then_some(user.status != UserStatus::NotVerified as i32),
+        birthdate: scopes
+            .contains(&SCOPE_PROFILE)
+            .then(|| user.date_of_birth.format("%Y-%m-%d").to_string()),
+    }
+}
+
+/// Unknown scopes are ignored rather than rejected (RFC 6749 section 3.3)
+fn granted_scope(requested: Option<&str>, token_config: &Jwt) -> String {
+    let supported_scopes = supported_scopes(token_config);
+    let mut scopes: Vec<&str> = vec![];
+    for scope in requested.unwrap_or_default().split_whitespace() {
+        if supported_scopes.contains(&scope) && !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    scopes.join(" ")
+}
+
+fn get_active_client(
+    oauth_repository: &impl OAuthRepository,
+    client_id: &str,
+) -> Result<OAuthClient, OAuthServiceError> {
+    match oauth_repository.get_client_by_id(client_id)? {
+        Some(client) if client.status == ClientStatus::Active as i32 => Ok(client),
+        _ => Err(OAuthServiceError::InvalidClient),
+    }
+}
+
+fn required<'a>(
+    value: &'a Option<String>,
+    description: &'static str,
+) -> Result<&'a String, OAuthServiceError> {
+    value
+        .as_ref()
+        .ok_or(OAuthServiceError::InvalidRequest(description))
+}
+
+/// Only S256 is accepted, "plain" would leak the verifier to anyone reading the redirect
+fn validate_code_challenge(
+    request: &AuthorizeRequestDto,
+) -> Result<&str, (&'static str, &'static str)> {
+    if request.response_type != "code" {
+        return Err((
+            "unsupported_response_type",
+            "Only response_type code is supported",
+        ));
+    }
+    if request.code_challenge_method.as_deref() != Some("S256") {
+        return Err(("invalid_request", "code_challenge_method must be S256"));
+    }
+    match request.code_challenge.as_deref() {
+        Some(challenge) if challenge.len() == 43 => Ok(challenge),
+        _ => Err(("invalid_request", "code_challenge is missing or invalid")),
+    }
+}
+
+/// code_challenge = BASE64URL(SHA256(code_verifier)) (RFC 7636 section 4.6)
+fn verify_code_verifier(code_verifier: &str, code_challenge: &str) -> bool {
+    let valid_format = (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c));
+    valid_format
+        && constant_time_eq(
+            hash_code(code_verifier).as_bytes(),
+            code_challenge.as_bytes(),
+        )
+}
+
+fn generate_code() -> String {
+    rand::rngs::OsRng
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(43)
+        .collect()
+}
+
+/// Only hashes of authorization codes are stored
+fn hash_code(code: &str) -> String {
+    URL_SAFE_NO_PAD.encode(ring::digest::digest(&ring::digest::SHA256, code.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthorizeOutcome, OAuthServiceError};
+    use crate::auth;
+    use crate::configuration::{Jwt, OAuth, SessionLimits};
+    use crate::model::login_events::ClientInfo;
+    use crate::model::oauth::{
+        AuthorizeRequestDto, ClientStatus, ConsentDecisionDto, ConsentDto, IntrospectionDto,
+        IntrospectionRequestDto, OAuthClient, OAuthTokenDto, TokenRequestDto, TokenTypeHint,
+    };
+    use crate::model::sessions::{Session, SessionStatus};
+    use crate::model::users::UserStatus;
+    use crate::service;
+    use crate::test_support;
+    use crate::test_support::{ec_key, user, MockRepo, EC_PUBLIC_KEY};
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    // Example of RFC 7636 Appendix B
+    const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    const CODE_CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    const REDIRECT_URI: &str = "https://shop.example.com/callback";
+
+    /// Access and ID tokens are signed with an EC key, the openid scope needs one
+    fn jwt_config() -> Jwt {
+        let mut config = test_support::jwt_config();
+        config.access_key_id = String::from("v2");
+        config.access_keys.insert(String::from("v2"), ec_key());
+        config
+    }
+
+    fn seeded_repo() -> MockRepo {
+        let client = |id: &str, name: &str| OAuthClient {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            client_secret: None,
+            redirect_uris: vec![String::from(REDIRECT_URI)],
+            status: ClientStatus::Active as i32,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            grant_types: vec![
+                String::from("authorization_code"),
+                String::from("refresh_token"),
+            ],
+        };
+        MockRepo::with_users(vec![user(2, UserStatus::Active)])
+            .with_clients(vec![client("shop", "Shop"), client("tools", "Tools")])
+    }
+
+    /// Session token of a login of user 2, as sent in the session cookie
+    fn login(repo: &MockRepo) -> String {
+        service::session_service::create_session_token_pair(
+            repo,
+            2,
+            "web",
+            "browser",
+            &ClientInfo::default(),
+            &jwt_config(),
+            &SessionLimits::default(),
+        )
+        .unwrap()
+        .session_token
+        .token
+    }
+
+    fn consent(repo: &MockRepo, session_token: &str, request: &AuthorizeRequestDto) -> ConsentDto {
+        match super::authorize(repo, session_token, request, &jwt_config(), &oauth_config()) {
+            Ok(AuthorizeOutcome::ConsentRequired(consent)) => consent,
+            other => panic!("Expected consent, got {:?}", other),
+        }
+    }
+
+    fn decide(
+        repo: &MockRepo,
+        session_token: &str,
+        consent: &ConsentDto,
+        approve: bool,
+    ) -> Result<String, OAuthServiceError> {
+        let decision = ConsentDecisionDto {
+            consent_token: consent.consent_token.clone(),
+            approve,
+        };
+        super::decide_consent(
+            repo,
+            session_token,
+            &decision,
+            &jwt_config(),
+            &oauth_config(),
+        )
+    }
+
+    fn query(location: &str) -> HashMap<String, String> {
+        let (_, query) = location.split_once('?').unwrap();
+        serde_urlencoded::from_str(query).unwrap()
+    }
+
+    /// Code of user 2 for the shop after consenting to the authorization request
+    fn authorization_code(repo: &MockRepo) -> String {
+        let session_token = login(repo);
+        let consent = consent(repo, &session_token, &authorize_request(REDIRECT_URI));
+        let location = decide(repo, &session_token, &consent, true).unwrap();
+        query(&location)["code"].clone()
+    }
+
+    fn exchange(
+        repo: &MockRepo,
+        client_id: &str,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<OAuthTokenDto, OAuthServiceError> {
+        token(
+            repo,
+            client_id,
+            TokenRequestDto {
+                grant_type: String::from("authorization_code"),
+                code: Some(code.to_owned()),
+                redirect_uri: Some(String::from(REDIRECT_URI)),
+                code_verifier: Some(code_verifier.to_owned()),
+                refresh_token: None,
+                client_id: Some(client_id.to_owned()),
+                client_secret: None,
+            },
+        )
+    }
+
+    fn refresh(
+        repo: &MockRepo,
+        client_id: &str,
+        refresh_token: &str,
+    ) -> Result<OAuthTokenDto, OAuthServiceError> {
+        token(
+            repo,
+            client_id,
+            TokenRequestDto {
+                grant_type: String::from("refresh_token"),
+                code: None,
+                redirect_uri: None,
+                code_verifier: None,
+                refresh_token: Some(refresh_token.to_owned()),
+                client_id: Some(client_id.to_owned()),
+                client_secret: None,
+            },
+        )
+    }
+
+    fn token(
+        repo: &MockRepo,
+        client_id: &str,
+        request: TokenRequestDto,
+    ) -> Result<OAuthTokenDto, OAuthServiceError> {
+        let client = super::authenticate_client(repo, client_id, None)?;
+        super::create_token(
+            repo,
+            &client,
+            &request,
+            &ClientInfo::default(),
+            &jwt_config(),
+            &SessionLimits::default(),
+        )
+    }
+
+    fn authorize_request(redirect_uri: &str) -> AuthorizeRequestDto {
+        AuthorizeRequestDto {
+            response_type: String::from("code"),
+            client_id: String::from("shop"),
+            redirect_uri: redirect_uri.to_owned(),
+            code_challenge: Some(CODE_CHALLENGE.to_owned()),
+            code_challenge_method: Some(String::from("S256")),
+            state: Some(String::from("xyz")),
+            scope: Some(String::from("openid email offline_access")),
+            nonce: Some(String::from("n-0S6_WzA2Mj")),
+        }
+    }
+
+    fn oauth_config() -> OAuth {
+        OAuth {
+            introspection_clients: std::collections::HashMap::from([(
+                String::from("gateway"),
+                String::from("gateway-secret"),
+            )]),
+            authorization_code_exp_ms: 60000,
+            consent_exp_ms: 600000,
+        }
+    }
+
+    fn service_client(client_secret: Option<String>) -> OAuthClient {
+        OAuthClient {
+            id: String::from("billing-job"),
+            name: String::from("Billing"),
+            client_secret,
+            redirect_uris: vec![],
+            status: ClientStatus::Active as i32,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            grant_types: vec![String::from("client_credentials")],
+        }
+    }
+
+    #[test]
+    fn issue_client_token() {
+        let config = jwt_config();
+        let client = service_client(Some(String::from("$argon2i$hash")));
+        let token = super::issue_client_token(&client, &config).unwrap();
+
+        assert!(token.refresh_token.is_none());
+        assert_eq!(900, token.expires_in);
+        match auth::decode_access_jwt(&token.access_token, &config) {
+            Ok(auth::AccessToken::Client(claims)) => assert_eq!("billing-job", claims.client_id),
+            other => panic!("Expected client claims, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn issue_client_token_to_public_client() {
+        let result = super::issue_client_token(&service_client(None), &jwt_config());
+        assert!(matches!(
+            result,
+            Err(super::OAuthServiceError::UnauthorizedClient)
+        ));
+    }
+
+    #[test]
+    fn generate_id_token() {
+        let config = jwt_config();
+        let user = user(2, UserStatus::Active);
+        let client = service_client(None);
+        let nonce = Some(String::from("n-0S6_WzA2Mj"));
+        let scopes = ["openid", "email"];
+        let id_token = super::generate_id_token(&user, &client, nonce, &scopes, &config).unwrap();
+
+        let header = jsonwebtoken::decode_header(&id_token).unwrap();
+        assert_eq!(Some(String::from("v2")), header.kid);
+        let mut validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::ES256);
+        validation.set_audience(&["billing-job"]);
+        validation.set_issuer(&["user-service"]);
+        let claims = jsonwebtoken::decode::<serde_json::Value>(
+            &id_token,
+            &jsonwebtoken::DecodingKey::from_ec_pem(EC_PUBLIC_KEY.as_bytes()).unwrap(),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+        assert_eq!("2", claims["sub"]);
+        assert_eq!("USER2@EXAMPLE.COM", claims["email"]);
+        assert_eq!(true, claims["email_verified"]);
+        assert_eq!("n-0S6_WzA2Mj", claims["nonce"]);
+        // Without the profile scope
+        assert!(claims.get("birthdate").is_none());
+        // An ID token must not be usable as access token
+        assert!(auth::decode_access_jwt(&id_token, &config).is_err());
+    }
+
+    #[test]
+    fn granted_scope() {
+        assert_eq!(
+            "openid email",
+            super::granted_scope(Some("openid  email offline_access openid"), &jwt_config())
+        );
+        assert_eq!("", super::granted_scope(None, &jwt_config()));
+        // ID tokens can't be signed with a secret clients can't know
+        assert_eq!(
+            "email",
+            super::granted_scope(Some("openid email"), &test_support::jwt_config())
+        );
+    }
+
+    #[test]
+    fn openid_configuration() {
+        let mut config = jwt_config();
+        config.issuer = String::from("https://id.example.com/");
+        let discovery = super::openid_configuration(&config);
+        assert_eq!("https://id.example.com/", discovery.issuer);
+        assert_eq!(
+            "https://id.example.com/.well-known/jwks.json",
+            discovery.jwks_uri
+        );
+        assert_eq!(
+            vec![String::from("ES256")],
+            discovery.id_token_signing_alg_values_supported
+        );
+        assert!(discovery.scopes_supported.contains(&String::from("openid")));
+
+        let discovery = super::openid_configuration(&test_support::jwt_config());
+        assert!(discovery.id_token_signing_alg_values_supported.is_empty());
+        assert!(!discovery.scopes_supported.contains(&String::from("openid")));
+    }
+
+    #[test]
+    fn authenticate_introspection_client() {
+        let result =
+            super::authenticate_introspection_client(&oauth_config(), "gateway", "gateway-secret");
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn authenticate_introspection_client_wrong_secret() {
+        let config = oauth_config();
+        for (client_id, secret) in &[
+            ("gateway", "gateway-secre"),
+            ("gateway", "gateway-secret2"),
+            ("other", "gateway-secret"),
+        ] {
+            let result = super::authenticate_introspection_client(&config, client_id, secret);
+            assert!(matches!(
+                result,
+                Err(super::OAuthServiceError::InvalidClientCredentials)
+            ));
+        }
+    }
+
+    #[test]
+    fn authorize() {
+        let repo = seeded_repo();
+        let session_token = login(&repo);
+        let consent = consent(&repo, &session_token, &authorize_request(REDIRECT_URI));
+        assert_eq!("shop", consent.client_id);
+        assert_eq!("Shop", consent.client_name);
+        assert_eq!("openid email", consent.scope);
+        // Nothing is granted before the user consents
+        assert!(repo.authorization_codes.borrow().is_empty());
+
+        let location = decide(&repo, &session_token, &consent, true).unwrap();
+        assert!(location.starts_with("https://shop.example.com/callback?code="));
+        assert!(location.ends_with("&state=xyz"));
+        let codes = repo.authorization_codes.borrow();
+        assert_eq!(1, codes.len());
+        assert_eq!(2, codes[0].user_id);
+        assert_eq!("shop", codes[0].client_id);
+        assert_eq!(CODE_CHALLENGE, codes[0].code_challenge);
+        assert_eq!("openid email", codes[0].scope);
+        assert_eq!(Some(String::from("n-0S6_WzA2Mj")), codes[0].nonce);
+        assert!(!location.contains(&codes[0].code_hash));
+    }
+
+    #[test]
+    fn authorize_denied_consent() {
+        let repo = seeded_repo();
+        let session_token = login(&repo);
+        let consent = consent(&repo, &session_token, &authorize_request(REDIRECT_URI));
+        let location = decide(&repo, &session_token, &consent, false).unwrap();
+
+        let params = query(&location);
+        assert_eq!("access_denied", params["error"]);
+        assert_eq!("xyz", params["state"]);
+        assert!(!params.contains_key("code"));
+        assert!(repo.authorization_codes.borrow().is_empty());
+    }
+
+    #[test]
+    fn authorize_requires_login() {
+        let repo = seeded_repo();
+        let request = authorize_request(REDIRECT_URI);
+        let result = super::authorize(&repo, "no-token", &request, &jwt_config(), &oauth_config());
+        assert!(matches!(result, Err(OAuthServiceError::LoginRequired)));
+
+        // Refresh tokens of OAuth clients don't stand in for a login
+        let code = authorization_code(&repo);
+        let refresh_token = exchange(&repo, "shop", &code, CODE_VERIFIER)
+            .unwrap()
+            .refresh_token
+            .unwrap();
+        let result = super::authorize(
+            &repo,
+            &refresh_token,
+            &request,
+            &jwt_config(),
+            &oauth_config(),
+        );
+        assert!(matches!(result, Err(OAuthServiceError::LoginRequired)));
+    }
+
+    #[test]
+    fn consent_is_bound_to_session() {
+        let repo = seeded_repo();
+        let consent = consent(&repo, &login(&repo), &authorize_request(REDIRECT_URI));
+        let result = decide(&repo, &login(&repo), &consent, true);
+        assert!(matches!(result, Err(OAuthServiceError::InvalidRequest(_))));
+        assert!(repo.authorization_codes.borrow().is_empty());
+    }
+
+    #[test]
+    fn authorize_unregistered_redirect_uri() {
+        let repo = seeded_repo();
+        let request = authorize_request("https://evil.example.com/callback");
+        let result = super::authorize(
+            &repo,
+            &login(&repo),
+            &request,
+            &jwt_config(),
+            &oauth_config(),
+        );
+        assert!(matches!(result, Err(OAuthServiceError::InvalidRedirectUri)));
+    }
+
+    #[test]
+    fn authorize_plain_code_challenge() {
+        let repo = seeded_repo();
+        let mut request = authorize_request(REDIRECT_URI);
+        request.code_challenge_method = Some(String::from("plain"));
+        match super::authorize(
+            &repo,
+            &login(&repo),
+            &request,
+            &jwt_config(),
+            &oauth_config(),
+        ) {
+            Ok(AuthorizeOutcome::Redirect(location)) => assert!(
+                location.starts_with("https://shop.example.com/callback?error=invalid_request")
+            ),
+            other => panic!("Expected redirect, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn exchange_authorization_code() {
+        let repo = seeded_repo();
+        let code = authorization_code(&repo);
+        let token = exchange(&repo, "shop", &code, CODE_VERIFIER).unwrap();
+
+        assert_eq!(Some(String::from("openid email")), token.scope);
+        assert!(token.id_token.is_some());
+        match auth::decode_access_jwt(&token.access_token, &jwt_config()) {
+            Ok(auth::AccessToken::User(claims)) => {
+                assert_eq!(2, claims.user_id);
+                assert_eq!("openid email", claims.scope);
+            }
+            other => panic!("Expected user claims, got {:?}", other),
+        }
+        let session = &repo.sessions.borrow()[1];
+        assert_eq!(
+            ("oauth", "shop"),
+            (session.platform.as_str(), session.sub_platform.as_str())
+        );
+        assert_eq!(Some(String::from("openid email")), session.scope);
+
+        // Codes are redeemed once
+        let result = exchange(&repo, "shop", &code, CODE_VERIFIER);
+        assert!(matches!(result, Err(OAuthServiceError::InvalidGrant)));
+    }
+
+    #[test]
+    fn exchange_requires_code_verifier() {
+        let repo = seeded_repo();
+        let code = authorization_code(&repo);
+        let result = exchange(
+            &repo,
+            "shop",
+            &code,
+            "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXK",
+        );
+        assert!(matches!(result, Err(OAuthServiceError::InvalidGrant)));
+        // A failed attempt spends the code as well
+        let result = exchange(&repo, "shop", &code, CODE_VERIFIER);
+        assert!(matches!(result, Err(OAuthServiceError::InvalidGrant)));
+    }
+
+    #[test]
+    fn exchange_by_other_client() {
+        let repo = seeded_repo();
+        let code = authorization_code(&repo);
+        let result = exchange(&repo, "tools", &code, CODE_VERIFIER);
+        assert!(matches!(result, Err(OAuthServiceError::InvalidGrant)));
+    }
+
+    #[test]
+    fn refresh_is_bound_to_client() {
+        let repo = seeded_repo();
+        let code = authorization_code(&repo);
+        let refresh_token = exchange(&repo, "shop", &code, CODE_VERIFIER)
+            .unwrap()
+            .refresh_token
+            .unwrap();
+
+        let result = refresh(&repo, "tools", &refresh_token);
+        assert!(matches!(result, Err(OAuthServiceError::InvalidGrant)));
+        let token = refresh(&repo, "shop", &refresh_token).unwrap();
+        assert!(token.refresh_token.is_some());
+
+        // Session tokens of logins aren't refresh tokens of any client
+        let result = refresh(&repo, "shop", &login(&repo));
+        assert!(matches!(result, Err(OAuthServiceError::InvalidGrant)));
+    }
+
+    #[test]
+    fn verify_code_verifier() {
+        assert!(super::verify_code_verifier(CODE_VERIFIER, CODE_CHALLENGE));
+        assert!(!super::verify_code_verifier(
+            "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXK",
+            CODE_CHALLENGE
+        ));
+        assert!(!super::verify_code_verifier(CODE_CHALLENGE, CODE_CHALLENGE));
+    }
+
+    fn user_claims(access_token: &str) -> auth::AccessClaims {
+        match auth::decode_access_jwt(access_token, &jwt_config()) {
+            Ok(auth::AccessToken::User(claims)) => claims,
+            other => panic!("Expected user claims, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn access_tokens_name_the_client() {
+        let repo = seeded_repo();
+        let code = authorization_code(&repo);
+        let token = exchange(&repo, "shop", &code, CODE_VERIFIER).unwrap();
+        let claims = user_claims(&token.access_token);
+        assert_eq!(Some(String::from("shop")), claims.client_id);
+
+        let refreshed = refresh(&repo, "shop", &token.refresh_token.unwrap()).unwrap();
+        let claims = user_claims(&refreshed.access_token);
+        assert_eq!(Some(String::from("shop")), claims.client_id);
+        assert!(claims.roles.is_empty());
+    }
+
+    #[test]
+    fn user_info_is_limited_by_scope() {
+        let repo = seeded_repo();
+        let code = authorization_code(&repo);
+        let token = exchange(&repo, "shop", &code, CODE_VERIFIER).unwrap();
+        let user_info = super::get_user_info(&repo, &user_claims(&token.access_token)).unwrap();
+        assert_eq!("2", user_info.sub);
+        assert_eq!(Some(String::from("USER2@EXAMPLE.COM")), user_info.email);
+        assert_eq!(Some(true), user_info.email_verified);
+        assert_eq!(None, user_info.birthdate);
+
+        // The user's own apps see every claim
+        let token_pair = service::session_service::create_session_token_pair(
+            &repo,
+            2,
+            "web",
+            "browser",
+            &ClientInfo::default(),
+            &jwt_config(),
+            &SessionLimits::default(),
+        )
+        .unwrap();
+        let user_info =
+            super::get_user_info(&repo, &user_claims(&token_pair.access_token.token)).unwrap();
+        assert_eq!(Some(String::from("1990-04-01")), user_info.birthdate);
+    }
+
+    #[test]
+    fn user_info_requires_openid_scope() {
+        let repo = seeded_repo();
+        let session_token = login(&repo);
+        let mut request = authorize_request(REDIRECT_URI);
+        request.scope = Some(String::from("email"));
+        let consent = consent(&repo, &session_token, &request);
+        let location = decide(&repo, &session_token, &consent, true).unwrap();
+        let token = exchange(&repo, "shop", &query(&location)["code"], CODE_VERIFIER).unwrap();
+        assert!(token.id_token.is_none());
+
+        let result = super::get_user_info(&repo, &user_claims(&token.access_token));
+        assert!(matches!(result, Err(OAuthServiceError::InvalidToken)));
+    }
+
+    fn introspect(repo: &MockRepo, token: &str) -> IntrospectionDto {
+        let request = IntrospectionRequestDto {
+            token: token.to_owned(),
+            token_type_hint: None,
+        };
+        super::introspect(repo, &request, &jwt_config()).unwrap()
+    }
+
+    /// Access and refresh token of the shop for user 2
+    fn oauth_tokens(repo: &MockRepo) -> (String, String) {
+        let code = authorization_code(repo);
+        let token = exchange(repo, "shop", &code, CODE_VERIFIER).unwrap();
+        (token.access_token, token.refresh_token.unwrap())
+    }
+
+    fn update_oauth_session(repo: &MockRepo, update: impl Fn(&mut Session)) {
+        for session in repo.sessions.borrow_mut().iter_mut() {
+            if session.platform == "oauth" {
+                update(session);
+            }
+        }
+    }
+
+    #[test]
+    fn introspect_oauth_tokens() {
+        let repo = seeded_repo();
+        let (access_token, refresh_token) = oauth_tokens(&repo);
+
+        let introspection = introspect(&repo, &access_token);
+        assert!(introspection.active);
+        assert_eq!(Some(TokenTypeHint::AccessToken), introspection.token_type);
+        assert_eq!(Some(String::from("openid email")), introspection.scope);
+        assert_eq!(Some(String::from("2")), introspection.sub);
+
+        let introspection = introspect(&repo, &refresh_token);
+        assert!(introspection.active);
+        assert_eq!(Some(TokenTypeHint::RefreshToken), introspection.token_type);
+        assert_eq!(Some(String::from("openid email")), introspection.scope);
+    }
+
+    #[test]
+    fn introspect_blacklisted_session() {
+        let repo = seeded_repo();
+        let (access_token, refresh_token) = oauth_tokens(&repo);
+        update_oauth_session(&repo, |session| {
+            session.status = SessionStatus::Blacklisted as i32
+        });
+
+        assert_eq!(
+            IntrospectionDto::inactive(),
+            introspect(&repo, &access_token)
+        );
+        assert_eq!(
+            IntrospectionDto::inactive(),
+            introspect(&repo, &refresh_token)
+        );
+    }
+
+    #[test]
+    fn introspect_expired_session() {
+        let repo = seeded_repo();
+        let (access_token, refresh_token) = oauth_tokens(&repo);
+        update_oauth_session(&repo, |session| {
+            session.expires_at = Utc::now() - chrono::Duration::minutes(1)
+        });
+
+        assert_eq!(
+            IntrospectionDto::inactive(),
+            introspect(&repo, &access_token)
+        );
+        assert_eq!(
+            IntrospectionDto::inactive(),
+            introspect(&repo, &refresh_token)
+        );
+    }
+
+    #[test]
+    fn introspect_rotated_refresh_token() {
+        let repo = seeded_repo();
+        let (_, refresh_token) = oauth_tokens(&repo);
+        let rotated = refresh(&repo, "shop", &refresh_token)
+            .unwrap()
+            .refresh_token
+            .unwrap();
+
+        assert_eq!(
+            IntrospectionDto::inactive(),
+            introspect(&repo, &refresh_token)
+        );
+        assert!(introspect(&repo, &rotated).active);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::model::roles::{NewUserRole, Role, RoleDto};
+use crate::repository::role_repository::RoleRepository;
+use crate::repository::user_repository::UserRepository;
+
+#[derive(Debug)]
+pub enum RoleServiceError {
+    GenericDatabaseError(diesel::result::Error),
+    RoleDoesNotExist,
+    UserDoesNotExist,
+}
+
+impl From<diesel::result::Error> for RoleServiceError {
+    fn from(error: diesel::result::Error) -> RoleServiceError {
+        RoleServiceError::GenericDatabaseError(error)
+    }
+}
+
+pub fn get_roles(role_repository: &impl RoleRepository) -> Result<Vec<RoleDto>, RoleServiceError> {
+    let role_permissions = role_repository.get_role_permissions()?;
+    Ok(role_repository
+        .get_roles()?
+        .into_iter()
+        .map(|role| RoleDto {
+            permissions: role_permissions
+                .iter()
+                .filter(|p| p.role_id == role.id)
+                .map(|p| p.permission.clone())
+                .collect(),
+            name: role.name,
+        })
+        .collect())
+}
+
+pub fn get_users_roles<R>(repositories: &R, user_id: i64) -> Result<Vec<String>, RoleServiceError>
+where
+    R: UserRepository + RoleRepository,
+{
+    repositories
+        .get_user_by_id(user_id)?
+        .ok_or(RoleServiceError::UserDoesNotExist)?;
+    Ok(repositories.get_role_names_by_user_id(user_id)?)
+}
+
+/// Takes effect with the next access token of the user, at the latest after access_exp_ms
+pub fn grant_role<R>(
+    repositories: &R,
+    admin_id: i64,
+    user_id: i64,
+    role_name: &str,
+) -> Result<(), RoleServiceError>
+where
+    R: UserRepository + RoleRepository,
+{
+    let role = get_role(repositories, user_id, role_name)?;
+    if repositories.add_user_role(&NewUserRole {
+        user_id,
+        role_id: role.id,
+    })? > 0
+    {
+        info!(
+            "User {} granted role {} to user {}",
+            admin_id, role.name, user_id
+        );
+    }
+    Ok(())
+}
+
+pub fn revoke_role<R>(
+    repositories: &R,
+    admin_id: i64,
+    user_id: i64,
+    role_name: &str,
+) -> Result<(), RoleServiceError>
+where
+    R: UserRepository + RoleRepository,
+{
+    let role = get_role(repositories, user_id, role_name)?;
+    if repositories.remove_user_role(user_id, role.id)? > 0 {
+        info!(
+            "User {} revoked role {} from user {}",
+            admin_id, role.name, user_id
+        );
+    }
+    Ok(())
+}
+
+fn get_role<R>(repositories: &R, user_id: i64, role_name: &str) -> Result<Role, RoleServiceError>
+where
+    R: UserRepository + RoleRepository,
+{
+    repositories
+        .get_user_by_id(user_id)?
+        .ok_or(RoleServiceError::UserDoesNotExist)?;
+    repositories
+        .get_role_by_name(role_name)?
+        .ok_or(RoleServiceError::RoleDoesNotExist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoleServiceError;
+    use crate::model::users::UserStatus;
+    use crate::repository::role_repository::RoleRepository;
+    use crate::test_support::{user, MockRepo};
+
+    fn seeded_repo() -> MockRepo {
+        MockRepo::with_users(vec![user(2, UserStatus::Active)]).with_roles()
+    }
+
+    #[test]
+    fn get_roles() {
+        let roles = super::get_roles(&MockRepo::default().with_roles()).unwrap();
+        assert_eq!(2, roles.len());
+        assert_eq!("admin", roles[0].name);
+        assert_eq!(vec!["roles:admin", "users:admin"], roles[0].permissions);
+        assert_eq!(vec!["users:admin"], roles[1].permissions);
+    }
+
+    #[test]
+    fn grant_and_revoke_role() {
+        let repo = seeded_repo();
+        super::grant_role(&repo, 1, 2, "support").unwrap();
+        assert_eq!(vec!["support"], repo.get_role_names_by_user_id(2).unwrap());
+        // Granting twice is a no-op
+        super::grant_role(&repo, 1, 2, "support").unwrap();
+        super::grant_role(&repo, 1, 2, "admin").unwrap();
+        assert_eq!(
+            vec!["admin", "support"],
+            repo.get_role_names_by_user_id(2).unwrap()
+        );
+
+        super::revoke_role(&repo, 1, 2, "admin").unwrap();
+        assert_eq!(vec!["support"], repo.get_role_names_by_user_id(2).unwrap());
+        // Revoking a role the user doesn't have is a no-op
+        super::revoke_role(&repo, 1, 2, "admin").unwrap();
+        assert_eq!(vec!["support"], repo.get_role_names_by_user_id(2).unwrap());
+    }
+
+    #[test]
+    fn unknown_role() {
+        let repo = seeded_repo();
+        let result = super::grant_role(&repo, 1, 2, "root");
+        assert!(matches!(result, Err(RoleServiceError::RoleDoesNotExist)));
+        let result = super::revoke_role(&repo, 1, 2, "root");
+        assert!(matches!(result, Err(RoleServiceError::RoleDoesNotExist)));
+        assert!(repo.user_roles.borrow().is_empty());
+    }
+
+    #[test]
+    fn unknown_user() {
+        let repo = seeded_repo();
+        let result = super::grant_role(&repo, 1, 3, "admin");
+        assert!(matches!(result, Err(RoleServiceError::UserDoesNotExist)));
+        let result = super::revoke_role(&repo, 1, 3, "admin");
+        assert!(matches!(result, Err(RoleServiceError::UserDoesNotExist)));
+        assert!(repo.user_roles.borrow().is_empty());
+    }
+}
@@ -1,11 +1,22 @@
 use crate::auth;
-use crate::configuration::Jwt;
+use crate::configuration::{Jwt, Lockout, Mfa, SessionLimitStrategy, SessionLimits, SessionReaper};
+use crate::model::login_events::{ClientInfo, LoginEventType, NewLoginEvent};
+use crate::model::oauth::OAUTH_PLATFORM;
+use crate::model::pending_logins::NewPendingLogin;
+use crate::model::roles::Authorities;
 use crate::model::sessions::{
-    LoginDto, NewSession, Session, SessionStatus, TokenDto, TokenPairDto,
+    LoginDto, MfaRequiredDto, NewSession, Session, SessionDto, SessionStatus, TokenDto,
+    TokenPairDto,
 };
-use crate::model::users::UserStatus;
+use crate::model::users::{User, UserStatus};
+use crate::repository::login_event_repository::LoginEventRepository;
+use crate::repository::pending_login_repository::PendingLoginRepository;
+use crate::repository::recovery_code_repository::RecoveryCodeRepository;
+use crate::repository::role_repository::RoleRepository;
 use crate::repository::session_repository::SessionRepository;
+use crate::repository::totp_repository::TotpRepository;
 use crate::repository::user_repository::UserRepository;
+use crate::repository::webauthn_repository::WebAuthnRepository;
 use crate::service;
 use chrono::Utc;
 use uuid::Uuid;
@@ -45,22 +56,178 @@ impl From<auth::AuthorizationError> for SessionServiceError {
     }
 }
 
+impl SessionServiceError {
+    /// Without details, as stored in login events
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SessionServiceError::AuthorizationError(e) => e.reason(),
+            _ => "InternalError",
+        }
+    }
+}
+
+/// Most recently seen first, the session of the caller is marked as current
 pub fn get_users_sessions(
     session_repository: &impl SessionRepository, //equal to register_user<R> where R: UserRepository
     user_id: i64,
-) -> Result<Vec<Session>, SessionServiceError> {
+    current_session_id: Uuid,
+) -> Result<Vec<SessionDto>, SessionServiceError> {
+    Ok(session_repository
+        .get_sessions_by_user_id(user_id)?
+        .into_iter()
+        .map(|session| SessionDto {
+            current: session.id == current_session_id,
+            session,
+        })
+        .collect())
+}
+
+pub fn blacklist_session(
+    session_repository: &impl SessionRepository,
+    user_id: i64,
+    session_id: Uuid,
+) -> Result<(), SessionServiceError> {
+    let session = match session_repository.get_session_by_id(session_id)? {
+        Some(session) => session,
+        None => return Err(auth::AuthorizationError::NoAuthorizationForAction.into()),
+    };
+    auth::verify_subject(user_id, session.user_id)?;
+    session_repository.update_session_status(session.id, SessionStatus::Blacklisted)?;
+    Ok(())
+}
+
+/// The token only serves to find the session. One that doesn't decode, e.g. because it expired,
+/// leaves nothing to log out.
+pub fn logout(
+    session_repository: &impl SessionRepository,
+    session_token: &str,
+    token_config: &Jwt,
+) -> Result<(), SessionServiceError> {
+    match auth::decode_session_jwt(session_token, token_config) {
+        Ok(claims) => blacklist_session(session_repository, claims.user_id, claims.session_id),
+        Err(_) => Ok(()),
+    }
+}
+
+pub fn blacklist_other_sessions(
+    session_repository: &impl SessionRepository,
+    user_id: i64,
+    session_token: &str,
+    token_config: &Jwt,
+) -> Result<usize, SessionServiceError> {
+    let claims = auth::decode_session_jwt(session_token, token_config)?;
+    auth::verify_subject(user_id, claims.user_id)?;
+    let session = get_valid_session(session_repository, &claims)?;
+
     session_repository
-        .get_sessions_by_user_id(user_id)
+        .blacklist_other_active_sessions(user_id, session.id)
         .map_err(|e| e.into())
 }
 
+/// Session of a session cookie, for endpoints the user calls from the browser while logged in
+pub fn authenticate_session<R>(
+    repositories: &R,
+    session_token: &str,
+    token_config: &Jwt,
+) -> Result<Session, SessionServiceError>
+where
+    R: UserRepository + SessionRepository,
+{
+    let claims = auth::decode_session_jwt(session_token, token_config)?;
+    let session = get_valid_session(repositories, &claims)?;
+    match repositories.get_user_by_id(session.user_id)? {
+        Some(user) if user.status == UserStatus::Active as i32 => Ok(session),
+        _ => Err(auth::AuthorizationError::NoAuthorizationForAction.into()),
+    }
+}
+
+/// Deletes sessions that expired or were blacklisted longer than the retention ago, in batches.
+/// Blacklisted sessions are kept at least as long as their access tokens, since revocation only
+/// sees sessions that are still in the table. Returns the number of deleted sessions.
+pub fn reap_sessions(
+    session_repository: &impl SessionRepository,
+    reaper_config: &SessionReaper,
+    token_config: &Jwt,
+) -> Result<usize, SessionServiceError> {
+    let retention_ms = reaper_config.retention_ms.max(token_config.access_exp_ms);
+    let before = chrono::Utc::now() - chrono::Duration::milliseconds(retention_ms);
+    let batch_size = reaper_config.batch_size.max(1);
+    let mut deleted = 0;
+    loop {
+        let batch = session_repository.delete_stale_sessions(before, batch_size)?;
+        deleted += batch;
+        if (batch as i64) < batch_size {
+            return Ok(deleted);
+        }
+    }
+}
+
+/// Outcome of a login with username and password
+pub enum LoginOutcome {
+    Authenticated(TokenPairDto),
+    MfaRequired(MfaRequiredDto), // Completed with a second factor, see mfa_service and webauthn_service
+}
+
+/// Logs in with username and password, every attempt is recorded as login event
 pub fn create_login_token_pair<R>(
     repositories: &R,
     login_dto: &LoginDto,
+    client_info: &ClientInfo,
     token_config: &Jwt,
-) -> Result<TokenPairDto, SessionServiceError>
+    mfa_config: &Mfa,
+    lockout_config: &Lockout,
+    session_limits: &SessionLimits,
+) -> Result<LoginOutcome, SessionServiceError>
 where
-    R: UserRepository + SessionRepository,
+    R: UserRepository
+        + SessionRepository
+        + RoleRepository
+        + TotpRepository
+        + WebAuthnRepository
+        + RecoveryCodeRepository
+        + PendingLoginRepository
+        + LoginEventRepository,
+{
+    let mut login_event = NewLoginEvent::new(LoginEventType::Password, client_info);
+    login_event.set_platform(&login_dto.platform, &login_dto.sub_platform);
+    let result = login(
+        repositories,
+        login_dto,
+        client_info,
+        &mut login_event,
+        token_config,
+        mfa_config,
+        lockout_config,
+        session_limits,
+    );
+    let failure_reason = match &result {
+        Ok(LoginOutcome::Authenticated(_)) => None,
+        Ok(LoginOutcome::MfaRequired(_)) => Some("SecondFactorRequired"),
+        Err(e) => Some(e.reason()),
+    };
+    service::login_event_service::record_login_event(repositories, login_event, failure_reason);
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+fn login<R>(
+    repositories: &R,
+    login_dto: &LoginDto,
+    client_info: &ClientInfo,
+    login_event: &mut NewLoginEvent,
+    token_config: &Jwt,
+    mfa_config: &Mfa,
+    lockout_config: &Lockout,
+    session_limits: &SessionLimits,
+) -> Result<LoginOutcome, SessionServiceError>
+where
+    R: UserRepository
+        + SessionRepository
+        + RoleRepository
+        + TotpRepository
+        + WebAuthnRepository
+        + RecoveryCodeRepository
+        + PendingLoginRepository,
 {
     let user = repositories
         .get_user_by_username(&login_dto.username)
@@ -68,31 +235,199 @@ where
         .ok_or(SessionServiceError::AuthorizationError(
             auth::AuthorizationError::UserDoesNotExist,
         ))?;
+    login_event.user_id = Some(user.id);
+
+    // Checked before the password, so a locked account doesn't tell whether guesses are right
+    verify_not_locked(&user)?;
 
     let result =
         service::user_service::validate_password(&user.password, login_dto.password.as_bytes())?;
 
     if result == false {
+        if let Some(locked_until) = record_failed_attempt(repositories, user.id, lockout_config)? {
+            return Err(SessionServiceError::AuthorizationError(
+                auth::AuthorizationError::AccountLocked(locked_until),
+            ));
+        }
         return Err(SessionServiceError::AuthorizationError(
             auth::AuthorizationError::PasswordInvalid,
         ));
     }
-
     if user.status != UserStatus::Active as i32 {
         return Err(SessionServiceError::AuthorizationError(
             auth::AuthorizationError::PasswordInvalid,
         )); // TODO: Own error
     }
 
+    let mut methods = vec![];
+    let totp = repositories.get_totp_by_user_id(user.id)?;
+    if totp.is_some_and(|totp| totp.confirmed_at.is_some()) {
+        methods.push(String::from("totp"));
+    }
+    if !repositories.get_credentials_by_user_id(user.id)?.is_empty() {
+        methods.push(String::from("webauthn"));
+    }
+    if !methods.is_empty() && !repositories.get_unused_recovery_codes(user.id)?.is_empty() {
+        methods.push(String::from("recovery_code"));
+    }
+    if !methods.is_empty() {
+        let pending_login = NewPendingLogin {
+            id: Uuid::new_v4(),
+            user_id: user.id,
+            expires_at: Utc::now() + chrono::Duration::milliseconds(mfa_config.pending_exp_ms),
+        };
+        repositories.delete_expired_pending_logins()?;
+        repositories.create_pending_login(&pending_login)?;
+        let mfa_token =
+            generate_mfa_token(&pending_login, login_dto, token_config).map_err(|e| {
+                error!("{}", e);
+                SessionServiceError::JwtGenerationError
+            })?;
+        return Ok(LoginOutcome::MfaRequired(MfaRequiredDto {
+            mfa_token,
+            methods,
+        }));
+    }
+
+    // Only a complete login resets the counter, the second factor has its own failures
+    reset_failed_attempts(repositories, &user)?;
+    create_session_token_pair(
+        repositories,
+        user.id,
+        &login_dto.platform,
+        &login_dto.sub_platform,
+        client_info,
+        token_config,
+        session_limits,
+    )
+    .map(LoginOutcome::Authenticated)
+}
+
+/// Fails while the user is locked out after too many failed attempts
+pub fn verify_not_locked(user: &User) -> Result<(), auth::AuthorizationError> {
+    match user.locked_until {
+        Some(locked_until) if locked_until > Utc::now() => {
+            Err(auth::AuthorizationError::AccountLocked(locked_until))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Counts a wrong password or second factor, returns the end of the lockout if the failure
+/// reached the threshold
+pub fn record_failed_attempt(
+    user_repository: &impl UserRepository,
+    user_id: i64,
+    lockout_config: &Lockout,
+) -> Result<Option<chrono::DateTime<Utc>>, SessionServiceError> {
+    let failed_attempts = user_repository.increment_failed_login_attempts(user_id)?;
+    if failed_attempts < lockout_config.threshold {
+        return Ok(None);
+    }
+    let locked_until = Utc::now() + lockout_duration(failed_attempts, lockout_config);
+    user_repository.lock_user(user_id, locked_until)?;
+    Ok(Some(locked_until))
+}
+
+/// Called once a login is complete, including its second factor
+pub fn reset_failed_attempts(
+    user_repository: &impl UserRepository,
+    user: &User,
+) -> Result<(), SessionServiceError> {
+    if user.failed_login_attempts > 0 {
+        user_repository.reset_failed_login_attempts(user.id)?;
+    }
+    Ok(())
+}
+
+/// Doubles with every failure past the threshold, up to the configured maximum
+fn lockout_duration(failed_attempts: i32, lockout_config: &Lockout) -> chrono::Duration {
+    let doublings = (failed_attempts - lockout_config.threshold).clamp(0, 30) as u32;
+    let duration_ms = lockout_config
+        .base_duration_ms
+        .saturating_mul(1 << doublings)
+        .min(lockout_config.max_duration_ms);
+    chrono::Duration::milliseconds(duration_ms)
+}
+
+/// Starts a new session for an already authenticated user
+pub fn create_session_token_pair<R>(
+    repositories: &R,
+    user_id: i64,
+    platform: &str,
+    sub_platform: &str,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+    session_limits: &SessionLimits,
+) -> Result<TokenPairDto, SessionServiceError>
+where
+    R: SessionRepository + RoleRepository,
+{
+    start_session(
+        repositories,
+        user_id,
+        platform,
+        sub_platform,
+        None,
+        client_info,
+        token_config,
+        session_limits,
+    )
+}
+
+/// Starts a session of an OAuth client for the user, its tokens carry the granted scope
+pub fn create_oauth_session_token_pair<R>(
+    repositories: &R,
+    user_id: i64,
+    client_id: &str,
+    scope: &str,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+    session_limits: &SessionLimits,
+) -> Result<TokenPairDto, SessionServiceError>
+where
+    R: SessionRepository + RoleRepository,
+{
+    start_session(
+        repositories,
+        user_id,
+        OAUTH_PLATFORM,
+        client_id,
+        Some(scope.to_owned()),
+        client_info,
+        token_config,
+        session_limits,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn start_session<R>(
+    repositories: &R,
+    user_id: i64,
+    platform: &str,
+    sub_platform: &str,
+    scope: Option<String>,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+    session_limits: &SessionLimits,
+) -> Result<TokenPairDto, SessionServiceError>
+where
+    R: SessionRepository + RoleRepository,
+{
+    enforce_session_limits(repositories, user_id, platform, session_limits)?;
+    let now = chrono::Utc::now();
     let session = NewSession {
         id: Uuid::new_v4(),
-        user_id: user.id,
-        platform: login_dto.platform.clone(),
-        sub_platform: login_dto.sub_platform.clone(),
-        refreshed_at: chrono::Utc::now(),
-        expires_at: chrono::Utc::now()
-            + chrono::Duration::milliseconds(token_config.session_exp_ms),
+        user_id,
+        platform: platform.to_owned(),
+        sub_platform: sub_platform.to_owned(),
+        refreshed_at: now,
+        expires_at: session_expiration(now, now, token_config),
         status: SessionStatus::Active as i32,
+        ip: client_info.ip.clone(),
+        user_agent: client_info.user_agent.clone(),
+        last_seen_at: now,
+        scope,
     };
     repositories.create_session(&session)?;
     // Cleanup
@@ -100,6 +435,7 @@ where
     let session_token = generate_session_token(
         &session.id,
         session.user_id,
+        0,
         session.expires_at,
         token_config,
     )
@@ -107,7 +443,15 @@ where
         error!("{}", e);
         SessionServiceError::JwtGenerationError
     })?;
-    let access_token = generate_access_token(session.user_id, token_config).map_err(|e| {
+    let authorities = get_authorities(repositories, session.user_id, platform, &session.scope)?;
+    let access_token = generate_access_token(
+        session.user_id,
+        &session.id,
+        authorities,
+        oauth_client_id(platform, sub_platform),
+        token_config,
+    )
+    .map_err(|e| {
         error!("{}", e);
         SessionServiceError::JwtGenerationError
     })?;
@@ -118,33 +462,164 @@ where
     })
 }
 
+/// Makes room for one more session of the user on the platform, or fails if the strategy is to
+/// reject. Concurrent logins may still exceed the limits by a session.
+fn enforce_session_limits(
+    session_repository: &impl SessionRepository,
+    user_id: i64,
+    platform: &str,
+    session_limits: &SessionLimits,
+) -> Result<(), SessionServiceError> {
+    if session_limits.max_sessions.is_none() && session_limits.max_sessions_per_platform.is_none() {
+        return Ok(());
+    }
+    let now = chrono::Utc::now();
+    let mut active_sessions: Vec<Session> = session_repository
+        .get_sessions_by_user_id(user_id)?
+        .into_iter()
+        .filter(|s| s.status == SessionStatus::Active as i32 && s.expires_at > now)
+        .collect();
+    active_sessions.sort_by_key(|s| s.refreshed_at);
+
+    // Evicting on the platform first also frees a slot of the overall limit
+    if let Some(max_sessions) = session_limits.max_sessions_per_platform {
+        let platform_session_ids: Vec<Uuid> = active_sessions
+            .iter()
+            .filter(|s| s.platform == platform)
+            .map(|s| s.id)
+            .collect();
+        let evicted = make_room(
+            session_repository,
+            &platform_session_ids,
+            max_sessions,
+            session_limits.strategy,
+        )?;
+        active_sessions.retain(|s| !evicted.contains(&s.id));
+    }
+    if let Some(max_sessions) = session_limits.max_sessions {
+        let session_ids: Vec<Uuid> = active_sessions.iter().map(|s| s.id).collect();
+        make_room(
+            session_repository,
+            &session_ids,
+            max_sessions,
+            session_limits.strategy,
+        )?;
+    }
+    Ok(())
+}
+
+/// Blacklists the first (least recently refreshed) sessions until one more fits under the limit.
+/// Returns the evicted sessions.
+fn make_room(
+    session_repository: &impl SessionRepository,
+    session_ids: &[Uuid],
+    max_sessions: usize,
+    strategy: SessionLimitStrategy,
+) -> Result<Vec<Uuid>, SessionServiceError> {
+    let excess = (session_ids.len() + 1)
+        .saturating_sub(max_sessions)
+        .min(session_ids.len());
+    if excess == 0 {
+        return Ok(vec![]);
+    }
+    if strategy == SessionLimitStrategy::Reject {
+        return Err(auth::AuthorizationError::SessionLimitReached.into());
+    }
+    let evicted = &session_ids[..excess];
+    for &session_id in evicted {
+        session_repository.update_session_status(session_id, SessionStatus::Blacklisted)?;
+    }
+    Ok(evicted.to_vec())
+}
+
+/// Every refresh is recorded as login event
 pub fn create_access_token_and_refresh<R>(
     repositories: &R,
     session_token: &str,
+    client_info: &ClientInfo,
     token_config: &Jwt,
 ) -> Result<TokenPairDto, SessionServiceError>
 where
-    R: UserRepository + SessionRepository,
+    R: UserRepository + SessionRepository + RoleRepository + LoginEventRepository,
+{
+    let mut login_event = NewLoginEvent::new(LoginEventType::Refresh, client_info);
+    let result = refresh(
+        repositories,
+        session_token,
+        client_info,
+        &mut login_event,
+        token_config,
+    );
+    let failure_reason = result.as_ref().err().map(SessionServiceError::reason);
+    service::login_event_service::record_login_event(repositories, login_event, failure_reason);
+    result
+}
+
+fn refresh<R>(
+    repositories: &R,
+    session_token: &str,
+    client_info: &ClientInfo,
+    login_event: &mut NewLoginEvent,
+    token_config: &Jwt,
+) -> Result<TokenPairDto, SessionServiceError>
+where
+    R: UserRepository + SessionRepository + RoleRepository,
 {
     let claims = auth::decode_session_jwt(session_token, token_config)?;
-    let session = match repositories.get_session_by_id(claims.session_id)? {
-        Some(session) => session,
-        None => return Err(auth::AuthorizationError::NoAuthorizationForAction.into()),
-    };
-    auth::verify_subject(claims.user_id, session.user_id)?;
-    if session.status == SessionStatus::Blacklisted as i32 {
-        return Err(auth::AuthorizationError::SessionTokenBlacklisted.into());
+    login_event.user_id = Some(claims.user_id);
+    let session = get_valid_session(repositories, &claims)?;
+    login_event.set_platform(&session.platform, &session.sub_platform);
+
+    // Suspending a user blacklists the sessions already, this catches anything that slipped past
+    match repositories.get_user_by_id(session.user_id)? {
+        Some(user) if user.status == UserStatus::Active as i32 => {}
+        _ => {
+            warn!(
+                "Session {} of inactive user {} was refreshed, blacklisting",
+                session.id, session.user_id
+            );
+            repositories.update_session_status(session.id, SessionStatus::Blacklisted)?;
+            return Err(auth::AuthorizationError::SessionTokenBlacklisted.into());
+        }
     }
 
     let now = chrono::Utc::now();
-    let new_exp = now + chrono::Duration::milliseconds(token_config.session_exp_ms);
-    repositories.update_refreshed_timestamps(claims.session_id, now, new_exp)?;
-    let session_token = generate_session_token(&session.id, session.user_id, new_exp, token_config)
-        .map_err(|e| {
-            error!("{}", e);
-            SessionServiceError::JwtGenerationError
-        })?;
-    let access_token = generate_access_token(claims.user_id, token_config).map_err(|e| {
+    if max_session_expiration(session.created_at, token_config) <= now {
+        return Err(auth::AuthorizationError::SessionExpired.into());
+    }
+    let new_exp = session_expiration(now, session.created_at, token_config);
+    let rotated =
+        repositories.rotate_session(session.id, session.generation, now, new_exp, client_info)?;
+    if rotated == 0 {
+        // Another refresh with the same token won the race
+        return Err(reject_reused_session_token(repositories, session.id)?);
+    }
+    let session_token = generate_session_token(
+        &session.id,
+        session.user_id,
+        session.generation + 1,
+        new_exp,
+        token_config,
+    )
+    .map_err(|e| {
+        error!("{}", e);
+        SessionServiceError::JwtGenerationError
+    })?;
+    // Reloaded on every refresh, so role changes reach the user within access_exp_ms
+    let authorities = get_authorities(
+        repositories,
+        session.user_id,
+        &session.platform,
+        &session.scope,
+    )?;
+    let access_token = generate_access_token(
+        session.user_id,
+        &session.id,
+        authorities,
+        oauth_client_id(&session.platform, &session.sub_platform),
+        token_config,
+    )
+    .map_err(|e| {
         error!("{}", e);
         SessionServiceError::JwtGenerationError
     })?;
@@ -155,54 +630,709 @@ where
     })
 }
 
+/// Idle timeout from now, but never past the maximum lifetime of the session
+fn session_expiration(
+    now: chrono::DateTime<Utc>,
+    created_at: chrono::DateTime<Utc>,
+    token_config: &Jwt,
+) -> chrono::DateTime<Utc> {
+    let idle_expiration = now + chrono::Duration::milliseconds(token_config.session_exp_ms);
+    idle_expiration.min(max_session_expiration(created_at, token_config))
+}
+
+fn max_session_expiration(
+    created_at: chrono::DateTime<Utc>,
+    token_config: &Jwt,
+) -> chrono::DateTime<Utc> {
+    created_at + chrono::Duration::milliseconds(token_config.session_max_lifetime_ms)
+}
+
+/// Loads the session referenced by the claims, rejecting blacklisted sessions and rotated tokens.
+fn get_valid_session(
+    session_repository: &impl SessionRepository,
+    claims: &auth::SessionClaims,
+) -> Result<Session, SessionServiceError> {
+    let session = match session_repository.get_session_by_id(claims.session_id)? {
+        Some(session) => session,
+        None => return Err(auth::AuthorizationError::NoAuthorizationForAction.into()),
+    };
+    auth::verify_subject(claims.user_id, session.user_id)?;
+    if session.status == SessionStatus::Blacklisted as i32 {
+        return Err(auth::AuthorizationError::SessionTokenBlacklisted.into());
+    }
+    if session.generation != claims.generation {
+        return Err(reject_reused_session_token(session_repository, session.id)?);
+    }
+    Ok(session)
+}
+
+/// A session token of an older generation was presented again, so it has most likely been stolen.
+/// The whole session is blacklisted, which also locks out whoever holds the current token.
+fn reject_reused_session_token(
+    session_repository: &impl SessionRepository,
+    session_id: Uuid,
+) -> Result<SessionServiceError, SessionServiceError> {
+    warn!(
+        "Rotated token of session {} was reused, blacklisting",
+        session_id
+    );
+    session_repository.update_session_status(session_id, SessionStatus::Blacklisted)?;
+    Ok(auth::AuthorizationError::SessionTokenBlacklisted.into())
+}
+
 fn generate_session_token(
     session_id: &Uuid,
     user_id: i64,
+    generation: i32,
     expires_at: chrono::DateTime<Utc>,
     token_config: &Jwt,
 ) -> Result<TokenDto, jsonwebtoken::errors::Error> {
     let my_claims = crate::auth::SessionClaims {
         exp: expires_at.timestamp(),
         iat: chrono::Utc::now().timestamp(),
-        iss: "user-servic".to_owned(),
+        iss: token_config.issuer.clone(),
+        aud: vec![token_config.audience.clone()],
         session_id: session_id.clone(),
         user_id: user_id,
+        generation,
     };
 
     let naive = chrono::NaiveDateTime::from_timestamp(my_claims.exp, 0);
     let exp: chrono::DateTime<Utc> = chrono::DateTime::from_utc(naive, Utc);
-    jsonwebtoken::encode(
-        &jsonwebtoken::Header::default(),
-        &my_claims,
-        &jsonwebtoken::EncodingKey::from_secret(token_config.session_secret.as_ref()),
-    )
-    .map(|token| TokenDto {
+    auth::encode_session_jwt(&my_claims, token_config).map(|token| TokenDto {
         token: token,
         expiration: exp,
     })
 }
 
+/// Roles and permissions emitted into access tokens. OAuth clients get no roles, the scope of
+/// their tokens is what the user granted them
+fn get_authorities(
+    role_repository: &impl RoleRepository,
+    user_id: i64,
+    platform: &str,
+    scope: &Option<String>,
+) -> Result<Authorities, SessionServiceError> {
+    if platform == OAUTH_PLATFORM {
+        return Ok(Authorities {
+            roles: vec![],
+            permissions: scope
+                .as_deref()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+        });
+    }
+    Ok(Authorities {
+        roles: role_repository.get_role_names_by_user_id(user_id)?,
+        permissions: role_repository.get_permissions_by_user_id(user_id)?,
+    })
+}
+
+/// Sessions of OAuth clients are named after the client
+fn oauth_client_id<'a>(platform: &str, sub_platform: &'a str) -> Option<&'a str> {
+    (platform == OAUTH_PLATFORM).then_some(sub_platform)
+}
+
 fn generate_access_token(
     user_id: i64,
+    session_id: &Uuid,
+    authorities: Authorities,
+    client_id: Option<&str>,
     token_config: &Jwt,
 ) -> Result<TokenDto, jsonwebtoken::errors::Error> {
     let my_claims = crate::auth::AccessClaims {
         exp: (chrono::Utc::now() + chrono::Duration::milliseconds(token_config.access_exp_ms))
             .timestamp(),
         iat: chrono::Utc::now().timestamp(),
-        iss: "user-servic".to_owned(),
+        iss: token_config.issuer.clone(),
+        aud: vec![token_config.audience.clone()],
         user_id: user_id,
+        sid: *session_id,
+        roles: authorities.roles,
+        scope: authorities.permissions.join(" "),
+        act: None,
+        client_id: client_id.map(String::from),
     };
 
     let naive = chrono::NaiveDateTime::from_timestamp(my_claims.exp, 0);
     let exp: chrono::DateTime<Utc> = chrono::DateTime::from_utc(naive, Utc);
-    jsonwebtoken::encode(
-        &jsonwebtoken::Header::default(),
-        &my_claims,
-        &jsonwebtoken::EncodingKey::from_secret(token_config.access_secret.as_ref()),
-    )
-    .map(|token| TokenDto {
+    auth::encode_access_jwt(&my_claims, token_config).map(|token| TokenDto {
         token: token,
         expiration: exp,
     })
 }
+
+fn generate_mfa_token(
+    pending_login: &NewPendingLogin,
+    login_dto: &LoginDto,
+    token_config: &Jwt,
+) -> Result<TokenDto, jsonwebtoken::errors::Error> {
+    let expiration = pending_login.expires_at;
+    let claims = auth::MfaClaims {
+        exp: expiration.timestamp(),
+        iat: chrono::Utc::now().timestamp(),
+        iss: token_config.issuer.clone(),
+        aud: vec![token_config.audience.clone()],
+        jti: pending_login.id,
+        mfa_user_id: pending_login.user_id,
+        platform: login_dto.platform.clone(),
+        sub_platform: login_dto.sub_platform.clone(),
+    };
+
+    auth::encode_mfa_jwt(&claims, token_config).map(|token| TokenDto { token, expiration })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::AuthorizationError;
+    use crate::configuration::{Lockout, SessionLimitStrategy, SessionLimits, SessionReaper};
+    use crate::model::login_events::ClientInfo;
+    use crate::model::sessions::{LoginDto, Session, SessionStatus};
+    use crate::model::totp::UserTotp;
+    use crate::model::users::UserStatus;
+    use crate::repository::session_repository::SessionRepository;
+    use crate::test_support::{
+        active_session, argon2_config, jwt_config, mfa_config, user, MockRepo,
+    };
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    // User 2 is an admin, user 4 is suspended
+    fn seeded_repo(sessions: Vec<Session>) -> MockRepo {
+        let repo = MockRepo::with_users(vec![
+            user(2, UserStatus::Active),
+            user(3, UserStatus::Active),
+            user(4, UserStatus::Suspended),
+        ])
+        .with_roles()
+        .with_sessions(sessions);
+        repo.user_roles.borrow_mut().push((2, 1));
+        repo
+    }
+
+    #[test]
+    fn get_users_sessions_marks_current() {
+        let current_id = Uuid::new_v4();
+        let other_id = Uuid::new_v4();
+        let repo = seeded_repo(vec![
+            active_session(current_id, 2),
+            active_session(other_id, 2),
+            active_session(Uuid::new_v4(), 3),
+        ]);
+        let sessions = super::get_users_sessions(&repo, 2, current_id).unwrap();
+        assert_eq!(2, sessions.len());
+        let current = |id| {
+            sessions
+                .iter()
+                .find(|s| s.session.id == id)
+                .unwrap()
+                .current
+        };
+        assert!(current(current_id));
+        assert!(!current(other_id));
+    }
+
+    #[test]
+    fn blacklist_session() {
+        let session_id = Uuid::new_v4();
+        let repo = seeded_repo(vec![active_session(session_id, 2)]);
+        let result = super::blacklist_session(&repo, 2, session_id);
+        assert!(result.is_ok());
+        let session = repo.session(session_id);
+        assert_eq!(SessionStatus::Blacklisted as i32, session.status);
+    }
+
+    #[test]
+    fn blacklist_session_of_other_user() {
+        let session_id = Uuid::new_v4();
+        let repo = seeded_repo(vec![active_session(session_id, 2)]);
+        let result = super::blacklist_session(&repo, 3, session_id);
+        assert!(matches!(
+            result,
+            Err(super::SessionServiceError::AuthorizationError(
+                AuthorizationError::NoAuthorizationForAction
+            ))
+        ));
+        let session = repo.session(session_id);
+        assert_eq!(SessionStatus::Active as i32, session.status);
+    }
+
+    #[test]
+    fn blacklist_other_sessions() {
+        let current_id = Uuid::new_v4();
+        let other_id = Uuid::new_v4();
+        let foreign_id = Uuid::new_v4();
+        let repo = seeded_repo(vec![
+            active_session(current_id, 2),
+            active_session(other_id, 2),
+            active_session(foreign_id, 3),
+        ]);
+        let config = jwt_config();
+        let exp = Utc::now() + chrono::Duration::days(1);
+        let token = super::generate_session_token(&current_id, 2, 0, exp, &config).unwrap();
+
+        let result = super::blacklist_other_sessions(&repo, 2, &token.token, &config);
+        assert_eq!(1, result.unwrap());
+        let status = |id| repo.session(id).status;
+        assert_eq!(SessionStatus::Active as i32, status(current_id));
+        assert_eq!(SessionStatus::Blacklisted as i32, status(other_id));
+        assert_eq!(SessionStatus::Active as i32, status(foreign_id));
+    }
+
+    #[test]
+    fn access_token_names_only_own_audience() {
+        let mut config = jwt_config();
+        config.audiences.push(String::from("billing"));
+        let authorities = crate::model::roles::Authorities {
+            roles: vec![],
+            permissions: vec![],
+        };
+        let token =
+            super::generate_access_token(2, &Uuid::new_v4(), authorities, None, &config).unwrap();
+        match crate::auth::decode_access_jwt(&token.token, &config) {
+            Ok(crate::auth::AccessToken::User(claims)) => {
+                assert_eq!(vec![String::from("user-service")], claims.aud)
+            }
+            other => panic!("Expected user claims, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn logout() {
+        let session_id = Uuid::new_v4();
+        let repo = seeded_repo(vec![active_session(session_id, 2)]);
+        let config = jwt_config();
+        let exp = Utc::now() + chrono::Duration::days(1);
+        let token = super::generate_session_token(&session_id, 2, 0, exp, &config).unwrap();
+
+        super::logout(&repo, "invalid", &config).unwrap();
+        let expired = Utc::now() - chrono::Duration::minutes(1);
+        let expired = super::generate_session_token(&session_id, 2, 0, expired, &config).unwrap();
+        super::logout(&repo, &expired.token, &config).unwrap();
+        assert_eq!(
+            SessionStatus::Active as i32,
+            repo.session(session_id).status
+        );
+
+        super::logout(&repo, &token.token, &config).unwrap();
+        assert_eq!(
+            SessionStatus::Blacklisted as i32,
+            repo.session(session_id).status
+        );
+    }
+
+    #[test]
+    fn refresh_rotates_session_token() {
+        let session_id = Uuid::new_v4();
+        let repo = seeded_repo(vec![active_session(session_id, 2)]);
+        let config = jwt_config();
+        let exp = Utc::now() + chrono::Duration::days(1);
+        let token = super::generate_session_token(&session_id, 2, 0, exp, &config).unwrap();
+
+        let client_info = ClientInfo {
+            ip: Some(String::from("10.0.0.1")),
+            user_agent: Some(String::from("Firefox")),
+        };
+        let token_pair =
+            super::create_access_token_and_refresh(&repo, &token.token, &client_info, &config)
+                .unwrap();
+        let claims = crate::auth::decode_session_jwt(&token_pair.session_token.token, &config);
+        assert_eq!(1, claims.unwrap().generation);
+        match crate::auth::decode_access_jwt(&token_pair.access_token.token, &config) {
+            Ok(crate::auth::AccessToken::User(claims)) => {
+                assert_eq!(vec![String::from("admin")], claims.roles);
+                assert!(claims.has_permission("users:admin"));
+                assert!(!claims.has_permission("users"));
+            }
+            other => panic!("Expected user claims, got {:?}", other),
+        }
+        let session = repo.session(session_id);
+        assert_eq!(1, session.generation);
+        assert_eq!(SessionStatus::Active as i32, session.status);
+        assert_eq!(Some("10.0.0.1"), session.ip.as_deref());
+        assert_eq!(Some("Firefox"), session.user_agent.as_deref());
+        assert!(session.last_seen_at > Utc::now() - chrono::Duration::minutes(1));
+
+        let login_events = repo.login_events.borrow();
+        assert_eq!(1, login_events.len());
+        assert!(login_events[0].success);
+        assert_eq!(Some(2), login_events[0].user_id);
+        assert_eq!(Some("10.0.0.1"), login_events[0].ip.as_deref());
+        assert_eq!(Some("firefox"), login_events[0].sub_platform.as_deref());
+    }
+
+    #[test]
+    fn oauth_session_has_no_permissions() {
+        let session_id = Uuid::new_v4();
+        let mut session = active_session(session_id, 2);
+        session.platform = String::from(crate::model::oauth::OAUTH_PLATFORM);
+        let repo = seeded_repo(vec![session]);
+        let config = jwt_config();
+        let exp = Utc::now() + chrono::Duration::days(1);
+        let token = super::generate_session_token(&session_id, 2, 0, exp, &config).unwrap();
+
+        let token_pair = super::create_access_token_and_refresh(
+            &repo,
+            &token.token,
+            &ClientInfo::default(),
+            &config,
+        )
+        .unwrap();
+        match crate::auth::decode_access_jwt(&token_pair.access_token.token, &config) {
+            Ok(crate::auth::AccessToken::User(claims)) => {
+                assert!(claims.roles.is_empty());
+                assert!(!claims.has_permission("users:admin"));
+            }
+            other => panic!("Expected user claims, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refresh_with_reused_token_blacklists_session() {
+        let session_id = Uuid::new_v4();
+        let repo = seeded_repo(vec![active_session(session_id, 2)]);
+        let config = jwt_config();
+        let exp = Utc::now() + chrono::Duration::days(1);
+        let token = super::generate_session_token(&session_id, 2, 0, exp, &config).unwrap();
+
+        let client_info = ClientInfo::default();
+        assert!(
+            super::create_access_token_and_refresh(&repo, &token.token, &client_info, &config)
+                .is_ok()
+        );
+        let result =
+            super::create_access_token_and_refresh(&repo, &token.token, &client_info, &config);
+        assert!(matches!(
+            result,
+            Err(super::SessionServiceError::AuthorizationError(
+                AuthorizationError::SessionTokenBlacklisted
+            ))
+        ));
+        assert_eq!(
+            Some("SessionTokenBlacklisted"),
+            repo.login_events.borrow()[1].failure_reason.as_deref()
+        );
+        let session = repo.session(session_id);
+        assert_eq!(SessionStatus::Blacklisted as i32, session.status);
+    }
+
+    #[test]
+    fn refresh_of_suspended_user_blacklists_session() {
+        let session_id = Uuid::new_v4();
+        let repo = seeded_repo(vec![active_session(session_id, 4)]);
+        let config = jwt_config();
+        let exp = Utc::now() + chrono::Duration::days(1);
+        let token = super::generate_session_token(&session_id, 4, 0, exp, &config).unwrap();
+
+        let result = super::create_access_token_and_refresh(
+            &repo,
+            &token.token,
+            &ClientInfo::default(),
+            &config,
+        );
+        assert!(matches!(
+            result,
+            Err(super::SessionServiceError::AuthorizationError(
+                AuthorizationError::SessionTokenBlacklisted
+            ))
+        ));
+        let session = repo.session(session_id);
+        assert_eq!(SessionStatus::Blacklisted as i32, session.status);
+        assert_eq!(0, session.generation);
+    }
+
+    #[test]
+    fn refresh_is_capped_at_max_lifetime() {
+        let session_id = Uuid::new_v4();
+        let mut session = active_session(session_id, 2);
+        session.created_at = Utc::now() - chrono::Duration::days(29);
+        let repo = seeded_repo(vec![session]);
+        let config = jwt_config();
+        let exp = Utc::now() + chrono::Duration::days(1);
+        let token = super::generate_session_token(&session_id, 2, 0, exp, &config).unwrap();
+
+        let client_info = ClientInfo::default();
+        let token_pair =
+            super::create_access_token_and_refresh(&repo, &token.token, &client_info, &config)
+                .unwrap();
+        // One day left of the 30 day lifetime, instead of the 7 day idle timeout
+        let expires_in = token_pair.session_token.expiration - Utc::now();
+        assert!(expires_in <= chrono::Duration::days(1));
+        assert!(expires_in > chrono::Duration::hours(23));
+    }
+
+    #[test]
+    fn refresh_after_max_lifetime() {
+        let session_id = Uuid::new_v4();
+        let mut session = active_session(session_id, 2);
+        session.created_at = Utc::now() - chrono::Duration::days(31);
+        let repo = seeded_repo(vec![session]);
+        let config = jwt_config();
+        let exp = Utc::now() + chrono::Duration::days(1);
+        let token = super::generate_session_token(&session_id, 2, 0, exp, &config).unwrap();
+
+        let result = super::create_access_token_and_refresh(
+            &repo,
+            &token.token,
+            &ClientInfo::default(),
+            &config,
+        );
+        assert!(matches!(
+            result,
+            Err(super::SessionServiceError::AuthorizationError(
+                AuthorizationError::SessionExpired
+            ))
+        ));
+        let session = repo.session(session_id);
+        assert_eq!(0, session.generation);
+    }
+
+    #[test]
+    fn session_limit_rejects_login() {
+        let repo = seeded_repo(vec![
+            active_session(Uuid::new_v4(), 2),
+            active_session(Uuid::new_v4(), 2),
+        ]);
+        let session_limits = SessionLimits {
+            max_sessions: Some(2),
+            max_sessions_per_platform: None,
+            strategy: SessionLimitStrategy::Reject,
+        };
+        let result = super::create_session_token_pair(
+            &repo,
+            2,
+            "web",
+            "firefox",
+            &ClientInfo::default(),
+            &jwt_config(),
+            &session_limits,
+        );
+        assert!(matches!(
+            result,
+            Err(super::SessionServiceError::AuthorizationError(
+                AuthorizationError::SessionLimitReached
+            ))
+        ));
+        // Blacklisted sessions and sessions of other users don't count
+        repo.blacklist_other_active_sessions(2, Uuid::new_v4())
+            .unwrap();
+        let result = super::create_session_token_pair(
+            &repo,
+            2,
+            "web",
+            "firefox",
+            &ClientInfo::default(),
+            &jwt_config(),
+            &session_limits,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn session_limit_evicts_least_recently_refreshed() {
+        let oldest_web_id = Uuid::new_v4();
+        let web_id = Uuid::new_v4();
+        let app_id = Uuid::new_v4();
+        let mut oldest_web = active_session(oldest_web_id, 2);
+        oldest_web.refreshed_at = Utc::now() - chrono::Duration::days(2);
+        let mut app = active_session(app_id, 2);
+        app.platform = String::from("app");
+        app.refreshed_at = Utc::now() - chrono::Duration::days(3);
+        let repo = seeded_repo(vec![oldest_web, active_session(web_id, 2), app]);
+        let session_limits = SessionLimits {
+            max_sessions: Some(3),
+            max_sessions_per_platform: Some(2),
+            strategy: SessionLimitStrategy::Evict,
+        };
+        let result = super::create_session_token_pair(
+            &repo,
+            2,
+            "web",
+            "firefox",
+            &ClientInfo::default(),
+            &jwt_config(),
+            &session_limits,
+        );
+        assert!(result.is_ok());
+        // The platform limit made room, which also keeps the user within the overall limit
+        let status = |id| repo.session(id).status;
+        assert_eq!(SessionStatus::Blacklisted as i32, status(oldest_web_id));
+        assert_eq!(SessionStatus::Active as i32, status(web_id));
+        assert_eq!(SessionStatus::Active as i32, status(app_id));
+    }
+
+    #[test]
+    fn reap_sessions_in_batches() {
+        let two_days_ago = Utc::now() - chrono::Duration::days(2);
+        let mut sessions: Vec<Session> = (0..5)
+            .map(|_| {
+                let mut session = active_session(Uuid::new_v4(), 2);
+                session.expires_at = two_days_ago;
+                session
+            })
+            .collect();
+        let mut blacklisted = active_session(Uuid::new_v4(), 2);
+        blacklisted.status = SessionStatus::Blacklisted as i32;
+        blacklisted.updated_at = two_days_ago;
+        sessions.push(blacklisted);
+        // Within the retention
+        let mut recently_blacklisted = active_session(Uuid::new_v4(), 3);
+        recently_blacklisted.status = SessionStatus::Blacklisted as i32;
+        let mut recently_expired = active_session(Uuid::new_v4(), 3);
+        recently_expired.expires_at = Utc::now() - chrono::Duration::hours(1);
+        sessions.extend(vec![
+            recently_blacklisted,
+            recently_expired,
+            active_session(Uuid::new_v4(), 3),
+        ]);
+        let repo = seeded_repo(sessions);
+        let reaper_config = SessionReaper {
+            interval_ms: 3600000,
+            retention_ms: 86400000,
+            batch_size: 2,
+        };
+
+        assert_eq!(
+            6,
+            super::reap_sessions(&repo, &reaper_config, &jwt_config()).unwrap()
+        );
+        assert_eq!(3, repo.sessions.borrow().len());
+        assert_eq!(
+            0,
+            super::reap_sessions(&repo, &reaper_config, &jwt_config()).unwrap()
+        );
+    }
+
+    /// User 2 with the password "password"
+    fn password_repo() -> MockRepo {
+        let repo = seeded_repo(vec![]);
+        repo.users.borrow_mut()[0].password =
+            crate::service::user_service::hash_password(b"password", &argon2_config()).unwrap();
+        repo
+    }
+
+    fn login(
+        repo: &MockRepo,
+        password: &str,
+    ) -> Result<super::LoginOutcome, super::SessionServiceError> {
+        let login_dto = LoginDto {
+            username: String::from("user2"),
+            password: password.to_owned(),
+            platform: String::from("web"),
+            sub_platform: String::from("firefox"),
+        };
+        let lockout_config = Lockout {
+            threshold: 3,
+            base_duration_ms: 60000,
+            max_duration_ms: 3600000,
+        };
+        super::create_login_token_pair(
+            repo,
+            &login_dto,
+            &ClientInfo::default(),
+            &jwt_config(),
+            &mfa_config(),
+            &lockout_config,
+            &SessionLimits::default(),
+        )
+    }
+
+    #[test]
+    fn wrong_passwords_lock_user() {
+        let repo = password_repo();
+        for failed_attempts in 1..3 {
+            assert!(matches!(
+                login(&repo, "wrong-password"),
+                Err(super::SessionServiceError::AuthorizationError(
+                    AuthorizationError::PasswordInvalid
+                ))
+            ));
+            assert_eq!(failed_attempts, repo.user(2).failed_login_attempts);
+        }
+        assert!(matches!(
+            login(&repo, "wrong-password"),
+            Err(super::SessionServiceError::AuthorizationError(
+                AuthorizationError::AccountLocked(_)
+            ))
+        ));
+        let locked_until = repo.user(2).locked_until.unwrap();
+        assert!(locked_until > Utc::now() + chrono::Duration::seconds(50));
+        assert!(repo.sessions.borrow().is_empty());
+    }
+
+    #[test]
+    fn locked_user_is_rejected_without_password_check() {
+        // An empty hash fails to verify, so the password must not be looked at
+        let repo = seeded_repo(vec![]);
+        repo.users.borrow_mut()[0].failed_login_attempts = 3;
+        repo.users.borrow_mut()[0].locked_until = Some(Utc::now() + chrono::Duration::minutes(1));
+
+        assert!(matches!(
+            login(&repo, "password"),
+            Err(super::SessionServiceError::AuthorizationError(
+                AuthorizationError::AccountLocked(_)
+            ))
+        ));
+        assert_eq!(3, repo.user(2).failed_login_attempts);
+        assert_eq!(
+            vec![Some(String::from("AccountLocked"))],
+            repo.failure_reasons()
+        );
+    }
+
+    #[test]
+    fn login_resets_failed_attempts() {
+        let repo = password_repo();
+        repo.users.borrow_mut()[0].failed_login_attempts = 2;
+
+        assert!(matches!(
+            login(&repo, "password"),
+            Ok(super::LoginOutcome::Authenticated(_))
+        ));
+        assert_eq!(0, repo.user(2).failed_login_attempts);
+        assert_eq!(1, repo.sessions.borrow().len());
+    }
+
+    #[test]
+    fn password_alone_does_not_reset_failed_attempts() {
+        let repo = password_repo();
+        repo.users.borrow_mut()[0].failed_login_attempts = 2;
+        repo.totp.borrow_mut().push(UserTotp {
+            user_id: 2,
+            secret: vec![],
+            confirmed_at: Some(Utc::now()),
+            last_used_step: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        });
+
+        match login(&repo, "password") {
+            Ok(super::LoginOutcome::MfaRequired(mfa_required)) => {
+                assert_eq!(vec![String::from("totp")], mfa_required.methods)
+            }
+            _ => panic!("Expected the second factor to be required"),
+        }
+        assert_eq!(2, repo.user(2).failed_login_attempts);
+        assert_eq!(1, repo.pending_logins.borrow().len());
+        assert!(repo.sessions.borrow().is_empty());
+    }
+
+    #[test]
+    fn lockout_duration_escalates() {
+        let lockout_config = crate::configuration::Lockout {
+            threshold: 5,
+            base_duration_ms: 60000,
+            max_duration_ms: 3600000,
+        };
+        let minutes = |failed_attempts| {
+            super::lockout_duration(failed_attempts, &lockout_config).num_minutes()
+        };
+        assert_eq!(1, minutes(5));
+        assert_eq!(2, minutes(6));
+        assert_eq!(32, minutes(10));
+        assert_eq!(60, minutes(11));
+        assert_eq!(60, minutes(1000));
+    }
+}
@@ -29,21 +29,27 @@ pub fn register_user(
     argon2_config: &argon2::Config,
 ) -> Result<usize, UserServiceError> {
     let mut user_dto = user_dto;
+    user_dto.password = hash_password(user_dto.password.as_bytes(), argon2_config)?;
+
+    let mut new_user = user_dto.into_new_user(PasswordVersion::ARGON2_1, UserStatus::Active); // Should be not verified, but is like that until email is implemented
+    user_repository
+        .create_user(&mut new_user)
+        .map_err(|e| e.into())
+}
+
+/// Hashes with a random salt, also used for other secrets that are stored like passwords
+pub fn hash_password(
+    password: &[u8],
+    argon2_config: &argon2::Config,
+) -> Result<String, UserServiceError> {
     let salt: String = rand::rngs::OsRng
         .sample_iter(&rand::distributions::Alphanumeric)
         .take(32)
         .collect();
-    let hash = argon2::hash_encoded(user_dto.password.as_bytes(), salt.as_bytes(), argon2_config)
-        .map_err(|e| {
+    argon2::hash_encoded(password, salt.as_bytes(), argon2_config).map_err(|e| {
         error!("{}", e);
         UserServiceError::HashingError
-    })?;
-    user_dto.password = hash;
-
-    let mut new_user = user_dto.into_new_user(PasswordVersion::ARGON2_1, UserStatus::Active); // Should be not verified, but is like that until email is implemented
-    user_repository
-        .create_user(&mut new_user)
-        .map_err(|e| e.into())
+    })
 }
 
 pub fn validate_password(hash: &str, password: &[u8]) -> Result<bool, UserServiceError> {
@@ -55,108 +61,34 @@ pub fn validate_password(hash: &str, password: &[u8]) -> Result<bool, UserServic
 
 #[cfg(test)]
 mod tests {
-    use crate::model::users::{NewUser, RegisterUserDto, User};
-    use crate::repository::user_repository::UserRepository;
+    use crate::model::users::{RegisterUserDto, UserStatus};
+    use crate::test_support::{argon2_config, user, MockRepo};
     use chrono::NaiveDate;
-    use chrono::Utc;
-    use diesel::QueryResult;
-
-    struct MockUserRepo {
-        scenario: i32,
-    }
-
-    struct MockErrorInfo {}
-    impl diesel::result::DatabaseErrorInformation for MockErrorInfo {
-        fn message(&self) -> &str {
-            ""
-        }
-
-        fn details(&self) -> Option<&str> {
-            None
-        }
 
-        fn hint(&self) -> Option<&str> {
-            None
-        }
-
-        fn table_name(&self) -> Option<&str> {
-            None
-        }
-
-        fn column_name(&self) -> Option<&str> {
-            None
-        }
-
-        fn constraint_name(&self) -> Option<&str> {
-            None
-        }
-    }
-
-    impl UserRepository for MockUserRepo {
-        fn create_user(&self, _: &mut NewUser) -> QueryResult<usize> {
-            match self.scenario {
-                1 => Ok(1),
-                2 => Err(diesel::result::Error::DatabaseError(
-                    diesel::result::DatabaseErrorKind::UniqueViolation,
-                    Box::new(MockErrorInfo {}),
-                )),
-                _ => Ok(1),
-            }
-        }
-
-        fn get_user_by_id(&self, id: i64) -> QueryResult<Option<User>> {
-            Ok(Some(User {
-                id: id,
-                username: String::from("Gustav"),
-                email: String::from("user2@example.com"),
-                password: String::from("somepwhash"),
-                password_version: 1,
-                date_of_birth: NaiveDate::from_ymd(1992, 1, 1),
-                status: 1,
-                created_at: Utc::now(),
-                updated_at: Utc::now(),
-            }))
-        }
-
-        fn get_user_by_username(&self, username: &str) -> QueryResult<Option<User>> {
-            Ok(Some(User {
-                id: 2,
-                username: username.to_owned(),
-                email: String::from("user2@example.com"),
-                password: String::from("somepwhash"),
-                password_version: 1,
-                date_of_birth: NaiveDate::from_ymd(1992, 1, 1),
-                status: 1,
-                created_at: Utc::now(),
-                updated_at: Utc::now(),
-            }))
+    fn user_dto(username: &str) -> RegisterUserDto {
+        RegisterUserDto {
+            username: username.to_owned(),
+            email: "mail@mail.com".to_owned(),
+            password: "somepassword".to_owned(),
+            date_of_birth: NaiveDate::from_ymd_opt(1990, 1, 1).unwrap(),
         }
     }
 
     #[test]
     fn register_user() {
-        let user_repo = MockUserRepo { scenario: 1 };
-        let user_dto = RegisterUserDto {
-            username: "MyUsername".to_owned(),
-            email: "mail@mail.com".to_owned(),
-            password: "somepassword".to_owned(),
-            date_of_birth: NaiveDate::from_ymd(1990, 1, 1),
-        };
-        let result = super::register_user(&user_repo, user_dto, &argon2::Config::default());
+        let user_repo = MockRepo::default();
+        let result = super::register_user(&user_repo, user_dto("MyUsername"), &argon2_config());
         let expected: Result<usize, super::UserServiceError> = Ok(1);
         assert_eq!(expected, result);
+        let user = user_repo.users.borrow()[0].clone();
+        assert_eq!("MYUSERNAME", user.username);
+        assert!(super::validate_password(&user.password, b"somepassword").unwrap());
     }
 
     #[test]
     fn register_user_exists() {
-        let user_repo = MockUserRepo { scenario: 2 };
-        let user_dto = RegisterUserDto {
-            username: "MyUsername".to_owned(),
-            email: "mail@mail.com".to_owned(),
-            password: "somepassword".to_owned(),
-            date_of_birth: NaiveDate::from_ymd(1990, 1, 1),
-        };
-        let result = super::register_user(&user_repo, user_dto, &argon2::Config::default());
+        let user_repo = MockRepo::with_users(vec![user(2, UserStatus::Active)]);
+        let result = super::register_user(&user_repo, user_dto("user2"), &argon2_config());
         let expected: Result<usize, super::UserServiceError> =
             Err(super::UserServiceError::DatabaseEntryAlreadyExists);
         assert_eq!(expected, result);
@@ -0,0 +1,763 @@
+use crate::auth;
+use crate::auth::AuthorizationError;
+use crate::configuration::{Jwt, SessionLimits, WebAuthn};
+use crate::model::login_events::{ClientInfo, LoginEventType, NewLoginEvent};
+use crate::model::recovery_codes::RecoveryCodesDto;
+use crate::model::sessions::TokenPairDto;
+use crate::model::users::UserStatus;
+use crate::model::webauthn::{
+    AuthenticatorSelectionDto, ChallengePurpose, CreationOptionsDto, CredentialDescriptorDto,
+    CredentialParameterDto, LoginOptionsRequestDto, NewWebAuthnChallenge, NewWebAuthnCredential,
+    RegisterCredentialDto, RelyingPartyDto, RequestOptionsDto, UserEntityDto, WebAuthnChallenge,
+    WebAuthnCredential, WebAuthnLoginDto,
+};
+use crate::repository::login_event_repository::LoginEventRepository;
+use crate::repository::pending_login_repository::PendingLoginRepository;
+use crate::repository::recovery_code_repository::RecoveryCodeRepository;
+use crate::repository::role_repository::RoleRepository;
+use crate::repository::session_repository::SessionRepository;
+use crate::repository::totp_repository::TotpRepository;
+use crate::repository::user_repository::UserRepository;
+use crate::repository::webauthn_repository::WebAuthnRepository;
+use crate::service;
+use crate::service::mfa_service::MfaServiceError;
+use crate::service::session_service::SessionServiceError;
+use crate::webauthn;
+use crate::webauthn::{ClientData, WebAuthnError};
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, URL_SAFE_NO_PAD};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use rand::RngCore;
+
+// Browsers don't pad, but some client libraries do
+const URL_SAFE_LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug)]
+pub enum WebAuthnServiceError {
+    GenericDatabaseError(diesel::result::Error),
+    AuthorizationError(AuthorizationError),
+    MissingField(&'static str),
+    VerificationFailed(&'static str),
+    CredentialAlreadyRegistered,
+    SessionServiceError(SessionServiceError),
+    MfaServiceError(MfaServiceError),
+}
+
+impl From<diesel::result::Error> for WebAuthnServiceError {
+    fn from(error: diesel::result::Error) -> WebAuthnServiceError {
+        WebAuthnServiceError::GenericDatabaseError(error)
+    }
+}
+
+impl From<WebAuthnError> for WebAuthnServiceError {
+    fn from(error: WebAuthnError) -> WebAuthnServiceError {
+        debug!("{}", error);
+        WebAuthnServiceError::VerificationFailed("Invalid credential data")
+    }
+}
+
+impl From<SessionServiceError> for WebAuthnServiceError {
+    fn from(error: SessionServiceError) -> WebAuthnServiceError {
+        WebAuthnServiceError::SessionServiceError(error)
+    }
+}
+
+impl From<MfaServiceError> for WebAuthnServiceError {
+    fn from(error: MfaServiceError) -> WebAuthnServiceError {
+        WebAuthnServiceError::MfaServiceError(error)
+    }
+}
+
+impl WebAuthnServiceError {
+    /// Without details, as stored in login events
+    pub fn reason(&self) -> &'static str {
+        match self {
+            WebAuthnServiceError::AuthorizationError(e) => e.reason(),
+            WebAuthnServiceError::MissingField(_) => "MissingField",
+            WebAuthnServiceError::VerificationFailed(_) => "VerificationFailed",
+            WebAuthnServiceError::SessionServiceError(e) => e.reason(),
+            _ => "InternalError",
+        }
+    }
+}
+
+pub fn registration_options<R>(
+    repositories: &R,
+    user_id: i64,
+    webauthn_config: &WebAuthn,
+) -> Result<CreationOptionsDto, WebAuthnServiceError>
+where
+    R: UserRepository + WebAuthnRepository,
+{
+    let user =
+        repositories
+            .get_user_by_id(user_id)?
+            .ok_or(WebAuthnServiceError::AuthorizationError(
+                AuthorizationError::UserDoesNotExist,
+            ))?;
+    let challenge = create_challenge(
+        repositories,
+        Some(user.id),
+        ChallengePurpose::Registration,
+        webauthn_config,
+    )?;
+    let exclude_credentials = repositories
+        .get_credentials_by_user_id(user.id)?
+        .iter()
+        .map(credential_descriptor)
+        .collect();
+
+    Ok(CreationOptionsDto {
+        rp: RelyingPartyDto {
+            id: webauthn_config.rp_id.clone(),
+            name: webauthn_config.rp_name.clone(),
+        },
+        user: UserEntityDto {
+            id: URL_SAFE_NO_PAD.encode(user_handle(user.id)),
+            name: user.username.clone(),
+            display_name: user.username,
+        },
+        challenge,
+        pub_key_cred_params: webauthn::SUPPORTED_ALGORITHMS
+            .iter()
+            .map(|&alg| CredentialParameterDto {
+                credential_type: String::from("public-key"),
+                alg,
+            })
+            .collect(),
+        timeout: webauthn_config.challenge_exp_ms,
+        exclude_credentials,
+        authenticator_selection: AuthenticatorSelectionDto {
+            resident_key: String::from("preferred"),
+            user_verification: String::from("preferred"),
+        },
+        attestation: String::from("none"),
+    })
+}
+
+/// Registration ceremony (WebAuthn Level 2 section 7.1), without attestation verification.
+/// Returns recovery codes if the passkey is the user's first second factor.
+pub fn register_credential<R>(
+    repositories: &R,
+    user_id: i64,
+    register_dto: &RegisterCredentialDto,
+    webauthn_config: &WebAuthn,
+    argon2_config: &argon2::Config,
+) -> Result<Option<RecoveryCodesDto>, WebAuthnServiceError>
+where
+    R: WebAuthnRepository + TotpRepository + RecoveryCodeRepository,
+{
+    let response = &register_dto.credential.response;
+    let client_data = webauthn::parse_client_data(&decode(&response.client_data_json)?)?;
+    let challenge = consume_challenge(
+        repositories,
+        &client_data,
+        "webauthn.create",
+        ChallengePurpose::Registration,
+        webauthn_config,
+    )?;
+    if challenge.user_id != Some(user_id) {
+        return Err(WebAuthnServiceError::VerificationFailed(
+            "Challenge was issued to another user",
+        ));
+    }
+
+    let authenticator_data =
+        webauthn::parse_attestation_object(&decode(&response.attestation_object)?)?;
+    verify_authenticator_data(&authenticator_data, false, webauthn_config)?;
+    let attested_credential =
+        authenticator_data
+            .attested_credential
+            .ok_or(WebAuthnServiceError::VerificationFailed(
+                "No attested credential data",
+            ))?;
+    if attested_credential.credential_id != decode(&register_dto.credential.id)? {
+        return Err(WebAuthnServiceError::VerificationFailed(
+            "Credential id mismatch",
+        ));
+    }
+    webauthn::public_key_algorithm(&attested_credential.public_key)?;
+    if repositories
+        .get_credential_by_id(&attested_credential.credential_id)?
+        .is_some()
+    {
+        return Err(WebAuthnServiceError::CredentialAlreadyRegistered);
+    }
+
+    let enrolled = repositories
+        .get_totp_by_user_id(user_id)?
+        .is_some_and(|user_totp| user_totp.confirmed_at.is_some())
+        || !repositories.get_credentials_by_user_id(user_id)?.is_empty();
+    repositories.create_credential(&NewWebAuthnCredential {
+        id: attested_credential.credential_id,
+        user_id,
+        name: register_dto.name.clone(),
+        public_key: attested_credential.public_key,
+        sign_count: authenticator_data.sign_count as i64,
+    })?;
+    if enrolled {
+        return Ok(None);
+    }
+    Ok(Some(service::mfa_service::create_recovery_codes(
+        repositories,
+        user_id,
+        argon2_config,
+    )?))
+}
+
+/// With an mfa token the challenge is bound to that user and only their credentials are allowed,
+/// otherwise any discoverable credential can answer it
+pub fn login_options(
+    webauthn_repository: &impl WebAuthnRepository,
+    request: &LoginOptionsRequestDto,
+    token_config: &Jwt,
+    webauthn_config: &WebAuthn,
+) -> Result<RequestOptionsDto, WebAuthnServiceError> {
+    let user_id = match &request.mfa_token {
+        Some(mfa_token) => Some(
+            auth::decode_mfa_jwt(mfa_token, token_config)
+                .map_err(WebAuthnServiceError::AuthorizationError)?
+                .mfa_user_id,
+        ),
+        None => None,
+    };
+    let allow_credentials = match user_id {
+        Some(user_id) => webauthn_repository
+            .get_credentials_by_user_id(user_id)?
+            .iter()
+            .map(credential_descriptor)
+            .collect(),
+        None => vec![],
+    };
+    let challenge = create_challenge(
+        webauthn_repository,
+        user_id,
+        ChallengePurpose::Authentication,
+        webauthn_config,
+    )?;
+
+    Ok(RequestOptionsDto {
+        challenge,
+        timeout: webauthn_config.challenge_exp_ms,
+        rp_id: webauthn_config.rp_id.clone(),
+        allow_credentials,
+        // A passkey alone has to prove the user, not only their presence
+        user_verification: String::from(if user_id.is_some() {
+            "preferred"
+        } else {
+            "required"
+        }),
+    })
+}
+
+/// Authentication ceremony (WebAuthn Level 2 section 7.2), ends in a new session like the
+/// password login
+pub fn login<R>(
+    repositories: &R,
+    login_dto: &WebAuthnLoginDto,
+    client_info: &ClientInfo,
+    token_config: &Jwt,
+    webauthn_config: &WebAuthn,
+    session_limits: &SessionLimits,
+) -> Result<TokenPairDto, WebAuthnServiceError>
+where
+    R: UserRepository
+        + SessionRepository
+        + RoleRepository
+        + WebAuthnRepository
+        + PendingLoginRepository
+        + LoginEventRepository,
+{
+    let mut login_event = NewLoginEvent::new(LoginEventType::WebAuthn, client_info);
+    let result = verify_assertion(
+        repositories,
+        login_dto,
+        client_info,
+        &mut login_event,
+        token_config,
+        webauthn_config,
+        session_limits,
+    );
+    let failure_reason = result.as_ref().err().map(WebAuthnServiceError::reason);
+    service::login_event_service::record_login_event(repositories, login_event, failure_reason);
+    result
+}
+
+fn verify_assertion<R>(
+    repositories: &R,
+    login_dto: &WebAuthnLoginDto,
+    client_info: &ClientInfo,
+    login_event: &mut NewLoginEvent,
+    token_config: &Jwt,
+    webauthn_config: &WebAuthn,
+    session_limits: &SessionLimits,
+) -> Result<TokenPairDto, WebAuthnServiceError>
+where
+    R: UserRepository
+        + SessionRepository
+        + RoleRepository
+        + WebAuthnRepository
+        + PendingLoginRepository,
+{
+    let mfa_claims = match &login_dto.mfa_token {
+        Some(mfa_token) => Some(
+            auth::decode_mfa_jwt(mfa_token, token_config)
+                .map_err(WebAuthnServiceError::AuthorizationError)?,
+        ),
+        None => None,
+    };
+    let (platform, sub_platform) = match &mfa_claims {
+        Some(claims) => (claims.platform.clone(), claims.sub_platform.clone()),
+        None => (
+            required(&login_dto.platform, "platform")?,
+            required(&login_dto.sub_platform, "sub_platform")?,
+        ),
+    };
+    login_event.user_id = mfa_claims.as_ref().map(|claims| claims.mfa_user_id);
+    login_event.set_platform(&platform, &sub_platform);
+
+    let response = &login_dto.credential.response;
+    let client_data_json = decode(&response.client_data_json)?;
+    let client_data = webauthn::parse_client_data(&client_data_json)?;
+    let challenge = consume_challenge(
+        repositories,
+        &client_data,
+        "webauthn.get",
+        ChallengePurpose::Authentication,
+        webauthn_config,
+    )?;
+    let credential = repositories
+        .get_credential_by_id(&decode(&login_dto.credential.id)?)?
+        .ok_or(WebAuthnServiceError::VerificationFailed(
+            "Unknown credential",
+        ))?;
+    if login_event.user_id.is_none() {
+        // Passwordless, a forged assertion for someone's credential still shows in their events
+        login_event.user_id = Some(credential.user_id);
+    }
+    // A challenge of a second factor login can't be used for a passwordless one and vice versa
+    let expected_user_id = mfa_claims.as_ref().map(|claims| claims.mfa_user_id);
+    if challenge.user_id != expected_user_id
+        || expected_user_id.is_some_and(|user_id| user_id != credential.user_id)
+    {
+        return Err(WebAuthnServiceError::VerificationFailed(
+            "Credential of another user",
+        ));
+    }
+    if let Some(handle) = &response.user_handle {
+        if decode(handle)? != user_handle(credential.user_id) {
+            return Err(WebAuthnServiceError::VerificationFailed(
+                "User handle mismatch",
+            ));
+        }
+    }
+
+    let user = match repositories.get_user_by_id(credential.user_id)? {
+        Some(user) if user.status == UserStatus::Active as i32 => user,
+        _ => {
+            return Err(WebAuthnServiceError::AuthorizationError(
+                AuthorizationError::UserDoesNotExist,
+            ))
+        }
+    };
+    // Checked before the assertion, like the password, so a passkey doesn't skip the lockout
+    service::session_service::verify_not_locked(&user)
+        .map_err(WebAuthnServiceError::AuthorizationError)?;
+
+    let authenticator_data_bytes = decode(&response.authenticator_data)?;
+    let authenticator_data = webauthn::parse_authenticator_data(&authenticator_data_bytes)?;
+    verify_authenticator_data(&authenticator_data, mfa_claims.is_none(), webauthn_config)?;
+    webauthn::verify_assertion_signature(
+        &credential.public_key,
+        &authenticator_data_bytes,
+        &client_data_json,
+        &decode(&response.signature)?,
+    )?;
+    update_sign_count(
+        repositories,
+        &credential,
+        authenticator_data.sign_count as i64,
+    )?;
+
+    if let Some(claims) = &mfa_claims {
+        // The mfa token is single use, like with TOTP
+        if repositories.delete_pending_login(claims.jti)? == 0 {
+            return Err(WebAuthnServiceError::AuthorizationError(
+                AuthorizationError::MfaTokenSpent,
+            ));
+        }
+    }
+    service::session_service::reset_failed_attempts(repositories, &user)?;
+    Ok(service::session_service::create_session_token_pair(
+        repositories,
+        user.id,
+        &platform,
+        &sub_platform,
+        client_info,
+        token_config,
+        session_limits,
+    )?)
+}
+
+fn create_challenge(
+    webauthn_repository: &impl WebAuthnRepository,
+    user_id: Option<i64>,
+    purpose: ChallengePurpose,
+    webauthn_config: &WebAuthn,
+) -> Result<String, WebAuthnServiceError> {
+    let mut bytes = [0; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    let challenge = URL_SAFE_NO_PAD.encode(bytes);
+
+    webauthn_repository.delete_expired_challenges()?;
+    webauthn_repository.create_challenge(&NewWebAuthnChallenge {
+        challenge: challenge.clone(),
+        user_id,
+        purpose: purpose as i32,
+        expires_at: chrono::Utc::now()
+            + chrono::Duration::milliseconds(webauthn_config.challenge_exp_ms),
+    })?;
+    Ok(challenge)
+}
+
+fn consume_challenge(
+    webauthn_repository: &impl WebAuthnRepository,
+    client_data: &ClientData,
+    ceremony_type: &str,
+    purpose: ChallengePurpose,
+    webauthn_config: &WebAuthn,
+) -> Result<WebAuthnChallenge, WebAuthnServiceError> {
+    if client_data.ceremony_type != ceremony_type {
+        return Err(WebAuthnServiceError::VerificationFailed(
+            "Wrong ceremony type",
+        ));
+    }
+    if client_data.origin != webauthn_config.origin {
+        return Err(WebAuthnServiceError::VerificationFailed("Wrong origin"));
+    }
+    match webauthn_repository.consume_challenge(&client_data.challenge, purpose as i32)? {
+        Some(challenge) if challenge.expires_at > chrono::Utc::now() => Ok(challenge),
+        _ => Err(WebAuthnServiceError::VerificationFailed(
+            "Unknown or expired challenge",
+        )),
+    }
+}
+
+fn verify_authenticator_data(
+    authenticator_data: &webauthn::AuthenticatorData,
+    user_verification_required: bool,
+    webauthn_config: &WebAuthn,
+) -> Result<(), WebAuthnServiceError> {
+    if authenticator_data.rp_id_hash != webauthn::rp_id_hash(&webauthn_config.rp_id) {
+        return Err(WebAuthnServiceError::VerificationFailed(
+            "Credential of another relying party",
+        ));
+    }
+    if !authenticator_data.has_flag(webauthn::FLAG_USER_PRESENT) {
+        return Err(WebAuthnServiceError::VerificationFailed("User not present"));
+    }
+    if user_verification_required && !authenticator_data.has_flag(webauthn::FLAG_USER_VERIFIED) {
+        return Err(WebAuthnServiceError::VerificationFailed(
+            "User not verified",
+        ));
+    }
+    Ok(())
+}
+
+/// A counter that doesn't increase hints at a cloned authenticator. Authenticators that don't
+/// count (e.g. synced passkeys) always report 0.
+fn update_sign_count(
+    webauthn_repository: &impl WebAuthnRepository,
+    credential: &WebAuthnCredential,
+    sign_count: i64,
+) -> Result<(), WebAuthnServiceError> {
+    if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+        return Err(WebAuthnServiceError::VerificationFailed(
+            "Signature counter did not increase",
+        ));
+    }
+    if webauthn_repository.update_credential_sign_count(
+        &credential.id,
+        credential.sign_count,
+        sign_count,
+    )? == 0
+    {
+        return Err(WebAuthnServiceError::VerificationFailed(
+            "Concurrent use of credential",
+        ));
+    }
+    Ok(())
+}
+
+fn credential_descriptor(credential: &WebAuthnCredential) -> CredentialDescriptorDto {
+    CredentialDescriptorDto {
+        credential_type: String::from("public-key"),
+        id: URL_SAFE_NO_PAD.encode(&credential.id),
+    }
+}
+
+/// Opaque user id handed to authenticators, returned as userHandle by passkeys
+fn user_handle(user_id: i64) -> Vec<u8> {
+    user_id.to_be_bytes().to_vec()
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, WebAuthnServiceError> {
+    URL_SAFE_LENIENT
+        .decode(value)
+        .map_err(|_| WebAuthnServiceError::VerificationFailed("Invalid base64url encoding"))
+}
+
+fn required(value: &Option<String>, field: &'static str) -> Result<String, WebAuthnServiceError> {
+    value
+        .clone()
+        .ok_or(WebAuthnServiceError::MissingField(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::{SessionLimits, WebAuthn};
+    use crate::model::login_events::ClientInfo;
+    use crate::model::recovery_codes::RecoveryCodesDto;
+    use crate::model::users::UserStatus;
+    use crate::model::webauthn::{
+        AssertionResponseDto, AttestationResponseDto, PublicKeyCredentialDto,
+        RegisterCredentialDto, WebAuthnLoginDto,
+    };
+    use crate::test_support::{argon2_config, jwt_config, user, MockRepo};
+    use crate::webauthn::tests::TestAuthenticator;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    const ORIGIN: &str = "https://id.example.com";
+
+    fn seeded_repo() -> MockRepo {
+        MockRepo::with_users(vec![user(2, UserStatus::Active)])
+    }
+
+    fn webauthn_config() -> WebAuthn {
+        WebAuthn {
+            rp_id: String::from("id.example.com"),
+            rp_name: String::from("Example"),
+            origin: String::from(ORIGIN),
+            challenge_exp_ms: 300000,
+        }
+    }
+
+    fn register(repo: &MockRepo, authenticator: &TestAuthenticator) -> Option<RecoveryCodesDto> {
+        let config = webauthn_config();
+        let options = super::registration_options(repo, 2, &config).unwrap();
+        let client_data = authenticator.client_data("webauthn.create", &options.challenge, ORIGIN);
+        let register_dto = RegisterCredentialDto {
+            name: String::from("Laptop"),
+            credential: PublicKeyCredentialDto {
+                id: URL_SAFE_NO_PAD.encode(&authenticator.credential_id),
+                response: AttestationResponseDto {
+                    client_data_json: URL_SAFE_NO_PAD.encode(client_data),
+                    attestation_object: URL_SAFE_NO_PAD
+                        .encode(authenticator.attestation_object(&config.rp_id, 0x05)),
+                },
+            },
+        };
+        super::register_credential(repo, 2, &register_dto, &config, &argon2_config()).unwrap()
+    }
+
+    fn passwordless_login_dto(
+        authenticator: &TestAuthenticator,
+        challenge: &str,
+        flags: u8,
+        sign_count: u32,
+    ) -> WebAuthnLoginDto {
+        let auth_data = authenticator.authenticator_data("id.example.com", flags, sign_count);
+        let client_data = authenticator.client_data("webauthn.get", challenge, ORIGIN);
+        WebAuthnLoginDto {
+            credential: PublicKeyCredentialDto {
+                id: URL_SAFE_NO_PAD.encode(&authenticator.credential_id),
+                response: AssertionResponseDto {
+                    signature: URL_SAFE_NO_PAD.encode(authenticator.sign(&auth_data, &client_data)),
+                    client_data_json: URL_SAFE_NO_PAD.encode(client_data),
+                    authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+                    user_handle: Some(URL_SAFE_NO_PAD.encode(2i64.to_be_bytes())),
+                },
+            },
+            mfa_token: None,
+            platform: Some(String::from("web")),
+            sub_platform: Some(String::from("firefox")),
+        }
+    }
+
+    #[test]
+    fn register_credential() {
+        let repo = seeded_repo();
+        let authenticator = TestAuthenticator::new();
+        let recovery_codes = register(&repo, &authenticator);
+
+        let credentials = repo.credentials.borrow();
+        assert_eq!(1, credentials.len());
+        assert_eq!(2, credentials[0].user_id);
+        assert_eq!(authenticator.cose_key(), credentials[0].public_key);
+        assert!(repo.challenges.borrow().is_empty());
+        // The first passkey enrolls the user in 2FA
+        assert_eq!(10, recovery_codes.unwrap().recovery_codes.len());
+        assert_eq!(10, repo.recovery_codes.borrow().len());
+    }
+
+    #[test]
+    fn register_further_credential() {
+        let repo = seeded_repo();
+        register(&repo, &TestAuthenticator::new());
+        let code_hashes = |repo: &MockRepo| -> Vec<String> {
+            let recovery_codes = repo.recovery_codes.borrow();
+            recovery_codes
+                .iter()
+                .map(|code| code.code_hash.clone())
+                .collect()
+        };
+        let recovery_codes = code_hashes(&repo);
+
+        let mut authenticator = TestAuthenticator::new();
+        authenticator.credential_id = vec![8; 16];
+        assert!(register(&repo, &authenticator).is_none());
+        assert_eq!(2, repo.credentials.borrow().len());
+        assert_eq!(recovery_codes, code_hashes(&repo));
+    }
+
+    #[test]
+    fn register_credential_from_other_origin() {
+        let repo = seeded_repo();
+        let authenticator = TestAuthenticator::new();
+        let config = webauthn_config();
+        let options = super::registration_options(&repo, 2, &config).unwrap();
+        let client_data = authenticator.client_data(
+            "webauthn.create",
+            &options.challenge,
+            "https://evil.example",
+        );
+        let register_dto = RegisterCredentialDto {
+            name: String::from("Laptop"),
+            credential: PublicKeyCredentialDto {
+                id: URL_SAFE_NO_PAD.encode(&authenticator.credential_id),
+                response: AttestationResponseDto {
+                    client_data_json: URL_SAFE_NO_PAD.encode(client_data),
+                    attestation_object: URL_SAFE_NO_PAD
+                        .encode(authenticator.attestation_object(&config.rp_id, 0x05)),
+                },
+            },
+        };
+        let result = super::register_credential(&repo, 2, &register_dto, &config, &argon2_config());
+        assert!(matches!(
+            result,
+            Err(super::WebAuthnServiceError::VerificationFailed(_))
+        ));
+        assert!(repo.credentials.borrow().is_empty());
+    }
+
+    #[test]
+    fn passwordless_login() {
+        let repo = seeded_repo();
+        let authenticator = TestAuthenticator::new();
+        register(&repo, &authenticator);
+        let config = webauthn_config();
+        let options_request = crate::model::webauthn::LoginOptionsRequestDto { mfa_token: None };
+        let options =
+            super::login_options(&repo, &options_request, &jwt_config(), &config).unwrap();
+        assert_eq!("required", options.user_verification);
+
+        let login_dto = passwordless_login_dto(&authenticator, &options.challenge, 0x05, 1);
+        assert!(super::login(
+            &repo,
+            &login_dto,
+            &ClientInfo::default(),
+            &jwt_config(),
+            &config,
+            &SessionLimits::default(),
+        )
+        .is_ok());
+        assert_eq!(1, repo.credentials.borrow()[0].sign_count);
+
+        // The challenge is spent
+        let result = super::login(
+            &repo,
+            &login_dto,
+            &ClientInfo::default(),
+            &jwt_config(),
+            &config,
+            &SessionLimits::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(super::WebAuthnServiceError::VerificationFailed(_))
+        ));
+        assert_eq!(
+            vec![None, Some(String::from("VerificationFailed"))],
+            repo.failure_reasons()
+        );
+    }
+
+    #[test]
+    fn passwordless_login_of_locked_user() {
+        let repo = seeded_repo();
+        let authenticator = TestAuthenticator::new();
+        register(&repo, &authenticator);
+        repo.users.borrow_mut()[0].failed_login_attempts = 3;
+        repo.users.borrow_mut()[0].locked_until =
+            Some(chrono::Utc::now() + chrono::Duration::minutes(1));
+        let config = webauthn_config();
+        let options_request = crate::model::webauthn::LoginOptionsRequestDto { mfa_token: None };
+        let options =
+            super::login_options(&repo, &options_request, &jwt_config(), &config).unwrap();
+
+        let login_dto = passwordless_login_dto(&authenticator, &options.challenge, 0x05, 1);
+        let result = super::login(
+            &repo,
+            &login_dto,
+            &ClientInfo::default(),
+            &jwt_config(),
+            &config,
+            &SessionLimits::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(super::WebAuthnServiceError::AuthorizationError(
+                crate::auth::AuthorizationError::AccountLocked(_)
+            ))
+        ));
+        assert_eq!(3, repo.user(2).failed_login_attempts);
+        assert_eq!(0, repo.credentials.borrow()[0].sign_count);
+        assert!(repo.sessions.borrow().is_empty());
+        assert_eq!(
+            vec![Some(String::from("AccountLocked"))],
+            repo.failure_reasons()
+        );
+    }
+
+    #[test]
+    fn passwordless_login_without_user_verification() {
+        let repo = seeded_repo();
+        let authenticator = TestAuthenticator::new();
+        register(&repo, &authenticator);
+        let config = webauthn_config();
+        let options_request = crate::model::webauthn::LoginOptionsRequestDto { mfa_token: None };
+        let options =
+            super::login_options(&repo, &options_request, &jwt_config(), &config).unwrap();
+
+        let login_dto = passwordless_login_dto(&authenticator, &options.challenge, 0x01, 1);
+        let result = super::login(
+            &repo,
+            &login_dto,
+            &ClientInfo::default(),
+            &jwt_config(),
+            &config,
+            &SessionLimits::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(super::WebAuthnServiceError::VerificationFailed(
+                "User not verified"
+            ))
+        ));
+    }
+}
@@ -0,0 +1,30 @@
+use crate::configuration::{Jwt, SessionReaper};
+use crate::db;
+use crate::db::PgPool;
+use crate::service;
+use actix_web::{rt, web};
+use std::time::Duration;
+
+/// Deletes stale sessions every `interval_ms`, see session_service::reap_sessions
+pub fn spawn(pool: PgPool, reaper_config: SessionReaper, token_config: Jwt) {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_millis(reaper_config.interval_ms));
+        loop {
+            interval.tick().await;
+            let pool = pool.clone();
+            let reaper_config = reaper_config.clone();
+            let token_config = token_config.clone();
+            let result = web::block(move || {
+                let conn = db::get_conn(&pool)?;
+                service::session_service::reap_sessions(&conn, &reaper_config, &token_config)
+            })
+            .await;
+
+            match result {
+                Ok(0) => debug!("Session reaper found no stale sessions"),
+                Ok(deleted) => info!("Session reaper deleted {} stale sessions", deleted),
+                Err(e) => error!("Could not reap sessions: {:?}", e),
+            }
+        }
+    });
+}
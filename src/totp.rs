@@ -0,0 +1,227 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::aead;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::error;
+use std::fmt;
+
+const DIGITS: usize = 6;
+const PERIOD_S: i64 = 30;
+const SECRET_LEN: usize = 20; // 160 bits, as recommended by RFC 4226
+const ALLOWED_SKEW_STEPS: i64 = 1; // Accept codes of the previous and next period for clock drift
+
+#[derive(Debug)]
+pub enum TotpError {
+    InvalidEncryptionKey,
+    RandomnessUnavailable,
+    EncryptionFailed,
+    DecryptionFailed,
+}
+
+impl fmt::Display for TotpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl error::Error for TotpError {}
+
+pub fn generate_secret() -> Result<Vec<u8>, TotpError> {
+    let mut secret = vec![0; SECRET_LEN];
+    SystemRandom::new()
+        .fill(&mut secret)
+        .map_err(|_| TotpError::RandomnessUnavailable)?;
+    Ok(secret)
+}
+
+/// Key URI understood by authenticator apps (https://github.com/google/google-authenticator/wiki/Key-Uri-Format)
+pub fn otpauth_uri(issuer: &str, account_name: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account_name),
+        base32(secret),
+        percent_encode(issuer),
+        DIGITS,
+        PERIOD_S
+    )
+}
+
+/// Returns the time step the code belongs to, so callers can refuse to accept it twice
+pub fn verify(secret: &[u8], code: &str, now_s: i64) -> Option<i64> {
+    if code.len() != DIGITS || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let current_step = now_s / PERIOD_S;
+    (current_step - ALLOWED_SKEW_STEPS..=current_step + ALLOWED_SKEW_STEPS).find(|&step| {
+        let expected = format!("{:0width$}", code_at(secret, step), width = DIGITS);
+        ring::constant_time::verify_slices_are_equal(expected.as_bytes(), code.as_bytes()).is_ok()
+    })
+}
+
+#[cfg(test)]
+pub fn current_code(secret: &[u8]) -> String {
+    let step = chrono::Utc::now().timestamp() / PERIOD_S;
+    format!("{:0width$}", code_at(secret, step), width = DIGITS)
+}
+
+/// HOTP (RFC 4226 section 5.3) with the time step as counter (RFC 6238)
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let hash = hmac::sign(&key, &step.to_be_bytes());
+    let hash = hash.as_ref();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS as u32)
+}
+
+/// Encrypts the secret with AES-256-GCM. The user id is authenticated along with it, so a secret
+/// copied to another user's row doesn't decrypt. Output is nonce || ciphertext || tag.
+pub fn encrypt(encryption_key: &str, user_id: i64, secret: &[u8]) -> Result<Vec<u8>, TotpError> {
+    let key = aead_key(encryption_key)?;
+    let mut nonce = [0; aead::NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| TotpError::RandomnessUnavailable)?;
+
+    let mut in_out = secret.to_vec();
+    key.seal_in_place_append_tag(
+        aead::Nonce::assume_unique_for_key(nonce),
+        aead::Aad::from(user_id.to_be_bytes()),
+        &mut in_out,
+    )
+    .map_err(|_| TotpError::EncryptionFailed)?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.append(&mut in_out);
+    Ok(sealed)
+}
+
+pub fn decrypt(encryption_key: &str, user_id: i64, sealed: &[u8]) -> Result<Vec<u8>, TotpError> {
+    let key = aead_key(encryption_key)?;
+    if sealed.len() < aead::NONCE_LEN {
+        return Err(TotpError::DecryptionFailed);
+    }
+    let (nonce, ciphertext) = sealed.split_at(aead::NONCE_LEN);
+    let nonce =
+        aead::Nonce::try_assume_unique_for_key(nonce).map_err(|_| TotpError::DecryptionFailed)?;
+
+    let mut in_out = ciphertext.to_vec();
+    let secret = key
+        .open_in_place(nonce, aead::Aad::from(user_id.to_be_bytes()), &mut in_out)
+        .map_err(|_| TotpError::DecryptionFailed)?;
+    Ok(secret.to_vec())
+}
+
+/// The key is configured base64 encoded and has to be 32 bytes long
+pub fn verify_encryption_key(encryption_key: &str) -> Result<(), TotpError> {
+    aead_key(encryption_key).map(|_| ())
+}
+
+fn aead_key(encryption_key: &str) -> Result<aead::LessSafeKey, TotpError> {
+    let bytes = STANDARD
+        .decode(encryption_key)
+        .map_err(|_| TotpError::InvalidEncryptionKey)?;
+    let key = aead::UnboundKey::new(&aead::AES_256_GCM, &bytes)
+        .map_err(|_| TotpError::InvalidEncryptionKey)?;
+    Ok(aead::LessSafeKey::new(key))
+}
+
+/// RFC 4648 base32 without padding, the encoding authenticator apps expect
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    // Secret of the RFC 6238 Appendix B test vectors (SHA1)
+    const SECRET: &[u8] = b"12345678901234567890";
+    const ENCRYPTION_KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn code_at() {
+        // RFC 6238 uses 8 digits, the last 6 of them are the 6 digit code
+        assert_eq!(287082, super::code_at(SECRET, 59 / 30));
+        assert_eq!(81804, super::code_at(SECRET, 1111111109 / 30));
+        assert_eq!(5924, super::code_at(SECRET, 1234567890 / 30));
+    }
+
+    #[test]
+    fn verify() {
+        assert_eq!(Some(37037036), super::verify(SECRET, "081804", 1111111109));
+        // Previous period is still accepted, older ones are not
+        assert_eq!(
+            Some(37037036),
+            super::verify(SECRET, "081804", 1111111109 + 30)
+        );
+        assert_eq!(None, super::verify(SECRET, "081804", 1111111109 + 60));
+        assert_eq!(None, super::verify(SECRET, "81804", 1111111109));
+        assert_eq!(None, super::verify(SECRET, "08180a", 1111111109));
+    }
+
+    #[test]
+    fn base32() {
+        assert_eq!("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", super::base32(SECRET));
+        assert_eq!("MZXW6YQ", super::base32(b"foob"));
+    }
+
+    #[test]
+    fn otpauth_uri() {
+        let uri = super::otpauth_uri("User Service", "alice@example.com", SECRET);
+        assert_eq!(
+            "otpauth://totp/User%20Service:alice%40example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=User%20Service&algorithm=SHA1&digits=6&period=30",
+            uri
+        );
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let sealed = super::encrypt(ENCRYPTION_KEY, 2, SECRET).unwrap();
+        assert!(!sealed.windows(SECRET.len()).any(|w| w == SECRET));
+        assert_eq!(
+            SECRET,
+            &super::decrypt(ENCRYPTION_KEY, 2, &sealed).unwrap()[..]
+        );
+        // Bound to the user it was encrypted for
+        assert!(super::decrypt(ENCRYPTION_KEY, 3, &sealed).is_err());
+    }
+
+    #[test]
+    fn invalid_encryption_key() {
+        assert!(super::verify_encryption_key(ENCRYPTION_KEY).is_ok());
+        assert!(super::verify_encryption_key("c2hvcnQ=").is_err());
+    }
+}
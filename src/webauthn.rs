@@ -0,0 +1,336 @@
+use serde::Deserialize;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::error;
+use std::fmt;
+
+// Authenticator data flags (WebAuthn Level 2 section 6.1)
+pub const FLAG_USER_PRESENT: u8 = 0x01;
+pub const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// COSE algorithm identifiers (https://www.iana.org/assignments/cose/cose.xhtml#algorithms)
+pub const COSE_ALG_ES256: i64 = -7;
+pub const COSE_ALG_EDDSA: i64 = -8;
+pub const COSE_ALG_RS256: i64 = -257;
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [COSE_ALG_ES256, COSE_ALG_EDDSA, COSE_ALG_RS256];
+
+#[derive(Debug)]
+pub enum WebAuthnError {
+    InvalidClientData,
+    InvalidAttestationObject,
+    InvalidAuthenticatorData,
+    InvalidPublicKey,
+    UnsupportedAlgorithm(i64),
+    InvalidSignature,
+}
+
+impl fmt::Display for WebAuthnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl error::Error for WebAuthnError {}
+
+/// The parts of CollectedClientData (WebAuthn Level 2 section 5.8.1) that are checked
+#[derive(Debug, Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub ceremony_type: String,
+    pub challenge: String,
+    pub origin: String,
+}
+
+#[derive(Debug)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: Vec<u8>,
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential: Option<AttestedCredential>,
+}
+
+#[derive(Debug)]
+pub struct AttestedCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>, // COSE_Key, stored as sent
+}
+
+impl AuthenticatorData {
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+}
+
+pub fn parse_client_data(client_data_json: &[u8]) -> Result<ClientData, WebAuthnError> {
+    serde_json::from_slice(client_data_json).map_err(|_| WebAuthnError::InvalidClientData)
+}
+
+/// Only "none" attestation is requested, so the attestation statement is not verified and only
+/// the authenticator data is taken from the object
+pub fn parse_attestation_object(
+    attestation_object: &[u8],
+) -> Result<AuthenticatorData, WebAuthnError> {
+    let object: BTreeMap<String, Value> = serde_cbor::from_slice(attestation_object)
+        .map_err(|_| WebAuthnError::InvalidAttestationObject)?;
+    match object.get("authData") {
+        Some(Value::Bytes(auth_data)) => parse_authenticator_data(auth_data),
+        _ => Err(WebAuthnError::InvalidAttestationObject),
+    }
+}
+
+/// Layout: rpIdHash (32) | flags (1) | signCount (4) | [aaguid (16) | idLength (2) | id | COSE_Key]
+pub fn parse_authenticator_data(bytes: &[u8]) -> Result<AuthenticatorData, WebAuthnError> {
+    if bytes.len() < 37 {
+        return Err(WebAuthnError::InvalidAuthenticatorData);
+    }
+    let flags = bytes[32];
+    let sign_count = u32::from_be_bytes(bytes[33..37].try_into().unwrap());
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        let data = &bytes[37..];
+        if data.len() < 18 {
+            return Err(WebAuthnError::InvalidAuthenticatorData);
+        }
+        let id_length = u16::from_be_bytes([data[16], data[17]]) as usize;
+        let key_start = 18 + id_length;
+        if data.len() <= key_start {
+            return Err(WebAuthnError::InvalidAuthenticatorData);
+        }
+        // Extensions may follow the key, so only the first CBOR item is taken
+        let mut deserializer = serde_cbor::Deserializer::from_slice(&data[key_start..]);
+        serde::Deserialize::deserialize(&mut deserializer)
+            .map(|_: Value| ())
+            .map_err(|_| WebAuthnError::InvalidPublicKey)?;
+        let key_end = key_start + deserializer.byte_offset();
+        Some(AttestedCredential {
+            credential_id: data[18..key_start].to_vec(),
+            public_key: data[key_start..key_end].to_vec(),
+        })
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: bytes[..32].to_vec(),
+        flags,
+        sign_count,
+        attested_credential,
+    })
+}
+
+pub fn rp_id_hash(rp_id: &str) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA256, rp_id.as_bytes())
+        .as_ref()
+        .to_vec()
+}
+
+/// The signature covers authenticatorData | SHA-256(clientDataJSON) (WebAuthn Level 2 section 7.2)
+pub fn verify_assertion_signature(
+    public_key: &[u8],
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+) -> Result<(), WebAuthnError> {
+    let client_data_hash = ring::digest::digest(&ring::digest::SHA256, client_data_json);
+    let mut message = authenticator_data.to_vec();
+    message.extend_from_slice(client_data_hash.as_ref());
+
+    let key = parse_cose_key(public_key)?;
+    let result = match algorithm(&key)? {
+        COSE_ALG_ES256 => {
+            let mut point = vec![0x04]; // Uncompressed
+            point.extend_from_slice(bytes_param(&key, -2)?);
+            point.extend_from_slice(bytes_param(&key, -3)?);
+            ring::signature::UnparsedPublicKey::new(&ring::signature::ECDSA_P256_SHA256_ASN1, point)
+                .verify(&message, signature)
+        }
+        COSE_ALG_EDDSA => ring::signature::UnparsedPublicKey::new(
+            &ring::signature::ED25519,
+            bytes_param(&key, -2)?,
+        )
+        .verify(&message, signature),
+        COSE_ALG_RS256 => ring::signature::RsaPublicKeyComponents {
+            n: bytes_param(&key, -1)?,
+            e: bytes_param(&key, -2)?,
+        }
+        .verify(
+            &ring::signature::RSA_PKCS1_2048_8192_SHA256,
+            &message,
+            signature,
+        ),
+        alg => return Err(WebAuthnError::UnsupportedAlgorithm(alg)),
+    };
+    result.map_err(|_| WebAuthnError::InvalidSignature)
+}
+
+/// Algorithm of a COSE_Key, fails for keys that can't be verified later on
+pub fn public_key_algorithm(public_key: &[u8]) -> Result<i64, WebAuthnError> {
+    let key = parse_cose_key(public_key)?;
+    match algorithm(&key)? {
+        alg if SUPPORTED_ALGORITHMS.contains(&alg) => Ok(alg),
+        alg => Err(WebAuthnError::UnsupportedAlgorithm(alg)),
+    }
+}
+
+fn parse_cose_key(public_key: &[u8]) -> Result<BTreeMap<Value, Value>, WebAuthnError> {
+    serde_cbor::from_slice(public_key).map_err(|_| WebAuthnError::InvalidPublicKey)
+}
+
+fn algorithm(key: &BTreeMap<Value, Value>) -> Result<i64, WebAuthnError> {
+    match key.get(&Value::Integer(3)) {
+        Some(Value::Integer(alg)) => Ok(*alg as i64),
+        _ => Err(WebAuthnError::InvalidPublicKey),
+    }
+}
+
+fn bytes_param(key: &BTreeMap<Value, Value>, label: i128) -> Result<&[u8], WebAuthnError> {
+    match key.get(&Value::Integer(label)) {
+        Some(Value::Bytes(bytes)) => Ok(bytes),
+        _ => Err(WebAuthnError::InvalidPublicKey),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use serde_cbor::Value;
+    use std::collections::BTreeMap;
+
+    /// Software authenticator with a P-256 key
+    pub struct TestAuthenticator {
+        key_pair: EcdsaKeyPair,
+        pub credential_id: Vec<u8>,
+    }
+
+    impl TestAuthenticator {
+        pub fn new() -> Self {
+            let rng = ring::rand::SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            TestAuthenticator {
+                key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+                    .unwrap(),
+                credential_id: vec![7; 16],
+            }
+        }
+
+        pub fn cose_key(&self) -> Vec<u8> {
+            let point = self.key_pair.public_key().as_ref();
+            let mut key = BTreeMap::new();
+            key.insert(Value::Integer(1), Value::Integer(2)); // kty: EC2
+            key.insert(Value::Integer(3), Value::Integer(-7)); // alg: ES256
+            key.insert(Value::Integer(-1), Value::Integer(1)); // crv: P-256
+            key.insert(Value::Integer(-2), Value::Bytes(point[1..33].to_vec()));
+            key.insert(Value::Integer(-3), Value::Bytes(point[33..].to_vec()));
+            serde_cbor::to_vec(&Value::Map(key)).unwrap()
+        }
+
+        pub fn authenticator_data(&self, rp_id: &str, flags: u8, sign_count: u32) -> Vec<u8> {
+            let mut data = super::rp_id_hash(rp_id);
+            data.push(flags);
+            data.extend_from_slice(&sign_count.to_be_bytes());
+            data
+        }
+
+        pub fn attestation_object(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+            let mut auth_data =
+                self.authenticator_data(rp_id, flags | super::FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+            auth_data.extend_from_slice(&[0; 16]); // aaguid
+            auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.credential_id);
+            auth_data.extend_from_slice(&self.cose_key());
+
+            let mut object = BTreeMap::new();
+            object.insert(
+                Value::Text(String::from("fmt")),
+                Value::Text(String::from("none")),
+            );
+            object.insert(
+                Value::Text(String::from("attStmt")),
+                Value::Map(BTreeMap::new()),
+            );
+            object.insert(
+                Value::Text(String::from("authData")),
+                Value::Bytes(auth_data),
+            );
+            serde_cbor::to_vec(&Value::Map(object)).unwrap()
+        }
+
+        pub fn client_data(&self, ceremony_type: &str, challenge: &str, origin: &str) -> Vec<u8> {
+            serde_json::to_vec(&serde_json::json!({
+                "type": ceremony_type,
+                "challenge": challenge,
+                "origin": origin,
+                "crossOrigin": false
+            }))
+            .unwrap()
+        }
+
+        pub fn sign(&self, authenticator_data: &[u8], client_data_json: &[u8]) -> Vec<u8> {
+            let hash = ring::digest::digest(&ring::digest::SHA256, client_data_json);
+            let mut message = authenticator_data.to_vec();
+            message.extend_from_slice(hash.as_ref());
+            self.key_pair
+                .sign(&ring::rand::SystemRandom::new(), &message)
+                .unwrap()
+                .as_ref()
+                .to_vec()
+        }
+    }
+
+    #[test]
+    fn parse_attestation_object() {
+        let authenticator = TestAuthenticator::new();
+        let attestation_object = authenticator.attestation_object("localhost", 0x05);
+        let auth_data = super::parse_attestation_object(&attestation_object).unwrap();
+
+        assert_eq!(super::rp_id_hash("localhost"), auth_data.rp_id_hash);
+        assert!(auth_data.has_flag(super::FLAG_USER_PRESENT));
+        assert!(auth_data.has_flag(super::FLAG_USER_VERIFIED));
+        let credential = auth_data.attested_credential.unwrap();
+        assert_eq!(authenticator.credential_id, credential.credential_id);
+        assert_eq!(authenticator.cose_key(), credential.public_key);
+        assert_eq!(
+            super::COSE_ALG_ES256,
+            super::public_key_algorithm(&credential.public_key).unwrap()
+        );
+    }
+
+    #[test]
+    fn verify_assertion_signature() {
+        let authenticator = TestAuthenticator::new();
+        let auth_data = authenticator.authenticator_data("localhost", 0x01, 1);
+        let client_data = authenticator.client_data("webauthn.get", "abc", "https://localhost");
+        let signature = authenticator.sign(&auth_data, &client_data);
+        let public_key = authenticator.cose_key();
+
+        assert!(super::verify_assertion_signature(
+            &public_key,
+            &auth_data,
+            &client_data,
+            &signature
+        )
+        .is_ok());
+        let other_client_data =
+            authenticator.client_data("webauthn.get", "abd", "https://localhost");
+        assert!(super::verify_assertion_signature(
+            &public_key,
+            &auth_data,
+            &other_client_data,
+            &signature
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn truncated_authenticator_data() {
+        assert!(super::parse_authenticator_data(&[0; 36]).is_err());
+        let mut auth_data = vec![0; 37];
+        auth_data[32] = super::FLAG_ATTESTED_CREDENTIAL_DATA;
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&[0, 200]);
+        assert!(super::parse_authenticator_data(&auth_data).is_err());
+    }
+}